@@ -0,0 +1,49 @@
+//! Finding the subscriptions a topic name matches, with the tree and by checking every filter.
+//! The tree's lookup time should barely move as the number of subscriptions grows.
+
+#[macro_use]
+extern crate criterion;
+extern crate mqtt;
+
+use criterion::{BenchmarkId, Criterion};
+use mqtt::topic::{SubscriptionTree, ValidTopicFilter};
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+/// Subscriptions of a fleet of devices, a few per device.
+fn filters(count: usize) -> Vec<ValidTopicFilter> {
+    (0..count).map(|i| {
+        let device = i / 4;
+        let filter = match i % 4 {
+            0 => format!("fleet/{}/sensor/+/temperature", device),
+            1 => format!("fleet/{}/status", device),
+            2 => format!("fleet/{}/#", device),
+            _ => format!("fleet/+/sensor/{}/humidity", device),
+        };
+        ValidTopicFilter::new(filter).unwrap()
+    }).collect()
+}
+
+fn matching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("matching");
+    let topic_name = "fleet/17/sensor/3/temperature";
+
+    for &size in &SIZES {
+        let filters = filters(size);
+        let mut tree = SubscriptionTree::new();
+        for (value, filter) in filters.iter().enumerate() {
+            tree.insert(filter, value);
+        }
+
+        group.bench_with_input(BenchmarkId::new("tree", size), &tree, |b, tree| {
+            b.iter(|| tree.matches(criterion::black_box(topic_name)).count())
+        });
+        group.bench_with_input(BenchmarkId::new("linear", size), &filters, |b, filters| {
+            b.iter(|| filters.iter().filter(|filter| filter.matches(criterion::black_box(topic_name))).count())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, matching);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+use mqtt::packet::{DecodingContext, ProtocolVersion, Strictness};
+
+/// Picks the protocol version and strictness from a byte of the input,
+/// so every configuration of the decoder gets fuzzed.
+pub fn context(byte: u8) -> DecodingContext {
+    let protocol_version = match byte % 3 {
+        0 => ProtocolVersion::V31,
+        1 => ProtocolVersion::V311,
+        _ => ProtocolVersion::V5,
+    };
+    let strictness = if byte & 0b1000_0000 > 0 { Strictness::Lenient } else { Strictness::Strict };
+    DecodingContext {
+        strictness,
+        topic_alias_maximum: u16::from(byte & 0b0100_0000 > 0) * 10,
+        ..DecodingContext::new(protocol_version)
+    }
+}
@@ -0,0 +1,49 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use mqtt::packet::*;
+
+mod common;
+
+/// Decodes the body behind its fixed header, for the packet types without a body decoder of their own.
+fn decode_frame(body: &[u8], state: &mut DecodingContext) -> Result<Packet, DecodingError> {
+    let mut frame = Vec::new();
+    state.header.encode(&mut frame).unwrap();
+    frame.extend_from_slice(body);
+    Packet::decode(&mut Cursor::new(frame), state)
+}
+
+// Skips the fixed header, so the body decoders see input with a consistent remaining length.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let mut state = common::context(data[0]);
+    let body = &data[2..];
+    state.header = Header { packet_type: data[1] >> 4, flags: data[1] & 0b0000_1111, remaining_length: body.len() as u32 };
+
+    let reader = &mut Cursor::new(body);
+    let state = &mut state;
+    let _ = match state.header.packet_type {
+        1 => ConnectData::decode(reader, state).map(drop),
+        2 => ConnackData::decode(reader, state).map(drop),
+        3 => PublishData::decode(reader, state).map(drop),
+        4..=7 => AckData::decode(reader, state).map(drop),
+        8 => SubscribeData::decode(reader, state).map(drop),
+        9 => SubackData::decode(reader, state).map(drop),
+        10 => UnsubscribeData::decode(reader, state).map(drop),
+        11 => UnsubackData::decode(reader, state).map(drop),
+        // PINGREQ and PINGRESP have an empty body
+        12 | 13 => decode_frame(body, state).map(drop),
+        14 => DisconnectData::decode(reader, state).map(drop),
+        15 => AuthData::decode(reader, state).map(drop),
+        // the reserved packet type never decodes
+        _ => {
+            let err = decode_frame(body, state).unwrap_err();
+            assert!(matches!(err.root_cause(), DecodingError::Forbidden), "{:?}", err);
+            Ok(())
+        },
+    };
+});
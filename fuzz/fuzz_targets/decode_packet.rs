@@ -0,0 +1,20 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use mqtt::packet::{Decode, Encode, Packet};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((&first, rest)) = data.split_first() {
+        let mut state = common::context(first);
+        if let Ok(packet) = Packet::decode(&mut Cursor::new(rest), &mut state) {
+            // anything that decodes has to encode again
+            let mut bytes = Vec::new();
+            packet.encode(&mut bytes).unwrap();
+            assert_eq!(bytes.len() as u32, packet.encoded_length());
+        }
+    }
+});
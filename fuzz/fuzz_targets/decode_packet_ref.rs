@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt::packet::PacketRef;
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((&first, rest)) = data.split_first() {
+        let mut state = common::context(first);
+        let _ = PacketRef::decode(rest, &mut state).map(PacketRef::into_owned);
+    }
+});
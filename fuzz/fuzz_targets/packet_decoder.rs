@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt::codec::PacketDecoder;
+
+mod common;
+
+// Feeds the input in chunks whose size is taken from the second byte.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let mut decoder = PacketDecoder::with_context(common::context(data[0]));
+    let chunk_size = usize::from(data[1]).max(1);
+    for chunk in data[2..].chunks(chunk_size) {
+        decoder.feed(chunk);
+        // errors discard what they fail on, so this runs out of data
+        while !matches!(decoder.next_packet(), Ok(None)) {}
+    }
+});
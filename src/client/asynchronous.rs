@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::codec::Framed;
+
+use crate::codec::MqttCodec;
+use crate::packet::*;
+use crate::qos::{Delivery, PacketIdAllocator};
+use crate::topic::validate_topic_name;
+use super::{Backoff, ClientError, Handler, KeepAlive, Outbox, OverflowPolicy, SessionStore};
+use super::packets::{check_reason_code, check_topic_filters, with_properties};
+use super::reconnect::Subscriptions;
+use super::router::{split_handlers, Handlers, Router};
+use super::session::Session;
+
+/// A handle for making requests over the connection owned by an `EventLoop`,
+/// cheap to clone and to move between tasks.
+///
+/// Requests fail with `ConnectionClosed` once the event loop has stopped.
+#[derive(Debug, Clone)]
+pub struct AsyncClient {
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+/// Owns the connection, sending the requests of its `AsyncClient`s and
+/// completing them as the server acknowledges them. It also sends the
+/// PINGREQs that keep the connection open while it is idle.
+///
+/// Nothing is sent or received until `run` is polled, typically in a task of its own.
+#[derive(Debug)]
+pub struct EventLoop {
+    framed: Framed<TcpStream, MqttCodec>,
+    addresses: Vec<SocketAddr>,
+    /// the CONNECT to send when reconnecting
+    connect: ConnectData,
+    backoff: Option<Backoff>,
+    session_present: bool,
+    keep_alive: KeepAlive,
+    requests: mpsc::UnboundedReceiver<Request>,
+    messages: mpsc::UnboundedSender<PublishData>,
+    qos: Session,
+    subscriptions: Subscriptions,
+    router: Router,
+    /// messages published while reconnecting
+    outbox: Outbox<Reply<()>>,
+    /// the message that did not fit in a full outbox that blocks
+    blocked: Option<(PublishData, Reply<()>)>,
+    /// requests waiting for their acknowledgement
+    pending: HashMap<PacketIdentifier, Pending>,
+    packet_identifiers: PacketIdAllocator,
+}
+
+/// The messages published to our subscriptions, in the order they arrived,
+/// except those handed to the handlers of `AsyncClient::subscribe_with_handlers`.
+#[derive(Debug)]
+pub struct Messages {
+    messages: mpsc::UnboundedReceiver<PublishData>,
+}
+
+type Reply<T> = oneshot::Sender<Result<T, ClientError>>;
+
+#[derive(Debug)]
+enum Request {
+    Publish { topic_name: String, payload: Vec<u8>, qos: Qos, retain: bool, reply: Reply<()> },
+    Subscribe { topic_filters: Vec<TopicFilter>, handlers: Handlers, reply: Reply<Vec<ReturnCode>> },
+    Unsubscribe { topic_filters: Vec<String>, reply: Reply<()> },
+    Disconnect { reply: Reply<()> },
+}
+
+#[derive(Debug)]
+enum Pending {
+    /// a QoS 2 message stays pending through PUBREC until PUBCOMP
+    Publish(Reply<()>),
+    /// kept to be sent again after reconnecting
+    /// along with the identifiers of its handlers in the `Router`
+    Subscribe(SubscribeData, Vec<u64>, Reply<Vec<ReturnCode>>),
+    Unsubscribe(UnsubscribeData, Reply<()>),
+}
+
+impl AsyncClient {
+    /// Connect to `address` and wait for the CONNACK, which must accept the connection.
+    pub async fn connect<A: ToSocketAddrs>(address: A, connect: ConnectData) -> Result<(AsyncClient, EventLoop, Messages), ClientError> {
+        AsyncClient::start(address, connect, Session::default(), PacketIdAllocator::new()).await
+    }
+
+    /// Like `connect`, but picking up the session saved in `store` and saving it there from then on.
+    /// Messages it still has in flight are sent again once connected, as after reconnecting.
+    pub async fn connect_with_store<A: ToSocketAddrs, S: SessionStore + 'static>(address: A, connect: ConnectData, store: S) -> Result<(AsyncClient, EventLoop, Messages), ClientError> {
+        let mut packet_identifiers = PacketIdAllocator::new();
+        let session = Session::restore(Box::new(store), &mut packet_identifiers)?;
+        AsyncClient::start(address, connect, session, packet_identifiers).await
+    }
+
+    async fn start<A: ToSocketAddrs>(address: A, connect: ConnectData, session: Session, packet_identifiers: PacketIdAllocator) -> Result<(AsyncClient, EventLoop, Messages), ClientError> {
+        let addresses: Vec<_> = lookup_host(address).await?.collect();
+        let reconnect = connect.clone().with_clean_session(false);
+        let (framed, session_present, keep_alive) = handshake(&addresses, connect).await?;
+
+        let (requests, requests_receiver) = mpsc::unbounded_channel();
+        let (messages, messages_receiver) = mpsc::unbounded_channel();
+        let mut event_loop = EventLoop {
+            framed,
+            addresses,
+            connect: reconnect,
+            backoff: None,
+            session_present,
+            keep_alive,
+            requests: requests_receiver,
+            messages,
+            qos: session,
+            subscriptions: Subscriptions::default(),
+            router: Router::default(),
+            outbox: Outbox::unbounded(),
+            blocked: None,
+            pending: HashMap::new(),
+            packet_identifiers,
+        };
+        event_loop.resend().await?;
+        Ok((AsyncClient { requests }, event_loop, Messages { messages: messages_receiver }))
+    }
+
+    /// Publish a message, completing once the server has acknowledged it as its QoS requires:
+    /// when it is sent for QoS 0, on PUBACK for QoS 1 and on PUBCOMP for QoS 2.
+    /// While the event loop reconnects it waits in the outbox, see `EventLoop::set_outbox`.
+    /// A topic name that is not valid fails with `TopicError` before anything is sent.
+    pub async fn publish<T: Into<String>, P: Into<Vec<u8>>>(&self, topic_name: T, payload: P, qos: Qos, retain: bool) -> Result<(), ClientError> {
+        let (topic_name, payload) = (topic_name.into(), payload.into());
+        validate_topic_name(&topic_name)?;
+        self.request(|reply| Request::Publish { topic_name, payload, qos, retain, reply }).await
+    }
+
+    /// Subscribe to `topic_filters`, completing with the server's answer for each of them.
+    pub async fn subscribe(&self, topic_filters: Vec<TopicFilter>) -> Result<Vec<ReturnCode>, ClientError> {
+        check_topic_filters(topic_filters.iter().map(TopicFilter::filter))?;
+        self.request(|reply| Request::Subscribe { topic_filters, handlers: Vec::new(), reply }).await
+    }
+
+    /// Subscribe like `subscribe`, handing the messages of each accepted topic filter to its handler
+    /// rather than to `Messages`. A message matching several filters goes to each of their handlers,
+    /// and subscribing to a filter again replaces its handler. Handlers run on the event loop.
+    pub async fn subscribe_with_handlers(&self, subscriptions: Vec<(TopicFilter, Handler)>) -> Result<Vec<ReturnCode>, ClientError> {
+        if subscriptions.is_empty() { return Err(ClientError::NoTopicFilters) };
+        let (topic_filters, handlers) = split_handlers(subscriptions)?;
+        self.request(|reply| Request::Subscribe { topic_filters, handlers, reply }).await
+    }
+
+    /// Unsubscribe from `topic_filters`, dropping their handlers.
+    pub async fn unsubscribe(&self, topic_filters: Vec<String>) -> Result<(), ClientError> {
+        check_topic_filters(topic_filters.iter().map(String::as_str))?;
+        self.request(|reply| Request::Unsubscribe { topic_filters, reply }).await
+    }
+
+    /// Send a DISCONNECT and close the connection, which stops the event loop.
+    pub async fn disconnect(&self) -> Result<(), ClientError> {
+        self.request(|reply| Request::Disconnect { reply }).await
+    }
+
+    async fn request<T, F: FnOnce(Reply<T>) -> Request>(&self, request: F) -> Result<T, ClientError> {
+        let (reply, response) = oneshot::channel();
+        self.requests.send(request(reply)).map_err(|_| ClientError::ConnectionClosed)?;
+        // the reply is dropped without an answer when the event loop stops
+        response.await.unwrap_or(Err(ClientError::ConnectionClosed))
+    }
+}
+
+impl Messages {
+    /// the next message, `None` once the event loop has stopped
+    pub async fn recv(&mut self) -> Option<PublishData> {
+        self.messages.recv().await
+    }
+}
+
+impl EventLoop {
+    /// whether the server resumed a previous session on the latest connection
+    pub fn session_present(&self) -> bool {
+        self.session_present
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.framed.codec().context().protocol_version
+    }
+
+    /// Reconnect after losing the connection, waiting as long as `backoff` says before each attempt,
+    /// or `None` to stop with the error that lost it.
+    ///
+    /// Reconnecting never asks for a clean session. When the server still has the session,
+    /// unacknowledged messages are sent again flagged as redeliveries, otherwise they are
+    /// sent anyway and every subscription is renewed. Pending requests carry on either way.
+    /// Once `backoff` runs out of attempts `run` fails with the error of the last one.
+    pub fn set_reconnect(&mut self, backoff: Option<Backoff>) {
+        self.backoff = backoff;
+    }
+
+    /// Limit the messages kept for sending once reconnected, which are otherwise unlimited.
+    ///
+    /// Publishing a message dropped for lack of room fails with `OutboxFull`, while with
+    /// `OverflowPolicy::Block` it waits for the outbox to be sent, and so do all later requests.
+    /// A message larger than the whole outbox fails right away, whatever the policy.
+    pub fn set_outbox(&mut self, max_messages: usize, max_bytes: usize, policy: OverflowPolicy) {
+        self.outbox = Outbox::new(max_messages, max_bytes, policy);
+    }
+
+    /// Drive the connection until a client disconnects or every client is dropped,
+    /// which both end in a DISCONNECT, or until the connection fails.
+    ///
+    /// A server that does not answer a PINGREQ within the keepalive fails it with `KeepAliveTimeout`.
+    pub async fn run(mut self) -> Result<(), ClientError> {
+        loop {
+            match self.step().await {
+                Ok(true) => return Ok(()),
+                Ok(false) => {},
+                Err(err) if self.backoff.is_some() && err.is_connection_lost() => if self.reconnect(err).await? { return Ok(()) },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Handle whatever comes first of a packet, a request and the keepalive deadline,
+    /// returning whether the connection was closed.
+    async fn step(&mut self) -> Result<bool, ClientError> {
+        let deadline = self.keep_alive.deadline();
+        let keep_alive = async move {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            packet = self.framed.next() => match packet {
+                Some(packet) => self.handle_packet(packet?).await?,
+                None => return Err(ClientError::ConnectionClosed),
+            },
+            request = self.requests.recv() => match request {
+                Some(request) => return self.handle_request(request).await,
+                None => {
+                    self.disconnect().await?;
+                    return Ok(true);
+                },
+            },
+            _ = keep_alive => if let Some(pingreq) = self.keep_alive.poll(Instant::now())? {
+                self.send(pingreq).await?;
+            },
+        }
+        Ok(false)
+    }
+
+    /// returns whether the request closed the connection
+    async fn handle_request(&mut self, request: Request) -> Result<bool, ClientError> {
+        match request {
+            Request::Publish { topic_name, payload, qos, retain, reply } => {
+                let publish = self.message(topic_name, payload, qos, retain);
+                self.publish(publish, reply).await?;
+            },
+            Request::Subscribe { topic_filters, handlers, reply } => if let Some(subscribe) = self.subscribe(topic_filters, handlers, reply) {
+                self.send(subscribe).await?;
+            },
+            Request::Unsubscribe { topic_filters, reply } => if let Some(unsubscribe) = self.unsubscribe(topic_filters, reply) {
+                self.send(unsubscribe).await?;
+            },
+            Request::Disconnect { reply } => {
+                let _ = reply.send(self.disconnect().await);
+                return Ok(true);
+            },
+        }
+        Ok(false)
+    }
+
+    /// Handle a request while there is no connection, returning whether it asked to disconnect.
+    ///
+    /// Messages go to the outbox, while subscribing and unsubscribing wait like any other pending request.
+    fn queue_request(&mut self, request: Request) -> bool {
+        match request {
+            Request::Publish { topic_name, payload, qos, retain, reply } => {
+                let publish = self.message(topic_name, payload, qos, retain);
+                match self.outbox.push(publish, reply) {
+                    Ok(dropped) => for (_, reply) in dropped {
+                        let _ = reply.send(Err(ClientError::OutboxFull));
+                    },
+                    // a message that can never fit fails whatever the policy
+                    Err(blocked) if self.outbox.policy() == OverflowPolicy::Block && self.outbox.fits(&blocked.0) => self.blocked = Some(blocked),
+                    Err((_, reply)) => { let _ = reply.send(Err(ClientError::OutboxFull)); },
+                }
+            },
+            Request::Subscribe { topic_filters, handlers, reply } => { self.subscribe(topic_filters, handlers, reply); },
+            Request::Unsubscribe { topic_filters, reply } => { self.unsubscribe(topic_filters, reply); },
+            Request::Disconnect { reply } => {
+                let _ = reply.send(Ok(()));
+                return true;
+            },
+        }
+        false
+    }
+
+    fn message(&self, topic_name: String, payload: Vec<u8>, qos: Qos, retain: bool) -> PublishData {
+        let builder = PublishData::builder(topic_name, payload).qos(qos).retain(retain);
+        with_properties(self.protocol_version(), builder, PublishDataBuilder::properties).build_unchecked()
+    }
+
+    /// Send a message, answering `reply` once it is acknowledged as its QoS requires.
+    async fn publish(&mut self, publish: PublishData, reply: Reply<()>) -> Result<(), ClientError> {
+        if publish.qos() == Qos::AtMostOnce {
+            self.send(Packet::Publish(publish)).await?;
+            let _ = reply.send(Ok(()));
+            return Ok(());
+        }
+        let (packet_identifier, reply) = match self.allocate(reply) {
+            Some(allocated) => allocated,
+            None => return Ok(()),
+        };
+        let publish = publish.with_packet_identifier(packet_identifier);
+        self.qos.publish(&publish)?;
+        self.pending.insert(packet_identifier, Pending::Publish(reply));
+        self.send(Packet::Publish(publish)).await
+    }
+
+    /// the SUBSCRIBE to send for a request now pending, `None` if there was no packet identifier for it
+    fn subscribe(&mut self, topic_filters: Vec<TopicFilter>, handlers: Handlers, reply: Reply<Vec<ReturnCode>>) -> Option<Packet> {
+        let (packet_identifier, reply) = self.allocate(reply)?;
+        let subscribe = with_properties(self.protocol_version(), SubscribeData::new(packet_identifier, topic_filters), SubscribeData::with_properties);
+        let ids = self.router.register(handlers);
+        self.pending.insert(packet_identifier, Pending::Subscribe(subscribe.clone(), ids, reply));
+        Some(Packet::Subscribe(subscribe))
+    }
+
+    /// the UNSUBSCRIBE to send for a request now pending, `None` if there was no packet identifier for it
+    fn unsubscribe(&mut self, topic_filters: Vec<String>, reply: Reply<()>) -> Option<Packet> {
+        let (packet_identifier, reply) = self.allocate(reply)?;
+        let unsubscribe = with_properties(self.protocol_version(), UnsubscribeData::new(packet_identifier, topic_filters), UnsubscribeData::with_properties);
+        self.pending.insert(packet_identifier, Pending::Unsubscribe(unsubscribe.clone(), reply));
+        Some(Packet::Unsubscribe(unsubscribe))
+    }
+
+    async fn handle_packet(&mut self, packet: Packet) -> Result<(), ClientError> {
+        match packet {
+            Packet::Publish(publish) => match self.qos.receive_publish(&publish)? {
+                Delivery::Deliver(ack) => {
+                    if !self.router.dispatch(&publish) {
+                        // nobody may be listening for messages, which is fine
+                        let _ = self.messages.send(publish);
+                    }
+                    if let Some(ack) = ack {
+                        self.send(ack).await?;
+                    }
+                },
+                Delivery::Duplicate(ack) => self.send(ack).await?,
+            },
+            Packet::Pubrel(ref ack) => {
+                let pubcomp = self.qos.pubrel(ack)?;
+                self.send(pubcomp).await?;
+            },
+            Packet::Puback(ref ack) => {
+                self.qos.puback(ack)?;
+                self.complete_publish(ack);
+            },
+            Packet::Pubrec(ref ack) => match self.qos.pubrec(ack)? {
+                Some(pubrel) => self.send(pubrel).await?,
+                // refused by the server
+                None => self.complete_publish(ack),
+            },
+            Packet::Pubcomp(ref ack) => {
+                self.qos.pubcomp(ack)?;
+                self.complete_publish(ack);
+            },
+            Packet::Suback(ref suback) if self.subscriptions.resubscribed(suback, &mut self.packet_identifiers) => {},
+            Packet::Suback(ref suback) => match self.take_pending(suback.packet_identifier()) {
+                Some(Pending::Subscribe(subscribe, ids, reply)) => {
+                    self.subscriptions.subscribed(subscribe.topic_filters(), suback.return_codes());
+                    self.router.subscribed(&ids, suback.return_codes());
+                    let _ = reply.send(Ok(suback.return_codes().to_vec()));
+                },
+                _ => return Err(ClientError::UnexpectedPacket(Box::new(packet))),
+            },
+            Packet::Unsuback(ref unsuback) => match self.take_pending(unsuback.packet_identifier()) {
+                Some(Pending::Unsubscribe(unsubscribe, reply)) => {
+                    self.subscriptions.unsubscribed(unsubscribe.topic_filters());
+                    self.router.unsubscribed(unsubscribe.topic_filters());
+                    let _ = reply.send(Ok(()));
+                },
+                _ => return Err(ClientError::UnexpectedPacket(Box::new(packet))),
+            },
+            Packet::Pingresp => self.keep_alive.pingresp_received(),
+            Packet::Disconnect(disconnect) => return Err(ClientError::Disconnected(disconnect.reason_code())),
+            packet => return Err(ClientError::UnexpectedPacket(Box::new(packet))),
+        }
+        Ok(())
+    }
+
+    /// Answer the request that published the message `ack` ends the flow of, if any,
+    /// and free its packet identifier: a restored message has no request waiting for it.
+    fn complete_publish(&mut self, ack: &AckData) {
+        let packet_identifier = ack.packet_identifier();
+        if let Some(Pending::Publish(reply)) = self.pending.remove(&packet_identifier) {
+            let _ = reply.send(check_reason_code(ack.reason_code()));
+        }
+        self.packet_identifiers.release(packet_identifier);
+    }
+
+    /// Replace the connection lost with `err`, or fail with the last error once the backoff gives up.
+    ///
+    /// Requests keep coming in meanwhile, returns whether one of them asked to disconnect.
+    async fn reconnect(&mut self, mut err: ClientError) -> Result<bool, ClientError> {
+        while let Some(delay) = self.backoff.as_mut().and_then(Backoff::next_delay) {
+            let retry = tokio::time::sleep(delay);
+            tokio::pin!(retry);
+            loop {
+                tokio::select! {
+                    _ = &mut retry => break,
+                    request = self.requests.recv(), if self.blocked.is_none() => match request {
+                        Some(request) => if self.queue_request(request) { return Ok(true) },
+                        None => return Ok(true),
+                    },
+                }
+            }
+            match self.resume().await {
+                Ok(()) => {
+                    if let Some(backoff) = self.backoff.as_mut() {
+                        backoff.reset();
+                    }
+                    return Ok(false);
+                },
+                Err(resume_err) if resume_err.is_connection_lost() => err = resume_err,
+                Err(resume_err) => return Err(resume_err),
+            }
+        }
+        Err(err)
+    }
+
+    /// Connect again and pick up where the lost connection left off.
+    async fn resume(&mut self) -> Result<(), ClientError> {
+        let (framed, session_present, keep_alive) = handshake(&self.addresses, self.connect.clone()).await?;
+        self.framed = framed;
+        self.session_present = session_present;
+        self.keep_alive = keep_alive;
+        self.resend().await
+    }
+
+    /// Send whatever the server may not have received on an earlier connection,
+    /// followed by the outbox.
+    async fn resend(&mut self) -> Result<(), ClientError> {
+        if !self.session_present {
+            self.qos.clear_inbound()?;
+            if let Some(subscribe) = self.subscriptions.resubscribe(self.protocol_version(), &mut self.packet_identifiers)? {
+                self.send(subscribe).await?;
+            }
+        }
+        for packet in self.qos.retransmissions() {
+            self.send(packet).await?;
+        }
+        let mut requests: Vec<_> = self.pending.iter().filter_map(|(packet_identifier, pending)| match pending {
+            Pending::Publish(_) => None,
+            Pending::Subscribe(subscribe, _, _) => Some((packet_identifier.0, Packet::Subscribe(subscribe.clone()))),
+            Pending::Unsubscribe(unsubscribe, _) => Some((packet_identifier.0, Packet::Unsubscribe(unsubscribe.clone()))),
+        }).collect();
+        requests.sort_by_key(|&(packet_identifier, _)| packet_identifier);
+        for (_, request) in requests {
+            self.send(request).await?;
+        }
+        // and then what was published in the meantime, in order
+        while let Some((publish, reply)) = self.outbox.pop() {
+            self.publish(publish, reply).await?;
+        }
+        if let Some((publish, reply)) = self.blocked.take() {
+            self.publish(publish, reply).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, packet: Packet) -> Result<(), ClientError> {
+        self.framed.send(packet).await?;
+        self.keep_alive.packet_sent(Instant::now());
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ClientError> {
+        self.send(Packet::Disconnect(DisconnectData::default())).await?;
+        Ok(self.framed.close().await?)
+    }
+
+    /// A packet identifier for the request answered by `reply`,
+    /// which fails right away when none are left.
+    fn allocate<T>(&mut self, reply: Reply<T>) -> Option<(PacketIdentifier, Reply<T>)> {
+        match self.packet_identifiers.allocate() {
+            Ok(packet_identifier) => Some((packet_identifier, reply)),
+            Err(err) => {
+                let _ = reply.send(Err(err.into()));
+                None
+            },
+        }
+    }
+
+    /// Remove the request waiting for `packet_identifier`, which becomes free again.
+    fn take_pending(&mut self, packet_identifier: PacketIdentifier) -> Option<Pending> {
+        let pending = self.pending.remove(&packet_identifier)?;
+        self.packet_identifiers.release(packet_identifier);
+        Some(pending)
+    }
+}
+
+/// Connect to one of `addresses` and wait for the CONNACK, which must accept the connection.
+/// The server has to answer within the keepalive, or the handshake fails with `KeepAliveTimeout`.
+async fn handshake(addresses: &[SocketAddr], connect: ConnectData) -> Result<(Framed<TcpStream, MqttCodec>, bool, KeepAlive), ClientError> {
+    let stream = TcpStream::connect(addresses).await?;
+    // the codec picks up the protocol version from the CONNECT
+    let mut framed = Framed::new(stream, MqttCodec::new());
+    let keepalive = connect.keepalive();
+    framed.send(Packet::Connect(connect)).await?;
+    let connack = match KeepAlive::new(keepalive, Instant::now()).interval() {
+        Some(interval) => tokio::time::timeout(interval, framed.next()).await.map_err(|_| ClientError::KeepAliveTimeout)?,
+        None => framed.next().await,
+    };
+    match connack {
+        Some(Ok(Packet::Connack(connack))) => match connack.return_code() {
+            ConnackReturnCode::Accepted => Ok((framed, connack.session_present(), KeepAlive::negotiate(keepalive, &connack, Instant::now()))),
+            return_code => Err(ClientError::ConnectionRefused(return_code)),
+        },
+        Some(Ok(packet)) => Err(ClientError::UnexpectedPacket(Box::new(packet))),
+        Some(Err(err)) => Err(err.into()),
+        None => Err(ClientError::ConnectionClosed),
+    }
+}
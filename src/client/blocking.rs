@@ -0,0 +1,427 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::codec::PacketDecoder;
+use crate::packet::*;
+use crate::qos::{Delivery, PacketIdAllocator};
+use crate::topic::validate_topic_name;
+use super::{Backoff, ClientError, Handler, KeepAlive, SessionStore};
+use super::packets::{check_reason_code, check_topic_filters, with_properties};
+use super::reconnect::Subscriptions;
+use super::router::{split_handlers, Router};
+use super::session::Session;
+
+/// A client that blocks on its socket, handling one request at a time.
+///
+/// Messages that arrive while waiting for an acknowledgement go to the handlers of the
+/// subscriptions they match, if any were given to `subscribe_with_handlers`, or are kept for `poll`.
+/// Incoming QoS 1 and 2 messages are acknowledged as they are received, and
+/// PINGREQs are sent whenever the client blocks for longer than the keepalive.
+///
+/// With `set_reconnect` a lost connection is replaced by a new one resuming the session,
+/// transparently to the request that was waiting on it.
+#[derive(Debug)]
+pub struct Client {
+    stream: TcpStream,
+    decoder: PacketDecoder,
+    addresses: Vec<SocketAddr>,
+    /// the CONNECT to send when reconnecting
+    connect: ConnectData,
+    backoff: Option<Backoff>,
+    session_present: bool,
+    keep_alive: KeepAlive,
+    /// how long a single `poll` or request may wait for the server
+    read_timeout: Option<Duration>,
+    packet_identifiers: PacketIdAllocator,
+    qos: Session,
+    subscriptions: Subscriptions,
+    router: Router,
+    /// the SUBSCRIBE or UNSUBSCRIBE waiting for its acknowledgement
+    request: Option<Packet>,
+    /// messages received but not yet returned by `poll`
+    incoming: VecDeque<PublishData>,
+}
+
+impl Client {
+    /// Connect to `address` and wait for the CONNACK, which must accept the connection.
+    pub fn connect<A: ToSocketAddrs>(address: A, connect: ConnectData) -> Result<Client, ClientError> {
+        Client::start(address, connect, Session::default(), PacketIdAllocator::new())
+    }
+
+    /// Like `connect`, but picking up the session saved in `store` and saving it there from then on.
+    /// Messages it still has in flight are sent again once connected, as after reconnecting.
+    pub fn connect_with_store<A: ToSocketAddrs, S: SessionStore + 'static>(address: A, connect: ConnectData, store: S) -> Result<Client, ClientError> {
+        let mut packet_identifiers = PacketIdAllocator::new();
+        let session = Session::restore(Box::new(store), &mut packet_identifiers)?;
+        Client::start(address, connect, session, packet_identifiers)
+    }
+
+    fn start<A: ToSocketAddrs>(address: A, connect: ConnectData, session: Session, packet_identifiers: PacketIdAllocator) -> Result<Client, ClientError> {
+        let addresses: Vec<_> = address.to_socket_addrs()?.collect();
+        let stream = TcpStream::connect(&addresses[..])?;
+        let protocol_version = connect.protocol_version().unwrap_or(ProtocolVersion::V311);
+        let mut client = Client {
+            stream,
+            decoder: PacketDecoder::with_context(DecodingContext::new(protocol_version)),
+            addresses,
+            connect: connect.clone().with_clean_session(false),
+            backoff: None,
+            session_present: false,
+            keep_alive: KeepAlive::new(connect.keepalive(), Instant::now()),
+            read_timeout: None,
+            packet_identifiers,
+            qos: session,
+            subscriptions: Subscriptions::default(),
+            router: Router::default(),
+            request: None,
+            incoming: VecDeque::new(),
+        };
+        client.handshake(connect)?;
+        client.resend()?;
+        Ok(client)
+    }
+
+    /// whether the server resumed a previous session on the latest connection
+    pub fn session_present(&self) -> bool {
+        self.session_present
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.decoder.context().protocol_version
+    }
+
+    /// How long `poll` and waiting for acknowledgements may block, `None` to wait forever.
+    /// Running out of time is an `IoError` of kind `TimedOut`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Reconnect after losing the connection, waiting as long as `backoff` says before each attempt,
+    /// or `None` to fail with the error that lost it.
+    ///
+    /// Reconnecting never asks for a clean session. When the server still has the session,
+    /// unacknowledged messages are sent again flagged as redeliveries, otherwise they are
+    /// sent anyway and every subscription is renewed. Once `backoff` runs out of attempts
+    /// the request fails with the error of the last one.
+    pub fn set_reconnect(&mut self, backoff: Option<Backoff>) {
+        self.backoff = backoff;
+    }
+
+    /// Publish a message, returning once the server has acknowledged it as its QoS requires.
+    /// A topic name that is not valid fails with `TopicError` before anything is sent.
+    pub fn publish<T: Into<String>, P: Into<Vec<u8>>>(&mut self, topic_name: T, payload: P, qos: Qos, retain: bool) -> Result<(), ClientError> {
+        let topic_name = topic_name.into();
+        validate_topic_name(&topic_name)?;
+        let builder = PublishData::builder(topic_name, payload).qos(qos).retain(retain);
+        let builder = with_properties(self.protocol_version(), builder, PublishDataBuilder::properties);
+        if qos == Qos::AtMostOnce {
+            return self.send(&Packet::Publish(builder.build_unchecked()));
+        }
+
+        self.with_packet_identifier(|client, packet_identifier| {
+            let publish = builder.packet_identifier(packet_identifier).build_unchecked();
+            client.qos.publish(&publish)?;
+            client.send(&Packet::Publish(publish))?;
+            loop {
+                match client.wait_for_ack()? {
+                    Packet::Puback(ack) | Packet::Pubrec(ack) | Packet::Pubcomp(ack) => if ack.packet_identifier() == packet_identifier {
+                        return check_reason_code(ack.reason_code());
+                    },
+                    packet => return Err(ClientError::UnexpectedPacket(Box::new(packet))),
+                }
+            }
+        })
+    }
+
+    /// Subscribe to `topic_filters`, returning the server's answer for each of them.
+    pub fn subscribe(&mut self, topic_filters: Vec<TopicFilter>) -> Result<Vec<ReturnCode>, ClientError> {
+        check_topic_filters(topic_filters.iter().map(TopicFilter::filter))?;
+        self.with_packet_identifier(|client, packet_identifier| {
+            let subscribe = with_properties(client.protocol_version(), SubscribeData::new(packet_identifier, topic_filters.clone()), SubscribeData::with_properties);
+            match client.request(Packet::Subscribe(subscribe))? {
+                Packet::Suback(suback) if suback.packet_identifier() == packet_identifier => {
+                    client.subscriptions.subscribed(&topic_filters, suback.return_codes());
+                    Ok(suback.return_codes().to_vec())
+                },
+                packet => Err(ClientError::UnexpectedPacket(Box::new(packet))),
+            }
+        })
+    }
+
+    /// Subscribe like `subscribe`, handing the messages of each accepted topic filter to its handler
+    /// rather than to `poll`. A message matching several filters goes to each of their handlers,
+    /// and subscribing to a filter again replaces its handler.
+    pub fn subscribe_with_handlers(&mut self, subscriptions: Vec<(TopicFilter, Handler)>) -> Result<Vec<ReturnCode>, ClientError> {
+        let (topic_filters, handlers) = split_handlers(subscriptions)?;
+        let ids = self.router.register(handlers);
+        match self.subscribe(topic_filters) {
+            Ok(return_codes) => {
+                self.router.subscribed(&ids, &return_codes);
+                Ok(return_codes)
+            },
+            Err(err) => {
+                self.router.unregister(&ids);
+                Err(err)
+            },
+        }
+    }
+
+    /// Unsubscribe from `topic_filters`, dropping their handlers.
+    pub fn unsubscribe(&mut self, topic_filters: Vec<String>) -> Result<(), ClientError> {
+        check_topic_filters(topic_filters.iter().map(String::as_str))?;
+        self.with_packet_identifier(|client, packet_identifier| {
+            let unsubscribe = with_properties(client.protocol_version(), UnsubscribeData::new(packet_identifier, topic_filters.clone()), UnsubscribeData::with_properties);
+            match client.request(Packet::Unsubscribe(unsubscribe))? {
+                Packet::Unsuback(unsuback) if unsuback.packet_identifier() == packet_identifier => {
+                    client.subscriptions.unsubscribed(&topic_filters);
+                    client.router.unsubscribed(&topic_filters);
+                    Ok(())
+                },
+                packet => Err(ClientError::UnexpectedPacket(Box::new(packet))),
+            }
+        })
+    }
+
+    /// Wait for the next message published to one of our subscriptions without a handler.
+    ///
+    /// Messages for handlers are handed to them meanwhile, so a client whose subscriptions
+    /// all have handlers keeps them going by calling `poll` in a loop.
+    pub fn poll(&mut self) -> Result<PublishData, ClientError> {
+        loop {
+            if let Some(publish) = self.incoming.pop_front() {
+                return Ok(publish);
+            }
+            match self.receive()? {
+                // a message whose `publish` gave up waiting for it
+                Some(Packet::Puback(_)) | Some(Packet::Pubrec(_)) | Some(Packet::Pubcomp(_)) | None => {},
+                Some(packet) => return Err(ClientError::UnexpectedPacket(Box::new(packet))),
+            }
+        }
+    }
+
+    /// Send a DISCONNECT, so the server discards the will, and close the connection.
+    pub fn disconnect(mut self) -> Result<(), ClientError> {
+        self.write(&Packet::Disconnect(DisconnectData::default()))?;
+        Ok(self.stream.shutdown(Shutdown::Both)?)
+    }
+
+    /// Send the CONNECT over a new connection and wait for the CONNACK, which must accept it.
+    fn handshake(&mut self, connect: ConnectData) -> Result<(), ClientError> {
+        let keepalive = connect.keepalive();
+        // the server has to answer the CONNECT in time as well
+        self.keep_alive = KeepAlive::new(keepalive, Instant::now());
+        self.write(&Packet::Connect(connect))?;
+        match self.next_packet(None)? {
+            Packet::Connack(connack) => match connack.return_code() {
+                ConnackReturnCode::Accepted => {
+                    self.session_present = connack.session_present();
+                    self.keep_alive = KeepAlive::negotiate(keepalive, &connack, Instant::now());
+                    Ok(())
+                },
+                return_code => Err(ClientError::ConnectionRefused(return_code)),
+            },
+            packet => Err(ClientError::UnexpectedPacket(Box::new(packet))),
+        }
+    }
+
+    /// Replace the connection lost with `err`, or fail with the last error once the backoff gives up.
+    fn reconnect(&mut self, mut err: ClientError) -> Result<(), ClientError> {
+        while let Some(delay) = self.backoff.as_mut().and_then(Backoff::next_delay) {
+            thread::sleep(delay);
+            match self.resume() {
+                Ok(()) => {
+                    if let Some(backoff) = self.backoff.as_mut() {
+                        backoff.reset();
+                    }
+                    return Ok(());
+                },
+                Err(resume_err) if resume_err.is_connection_lost() => err = resume_err,
+                Err(resume_err) => return Err(resume_err),
+            }
+        }
+        Err(err)
+    }
+
+    /// Connect again and pick up where the lost connection left off.
+    fn resume(&mut self) -> Result<(), ClientError> {
+        self.stream = TcpStream::connect(&self.addresses[..])?;
+        self.decoder = PacketDecoder::with_context(DecodingContext::new(self.protocol_version()));
+        self.handshake(self.connect.clone())?;
+        self.resend()
+    }
+
+    /// Send whatever the server may not have received on an earlier connection.
+    fn resend(&mut self) -> Result<(), ClientError> {
+        if !self.session_present {
+            self.qos.clear_inbound()?;
+            if let Some(subscribe) = self.subscriptions.resubscribe(self.protocol_version(), &mut self.packet_identifiers)? {
+                self.write(&subscribe)?;
+            }
+        }
+        for packet in self.qos.retransmissions() {
+            self.write(&packet)?;
+        }
+        if let Some(request) = self.request.clone() {
+            self.write(&request)?;
+        }
+        Ok(())
+    }
+
+    /// Make a request with a packet identifier of its own, released once it is done.
+    fn with_packet_identifier<T, F>(&mut self, request: F) -> Result<T, ClientError>
+        where F: FnOnce(&mut Client, PacketIdentifier) -> Result<T, ClientError> {
+        let packet_identifier = self.packet_identifiers.allocate()?;
+        let result = request(self, packet_identifier);
+        // a message the request gave up on keeps it until its flow completes
+        if !self.qos.is_in_flight(packet_identifier) {
+            self.packet_identifiers.release(packet_identifier);
+        }
+        result
+    }
+
+    /// Send a SUBSCRIBE or UNSUBSCRIBE and wait for its acknowledgement, sending it again after reconnecting.
+    fn request(&mut self, packet: Packet) -> Result<Packet, ClientError> {
+        self.request = Some(packet.clone());
+        let result = self.send(&packet).and_then(|()| loop {
+            match self.wait_for_ack()? {
+                // a message whose `publish` gave up waiting for it
+                Packet::Puback(_) | Packet::Pubrec(_) | Packet::Pubcomp(_) => {},
+                ack => return Ok(ack),
+            }
+        });
+        self.request = None;
+        result
+    }
+
+    fn wait_for_ack(&mut self) -> Result<Packet, ClientError> {
+        loop {
+            if let Some(packet) = self.receive()? {
+                return Ok(packet);
+            }
+        }
+    }
+
+    /// Read a packet and deal with what the server may send at any time,
+    /// returning anything else to the caller, including the acknowledgement
+    /// that completes a QoS 1 or 2 message.
+    fn receive(&mut self) -> Result<Option<Packet>, ClientError> {
+        match self.read_packet()? {
+            Packet::Publish(publish) => {
+                match self.qos.receive_publish(&publish)? {
+                    Delivery::Deliver(ack) => {
+                        if !self.router.dispatch(&publish) {
+                            self.incoming.push_back(publish);
+                        }
+                        if let Some(ack) = ack {
+                            self.send(&ack)?;
+                        }
+                    },
+                    Delivery::Duplicate(ack) => self.send(&ack)?,
+                }
+                Ok(None)
+            },
+            Packet::Pubrel(ack) => {
+                let pubcomp = self.qos.pubrel(&ack)?;
+                self.send(&pubcomp)?;
+                Ok(None)
+            },
+            Packet::Puback(ack) => {
+                self.qos.puback(&ack)?;
+                Ok(self.published(Packet::Puback(ack)))
+            },
+            Packet::Pubrec(ack) => match self.qos.pubrec(&ack)? {
+                Some(pubrel) => {
+                    self.send(&pubrel)?;
+                    Ok(None)
+                },
+                // refused by the server
+                None => Ok(self.published(Packet::Pubrec(ack))),
+            },
+            Packet::Pubcomp(ack) => {
+                self.qos.pubcomp(&ack)?;
+                Ok(self.published(Packet::Pubcomp(ack)))
+            },
+            Packet::Suback(ref suback) if self.subscriptions.resubscribed(suback, &mut self.packet_identifiers) => Ok(None),
+            Packet::Pingresp => {
+                self.keep_alive.pingresp_received();
+                Ok(None)
+            },
+            Packet::Disconnect(disconnect) => Err(ClientError::Disconnected(disconnect.reason_code())),
+            packet => Ok(Some(packet)),
+        }
+    }
+
+    /// `ack` completed the flow of an outbound message, so its packet identifier is free again.
+    fn published(&mut self, ack: Packet) -> Option<Packet> {
+        if let Packet::Puback(ref data) | Packet::Pubrec(ref data) | Packet::Pubcomp(ref data) = ack {
+            self.packet_identifiers.release(data.packet_identifier());
+        }
+        Some(ack)
+    }
+
+    /// Read the next packet, reconnecting if the connection turns out to be lost.
+    fn read_packet(&mut self) -> Result<Packet, ClientError> {
+        let timeout = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            match self.next_packet(timeout) {
+                Err(err) if self.backoff.is_some() && err.is_connection_lost() => self.reconnect(err)?,
+                result => return result,
+            }
+        }
+    }
+
+    /// Read the next packet before `timeout`, pinging the server while waiting for it.
+    fn next_packet(&mut self, timeout: Option<Instant>) -> Result<Packet, ClientError> {
+        let mut buffer = [0; 4096];
+        loop {
+            if let Some(packet) = self.decoder.next_packet()? {
+                return Ok(packet);
+            }
+
+            let now = Instant::now();
+            if let Some(pingreq) = self.keep_alive.poll(now)? {
+                self.write(&pingreq)?;
+            }
+            if timeout.is_some_and(|timeout| now >= timeout) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for the server").into());
+            }
+            let wake_up = match (timeout, self.keep_alive.deadline()) {
+                (Some(timeout), Some(deadline)) => Some(timeout.min(deadline)),
+                (timeout, deadline) => timeout.or(deadline),
+            };
+            // a zero timeout is an error rather than not waiting at all
+            let wait = wake_up.map(|wake_up| wake_up.saturating_duration_since(now).max(Duration::from_millis(1)));
+            self.stream.set_read_timeout(wait)?;
+
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ClientError::ConnectionClosed),
+                Ok(read) => self.decoder.feed(&buffer[..read]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => {},
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Send a packet, reconnecting if the connection turns out to be lost.
+    ///
+    /// The packet is not sent again itself: whatever the server must see is resent by `resume`.
+    fn send(&mut self, packet: &Packet) -> Result<(), ClientError> {
+        match self.write(packet) {
+            Err(err) if self.backoff.is_some() && err.is_connection_lost() => self.reconnect(err),
+            result => result,
+        }
+    }
+
+    fn write(&mut self, packet: &Packet) -> Result<(), ClientError> {
+        // encoded up front so the packet goes out in a single write
+        let mut bytes = Vec::with_capacity(packet.encoded_length() as usize);
+        packet.encode(&mut bytes)?;
+        self.stream.write_all(&bytes)?;
+        self.keep_alive.packet_sent(Instant::now());
+        Ok(())
+    }
+}
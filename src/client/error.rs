@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::packet::{ConnackReturnCode, DecodingError, Packet, ReasonCode};
+use crate::qos::QosError;
+use crate::topic::TopicError;
+
+#[derive(Debug)]
+pub enum ClientError {
+    IoError(io::Error),
+    DecodingError(DecodingError),
+    /// the server broke the rules of a QoS 1 or 2 message flow
+    QosError(QosError),
+    /// the server answered the CONNECT with something other than `Accepted`
+    ConnectionRefused(ConnackReturnCode),
+    /// the server acknowledged a request with an MQTT 5 failure reason code
+    Rejected(ReasonCode),
+    /// a packet the server should not have sent at this point
+    UnexpectedPacket(Box<Packet>),
+    /// the server sent a DISCONNECT
+    Disconnected(ReasonCode),
+    /// the server closed the connection without a DISCONNECT
+    ConnectionClosed,
+    /// the server did not answer a PINGREQ within the keepalive interval
+    KeepAliveTimeout,
+    /// the message was dropped from the outbox, which was full while reconnecting
+    OutboxFull,
+    /// the `SessionStore` failed to load or save the session
+    StoreError(io::Error),
+    /// a topic name or filter that is not valid, refused before sending it
+    TopicError(TopicError),
+    /// a SUBSCRIBE or UNSUBSCRIBE without any topic filter, refused before sending it
+    NoTopicFilters,
+}
+
+impl ClientError {
+    /// Whether the connection was lost, rather than the server misbehaving or refusing a request,
+    /// so that reconnecting may help. Running out of time in `set_read_timeout` does not count.
+    pub fn is_connection_lost(&self) -> bool {
+        match self {
+            ClientError::IoError(err) => err.kind() != io::ErrorKind::TimedOut && err.kind() != io::ErrorKind::WouldBlock,
+            ClientError::ConnectionClosed | ClientError::KeepAliveTimeout => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::IoError(err) => write!(f, "I/O error: {}", err),
+            ClientError::DecodingError(err) => write!(f, "received an invalid packet: {}", err),
+            ClientError::QosError(err) => write!(f, "{}", err),
+            ClientError::ConnectionRefused(return_code) => write!(f, "connection refused: {:?}", return_code),
+            ClientError::Rejected(reason_code) => write!(f, "request rejected with reason code {:#04x}", reason_code.0),
+            ClientError::UnexpectedPacket(packet) => write!(f, "unexpected packet {:?}", packet),
+            ClientError::Disconnected(reason_code) => write!(f, "disconnected by the server with reason code {:#04x}", reason_code.0),
+            ClientError::ConnectionClosed => write!(f, "connection closed by the server"),
+            ClientError::KeepAliveTimeout => write!(f, "no PINGRESP from the server within the keepalive interval"),
+            ClientError::OutboxFull => write!(f, "message dropped from the full outbox"),
+            ClientError::StoreError(err) => write!(f, "session store error: {}", err),
+            ClientError::TopicError(err) => write!(f, "invalid topic: {}", err),
+            ClientError::NoTopicFilters => write!(f, "no topic filters to subscribe to or unsubscribe from"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::IoError(err) => Some(err),
+            ClientError::DecodingError(err) => Some(err),
+            ClientError::QosError(err) => Some(err),
+            ClientError::StoreError(err) => Some(err),
+            ClientError::TopicError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::IoError(err)
+    }
+}
+
+impl From<DecodingError> for ClientError {
+    fn from(err: DecodingError) -> Self {
+        ClientError::DecodingError(err)
+    }
+}
+
+impl From<QosError> for ClientError {
+    fn from(err: QosError) -> Self {
+        ClientError::QosError(err)
+    }
+}
+
+impl From<TopicError> for ClientError {
+    fn from(err: TopicError) -> Self {
+        ClientError::TopicError(err)
+    }
+}
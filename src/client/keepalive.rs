@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use crate::packet::{ConnackData, Packet, Property};
+use super::ClientError;
+
+/// When a client has to send a PINGREQ to keep an idle connection open,
+/// and when to give up on the server answering it, see section 3.1.2.10.
+///
+/// It does no I/O: the client reports what it sends and receives, and asks
+/// `poll` what to do once the `deadline` has passed.
+#[derive(Debug, Clone)]
+pub struct KeepAlive {
+    /// `None` when keepalive is turned off
+    interval: Option<Duration>,
+    last_sent: Instant,
+    /// when the PINGREQ still waiting for a PINGRESP was sent
+    ping_sent: Option<Instant>,
+}
+
+impl KeepAlive {
+    /// a keepalive of `seconds`, 0 turning it off
+    pub fn new(seconds: u16, now: Instant) -> KeepAlive {
+        let interval = if seconds > 0 { Some(Duration::from_secs(u64::from(seconds))) } else { None };
+        KeepAlive { interval, last_sent: now, ping_sent: None }
+    }
+
+    /// The `keepalive` the CONNECT asked for, unless an MQTT 5 server told the client to use another one.
+    pub fn negotiate(keepalive: u16, connack: &ConnackData, now: Instant) -> KeepAlive {
+        let server_keep_alive = connack.properties().into_iter().flat_map(|properties| properties.iter())
+            .filter_map(|property| match property {
+                Property::ServerKeepAlive(seconds) => Some(*seconds),
+                _ => None,
+            }).next();
+        KeepAlive::new(server_keep_alive.unwrap_or(keepalive), now)
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Any packet resets the idle time, not just PINGREQs.
+    pub fn packet_sent(&mut self, now: Instant) {
+        self.last_sent = now;
+    }
+
+    pub fn pingresp_received(&mut self) {
+        self.ping_sent = None;
+    }
+
+    /// when `poll` has something to do next, `None` when keepalive is turned off
+    pub fn deadline(&self) -> Option<Instant> {
+        let interval = self.interval?;
+        Some(self.ping_sent.unwrap_or(self.last_sent) + interval)
+    }
+
+    /// Returns the PINGREQ to send when the connection has been idle for the interval,
+    /// or `KeepAliveTimeout` when a PINGREQ has gone unanswered for as long.
+    pub fn poll(&mut self, now: Instant) -> Result<Option<Packet>, ClientError> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {
+                if self.ping_sent.is_some() {
+                    return Err(ClientError::KeepAliveTimeout);
+                }
+                self.ping_sent = Some(now);
+                Ok(Some(Packet::Pingreq))
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{ConnackReturnCode, Properties};
+
+    #[test]
+    fn pinging_when_idle() {
+        let start = Instant::now();
+        let mut keep_alive = KeepAlive::new(10, start);
+        assert_eq!(keep_alive.deadline(), Some(start + Duration::from_secs(10)));
+        assert!(keep_alive.poll(start + Duration::from_secs(9)).unwrap().is_none());
+
+        keep_alive.packet_sent(start + Duration::from_secs(5));
+        assert!(keep_alive.poll(start + Duration::from_secs(10)).unwrap().is_none());
+        assert_eq!(keep_alive.poll(start + Duration::from_secs(15)).unwrap(), Some(Packet::Pingreq));
+        assert!(keep_alive.poll(start + Duration::from_secs(16)).unwrap().is_none());
+
+        keep_alive.pingresp_received();
+        assert_eq!(keep_alive.deadline(), Some(start + Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn timing_out_without_a_pingresp() {
+        let start = Instant::now();
+        let mut keep_alive = KeepAlive::new(10, start);
+        assert_eq!(keep_alive.poll(start + Duration::from_secs(10)).unwrap(), Some(Packet::Pingreq));
+        keep_alive.packet_sent(start + Duration::from_secs(10));
+        // other packets going out do not make up for the missing PINGRESP
+        keep_alive.packet_sent(start + Duration::from_secs(19));
+        match keep_alive.poll(start + Duration::from_secs(20)) {
+            Err(ClientError::KeepAliveTimeout) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negotiating_the_interval() {
+        let now = Instant::now();
+        let keep_alive = KeepAlive::negotiate(0, &ConnackData::new(false, ConnackReturnCode::Accepted), now);
+        assert_eq!(keep_alive.interval(), None);
+        assert_eq!(keep_alive.deadline(), None);
+
+        let connack = ConnackData::new(false, ConnackReturnCode::Accepted)
+            .with_properties(Properties(vec![Property::ServerKeepAlive(30)]));
+        let keep_alive = KeepAlive::negotiate(0, &connack, now);
+        assert_eq!(keep_alive.interval(), Some(Duration::from_secs(30)));
+    }
+}
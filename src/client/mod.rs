@@ -0,0 +1,21 @@
+mod error;
+mod packets;
+mod keepalive;
+mod reconnect;
+mod outbox;
+mod store;
+mod session;
+mod router;
+mod blocking;
+pub use self::error::*;
+pub use self::keepalive::*;
+pub use self::reconnect::Backoff;
+pub use self::outbox::*;
+pub use self::store::{FileSessionStore, MemorySessionStore, SessionStore};
+pub use self::router::Handler;
+pub use self::blocking::*;
+
+#[cfg(feature = "tokio")]
+mod asynchronous;
+#[cfg(feature = "tokio")]
+pub use self::asynchronous::*;
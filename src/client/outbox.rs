@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use crate::packet::PublishData;
+
+/// What an `Outbox` does with a message it has no room for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// drop the oldest messages until the new one fits
+    DropOldest,
+    /// drop the new message
+    DropNewest,
+    /// hand the new message back, to be sent once there is room
+    Block,
+}
+
+/// Messages published while the client has no connection, to be sent in order once it has one again.
+///
+/// The limits count messages and the bytes of their topic names and payloads.
+/// Each message carries a `T` along, such as whoever is waiting for it to be sent.
+#[derive(Debug)]
+pub struct Outbox<T> {
+    messages: VecDeque<(PublishData, T)>,
+    bytes: usize,
+    max_messages: usize,
+    max_bytes: usize,
+    policy: OverflowPolicy,
+}
+
+impl<T> Outbox<T> {
+    pub fn new(max_messages: usize, max_bytes: usize, policy: OverflowPolicy) -> Outbox<T> {
+        Outbox { messages: VecDeque::new(), bytes: 0, max_messages, max_bytes, policy }
+    }
+
+    /// an outbox without limits
+    pub fn unbounded() -> Outbox<T> {
+        Outbox::new(usize::MAX, usize::MAX, OverflowPolicy::Block)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// the bytes of the topic names and payloads of the queued messages
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Queue a message, returning the messages dropped to make room for it.
+    ///
+    /// A message that cannot be queued is returned as the error: dropped for `DropNewest`,
+    /// and for `Block` to be pushed again once `pop` has made room. So is a message
+    /// larger than the outbox itself, whatever the policy.
+    pub fn push(&mut self, publish: PublishData, value: T) -> Result<Vec<(PublishData, T)>, (PublishData, T)> {
+        if !self.fits(&publish) {
+            return Err((publish, value));
+        }
+        let size = size(&publish);
+        let mut dropped = Vec::new();
+        while !self.has_room(size) {
+            match self.policy {
+                OverflowPolicy::DropOldest => dropped.extend(self.pop()),
+                OverflowPolicy::DropNewest | OverflowPolicy::Block => return Err((publish, value)),
+            }
+        }
+        self.bytes += size;
+        self.messages.push_back((publish, value));
+        Ok(dropped)
+    }
+
+    /// Whether the message fits in the outbox once it is empty, so that it is worth waiting for room.
+    pub fn fits(&self, publish: &PublishData) -> bool {
+        size(publish) <= self.max_bytes && self.max_messages > 0
+    }
+
+    /// the oldest message, to send next
+    pub fn pop(&mut self) -> Option<(PublishData, T)> {
+        let (publish, value) = self.messages.pop_front()?;
+        self.bytes -= size(&publish);
+        Some((publish, value))
+    }
+
+    fn has_room(&self, size: usize) -> bool {
+        self.messages.len() < self.max_messages && self.bytes + size <= self.max_bytes
+    }
+}
+
+impl<T> Default for Outbox<T> {
+    fn default() -> Outbox<T> {
+        Outbox::unbounded()
+    }
+}
+
+fn size(publish: &PublishData) -> usize {
+    publish.topic_name().len() + publish.payload().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(payload: &str) -> PublishData {
+        PublishData::builder("t", payload).build().unwrap()
+    }
+
+    fn payloads<T>(outbox: &mut Outbox<T>) -> Vec<String> {
+        std::iter::from_fn(|| outbox.pop()).map(|(publish, _)| String::from_utf8(publish.payload().to_vec()).unwrap()).collect()
+    }
+
+    #[test]
+    fn dropping_the_oldest() {
+        let mut outbox = Outbox::new(3, 100, OverflowPolicy::DropOldest);
+        for (i, payload) in ["a", "b", "c"].iter().enumerate() {
+            assert!(outbox.push(message(payload), i).unwrap().is_empty());
+        }
+        assert_eq!(outbox.push(message("d"), 3), Ok(vec![(message("a"), 0)]));
+        assert_eq!((outbox.len(), outbox.bytes()), (3, 6));
+        assert_eq!(payloads(&mut outbox), vec!["b", "c", "d"]);
+        assert_eq!(outbox.bytes(), 0);
+    }
+
+    #[test]
+    fn limiting_bytes() {
+        let mut outbox = Outbox::new(10, 10, OverflowPolicy::DropOldest);
+        outbox.push(message("aaa"), ()).unwrap();
+        outbox.push(message("bbb"), ()).unwrap();
+        // drops both to fit
+        assert_eq!(outbox.push(message("cccccc"), ()).unwrap().len(), 2);
+        assert!(!outbox.fits(&message("too large!")));
+        assert_eq!(outbox.push(message("too large!"), ()), Err((message("too large!"), ())));
+        assert_eq!(payloads(&mut outbox), vec!["cccccc"]);
+    }
+
+    #[test]
+    fn refusing_the_newest() {
+        for &policy in &[OverflowPolicy::DropNewest, OverflowPolicy::Block] {
+            let mut outbox = Outbox::new(2, 100, policy);
+            outbox.push(message("a"), ()).unwrap();
+            outbox.push(message("b"), ()).unwrap();
+            assert_eq!(outbox.push(message("c"), ()), Err((message("c"), ())));
+
+            outbox.pop();
+            assert!(outbox.push(message("c"), ()).is_ok());
+            assert_eq!(payloads(&mut outbox), vec!["b", "c"]);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! What both clients send, independent of how they do I/O.
+
+use crate::packet::*;
+use crate::topic::validate_topic_filter;
+use super::ClientError;
+
+/// MQTT 5 packets always carry properties, even when there are none.
+pub(crate) fn with_properties<D>(protocol_version: ProtocolVersion, data: D, with_properties: fn(D, Properties) -> D) -> D {
+    if protocol_version >= ProtocolVersion::V5 { with_properties(data, Properties::new()) } else { data }
+}
+
+pub(crate) fn check_reason_code(reason_code: ReasonCode) -> Result<(), ClientError> {
+    if reason_code.is_error() { Err(ClientError::Rejected(reason_code)) } else { Ok(()) }
+}
+
+/// Refuse to send topic filters the server has to reject, see `topic::ValidTopicFilter`,
+/// or none at all.
+pub(crate) fn check_topic_filters<'a, I: IntoIterator<Item = &'a str>>(topic_filters: I) -> Result<(), ClientError> {
+    let mut topic_filters = topic_filters.into_iter().peekable();
+    if topic_filters.peek().is_none() { return Err(ClientError::NoTopicFilters) };
+    Ok(topic_filters.try_for_each(validate_topic_filter)?)
+}
@@ -0,0 +1,172 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::packet::*;
+use crate::qos::{PacketIdAllocator, QosError};
+use super::packets::with_properties;
+
+/// How long a client waits before each attempt to reconnect after losing its connection.
+///
+/// The delay doubles with every failed attempt up to a maximum, and part of it is
+/// random so that clients dropped together do not all come back at the same moment.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    /// the fraction of each delay that is random
+    jitter: f64,
+    max_attempts: Option<u32>,
+    /// failed attempts since the last successful one
+    attempts: u32,
+    random: RandomState,
+    draws: u64,
+}
+
+impl Backoff {
+    /// A delay of `initial` doubling up to `max`, half of it random, trying forever.
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff { initial, max, jitter: 0.5, max_attempts: None, attempts: 0, random: RandomState::new(), draws: 0 }
+    }
+
+    /// How much of each delay is random: 0 always waits the full delay,
+    /// 1 anything between no time at all and the full delay.
+    pub fn with_jitter(mut self, jitter: f64) -> Backoff {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Give up after `max_attempts` failed attempts in a row.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Backoff {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// the delay before the next attempt, `None` once all attempts are used up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max_attempts| self.attempts >= max_attempts) {
+            return None;
+        }
+        let delay = self.initial.checked_mul(1 << self.attempts.min(31)).map_or(self.max, |delay| delay.min(self.max));
+        self.attempts += 1;
+        Some(delay.mul_f64(1.0 - self.jitter * self.random()))
+    }
+
+    /// Start from the initial delay again, once connected.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// a number in [0, 1), without depending on a random number crate
+    fn random(&mut self) -> f64 {
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u64(self.draws);
+        self.draws += 1;
+        (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The topic filters the server granted, to subscribe to again when it lost the session.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Subscriptions {
+    topic_filters: Vec<TopicFilter>,
+    /// the SUBSCRIBE sent after reconnecting, which no request waits for
+    resubscription: Option<PacketIdentifier>,
+}
+
+impl Subscriptions {
+    /// Record the filters of a SUBSCRIBE the server answered with `return_codes`.
+    pub(crate) fn subscribed(&mut self, topic_filters: &[TopicFilter], return_codes: &[ReturnCode]) {
+        for (topic_filter, return_code) in topic_filters.iter().zip(return_codes) {
+            if let ReturnCode::Success(_) = return_code {
+                self.topic_filters.retain(|subscribed| subscribed.filter() != topic_filter.filter());
+                self.topic_filters.push(topic_filter.clone());
+            }
+        }
+    }
+
+    pub(crate) fn unsubscribed(&mut self, topic_filters: &[String]) {
+        self.topic_filters.retain(|subscribed| !topic_filters.iter().any(|filter| filter == subscribed.filter()));
+    }
+
+    /// The SUBSCRIBE renewing every subscription, `None` when there are none.
+    pub(crate) fn resubscribe(&mut self, protocol_version: ProtocolVersion, packet_identifiers: &mut PacketIdAllocator) -> Result<Option<Packet>, QosError> {
+        // the SUBACK to an earlier one is never coming
+        if let Some(packet_identifier) = self.resubscription.take() {
+            packet_identifiers.release(packet_identifier);
+        }
+        if self.topic_filters.is_empty() {
+            return Ok(None);
+        }
+        let packet_identifier = packet_identifiers.allocate()?;
+        self.resubscription = Some(packet_identifier);
+        let subscribe = SubscribeData::new(packet_identifier, self.topic_filters.clone());
+        Ok(Some(Packet::Subscribe(with_properties(protocol_version, subscribe, SubscribeData::with_properties))))
+    }
+
+    /// Whether `suback` answers the SUBSCRIBE from `resubscribe`, whose packet identifier is then released.
+    pub(crate) fn resubscribed(&mut self, suback: &SubackData, packet_identifiers: &mut PacketIdAllocator) -> bool {
+        if self.resubscription != Some(suback.packet_identifier()) {
+            return false;
+        }
+        self.resubscription = None;
+        packet_identifiers.release(suback.packet_identifier());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubling_delays() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1)).with_jitter(0.0).with_max_attempts(6);
+        let delays: Vec<_> = std::iter::from_fn(|| backoff.next_delay()).map(|delay| delay.as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(backoff.attempts(), 6);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn jittered_delays() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(1));
+        let delays: Vec<_> = (0..100).map(|_| backoff.next_delay().unwrap()).collect();
+        assert!(delays.iter().all(|&delay| delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1)));
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+
+        // far more attempts than the delay can double
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60)).with_jitter(0.0);
+        assert_eq!((0..100).map(|_| backoff.next_delay().unwrap()).last(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn resubscribing() {
+        let mut subscriptions = Subscriptions::default();
+        let mut packet_identifiers = PacketIdAllocator::new();
+        assert_eq!(subscriptions.resubscribe(ProtocolVersion::V311, &mut packet_identifiers), Ok(None));
+
+        let topic_filters = vec![TopicFilter::new("a/+", Qos::AtMostOnce), TopicFilter::new("b", Qos::AtLeastOnce), TopicFilter::new("c", Qos::AtLeastOnce)];
+        subscriptions.subscribed(&topic_filters, &[ReturnCode::Success(Qos::AtMostOnce), ReturnCode::Failure, ReturnCode::Success(Qos::AtLeastOnce)]);
+        subscriptions.subscribed(&[TopicFilter::new("a/+", Qos::ExactlyOnce)], &[ReturnCode::Success(Qos::ExactlyOnce)]);
+        subscriptions.unsubscribed(&["c".to_owned()]);
+
+        let subscribe = SubscribeData::new(PacketIdentifier(1), vec![TopicFilter::new("a/+", Qos::ExactlyOnce)]);
+        assert_eq!(subscriptions.resubscribe(ProtocolVersion::V311, &mut packet_identifiers), Ok(Some(Packet::Subscribe(subscribe))));
+        // reconnecting again before the SUBACK
+        let subscribe = SubscribeData::new(PacketIdentifier(2), vec![TopicFilter::new("a/+", Qos::ExactlyOnce)]);
+        assert_eq!(subscriptions.resubscribe(ProtocolVersion::V311, &mut packet_identifiers), Ok(Some(Packet::Subscribe(subscribe))));
+        assert_eq!(packet_identifiers.in_use(), 1);
+
+        assert!(!subscriptions.resubscribed(&SubackData::new(PacketIdentifier(1), vec![]), &mut packet_identifiers));
+        assert!(subscriptions.resubscribed(&SubackData::new(PacketIdentifier(2), vec![]), &mut packet_identifiers));
+        assert!(!subscriptions.resubscribed(&SubackData::new(PacketIdentifier(2), vec![]), &mut packet_identifiers));
+        assert_eq!(packet_identifiers.in_use(), 0);
+    }
+}
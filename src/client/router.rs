@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc;
+
+use crate::packet;
+use crate::packet::{PublishData, ReturnCode};
+use crate::topic::{SubscriptionTree, TopicError, ValidTopicFilter};
+
+/// Where the messages of a subscription go instead of to the client's own queue.
+///
+/// Handlers run on whatever drives the client, so a callback should return quickly.
+pub enum Handler {
+    Callback(Box<dyn FnMut(&PublishData) + Send>),
+    Channel(mpsc::Sender<PublishData>),
+    #[cfg(feature = "tokio")]
+    AsyncChannel(tokio::sync::mpsc::UnboundedSender<PublishData>),
+}
+
+impl Handler {
+    pub fn callback<F: FnMut(&PublishData) + Send + 'static>(callback: F) -> Handler {
+        Handler::Callback(Box::new(callback))
+    }
+
+    /// Hand over a message, returns false once the receiving end of a channel is gone.
+    fn handle(&mut self, publish: &PublishData) -> bool {
+        match self {
+            Handler::Callback(callback) => {
+                callback(publish);
+                true
+            },
+            Handler::Channel(sender) => sender.send(publish.clone()).is_ok(),
+            #[cfg(feature = "tokio")]
+            Handler::AsyncChannel(sender) => sender.send(publish.clone()).is_ok(),
+        }
+    }
+}
+
+impl From<mpsc::Sender<PublishData>> for Handler {
+    fn from(sender: mpsc::Sender<PublishData>) -> Handler {
+        Handler::Channel(sender)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl From<tokio::sync::mpsc::UnboundedSender<PublishData>> for Handler {
+    fn from(sender: tokio::sync::mpsc::UnboundedSender<PublishData>) -> Handler {
+        Handler::AsyncChannel(sender)
+    }
+}
+
+impl fmt::Debug for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Handler::Callback(_) => f.write_str("Callback"),
+            Handler::Channel(_) => f.write_str("Channel"),
+            #[cfg(feature = "tokio")]
+            Handler::AsyncChannel(_) => f.write_str("AsyncChannel"),
+        }
+    }
+}
+
+/// the handlers of a SUBSCRIBE, with the filters they are for
+pub(crate) type Handlers = Vec<(ValidTopicFilter, Handler)>;
+
+/// The handlers of the subscriptions that have one, at most one per topic filter.
+#[derive(Debug, Default)]
+pub(crate) struct Router {
+    filters: SubscriptionTree<u64>,
+    handlers: HashMap<u64, (ValidTopicFilter, Handler)>,
+    next_id: u64,
+}
+
+impl Router {
+    /// Register the handlers of a SUBSCRIBE before sending it, since the server may send
+    /// their messages ahead of the SUBACK, replacing those the same filters had.
+    /// Returns the handlers' identifiers, for `subscribed` to drop those the server refused.
+    pub(crate) fn register(&mut self, handlers: Handlers) -> Vec<u64> {
+        handlers.into_iter().map(|(filter, handler)| {
+            for id in self.filters.remove_all(&filter) {
+                self.handlers.remove(&id);
+            }
+            self.next_id += 1;
+            self.filters.insert(&filter, self.next_id);
+            self.handlers.insert(self.next_id, (filter, handler));
+            self.next_id
+        }).collect()
+    }
+
+    /// Drop the handlers of the filters the server refused in its SUBACK.
+    pub(crate) fn subscribed(&mut self, ids: &[u64], return_codes: &[ReturnCode]) {
+        for (&id, return_code) in ids.iter().zip(return_codes) {
+            if let ReturnCode::Success(_) = return_code {
+                continue;
+            }
+            self.remove(id);
+        }
+    }
+
+    /// Drop the handlers of a SUBSCRIBE that failed.
+    pub(crate) fn unregister(&mut self, ids: &[u64]) {
+        for &id in ids {
+            self.remove(id);
+        }
+    }
+
+    pub(crate) fn unsubscribed(&mut self, topic_filters: &[String]) {
+        for filter in topic_filters.iter().filter_map(|filter| ValidTopicFilter::new(filter.as_str()).ok()) {
+            for id in self.filters.remove_all(&filter) {
+                self.handlers.remove(&id);
+            }
+        }
+    }
+
+    /// Hand a message to the handler of every filter it matches, returns false if there was none.
+    pub(crate) fn dispatch(&mut self, publish: &PublishData) -> bool {
+        let ids: Vec<u64> = self.filters.matches(publish.topic_name()).cloned().collect();
+        let mut handled = false;
+        for id in ids {
+            let alive = match self.handlers.get_mut(&id) {
+                Some((_, handler)) => handler.handle(publish),
+                None => continue,
+            };
+            if alive {
+                handled = true;
+            } else {
+                // nobody is listening on the channel anymore
+                self.remove(id);
+            }
+        }
+        handled
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some((filter, _)) = self.handlers.remove(&id) {
+            self.filters.remove(&filter, &id);
+        }
+    }
+}
+
+/// Split subscriptions into the topic filters to subscribe to and the handlers to register for them.
+pub(crate) fn split_handlers(subscriptions: Vec<(packet::TopicFilter, Handler)>) -> Result<(Vec<packet::TopicFilter>, Handlers), TopicError> {
+    let mut topic_filters = Vec::with_capacity(subscriptions.len());
+    let mut handlers = Vec::with_capacity(subscriptions.len());
+    for (topic_filter, handler) in subscriptions {
+        handlers.push((ValidTopicFilter::new(topic_filter.filter())?, handler));
+        topic_filters.push(topic_filter);
+    }
+    Ok((topic_filters, handlers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Qos;
+    use std::sync::{Arc, Mutex};
+
+    fn filter(filter: &str) -> ValidTopicFilter {
+        ValidTopicFilter::new(filter).unwrap()
+    }
+
+    fn message(topic_name: &str) -> PublishData {
+        PublishData::builder(topic_name, "payload").build().unwrap()
+    }
+
+    #[test]
+    fn dispatching_to_overlapping_filters() {
+        let mut router = Router::default();
+        let (sender, receiver) = mpsc::channel();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let handlers = vec![
+            (filter("a/+"), Handler::from(sender)),
+            (filter("a/#"), Handler::callback(move |publish: &PublishData| recorded.lock().unwrap().push(publish.topic_name().to_owned()))),
+            (filter("b"), Handler::callback(|_: &PublishData| panic!("refused by the server"))),
+        ];
+        let ids = router.register(handlers);
+        router.subscribed(&ids, &[ReturnCode::Success(Qos::AtMostOnce), ReturnCode::Success(Qos::AtLeastOnce), ReturnCode::Failure]);
+
+        assert!(router.dispatch(&message("a/b")));
+        assert!(router.dispatch(&message("a/b/c")));
+        assert!(!router.dispatch(&message("b")));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![message("a/b")]);
+        assert_eq!(*calls.lock().unwrap(), vec!["a/b", "a/b/c"]);
+
+        router.unsubscribed(&["a/#".to_owned()]);
+        assert!(router.dispatch(&message("a/c")));
+        assert!(!router.dispatch(&message("a/b/c")));
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn replacing_handlers() {
+        let mut router = Router::default();
+        let (first, first_receiver) = mpsc::channel();
+        let (second, second_receiver) = mpsc::channel();
+        router.register(vec![(filter("a"), first.into())]);
+        // messages may arrive before the SUBACK
+        let ids = router.register(vec![(filter("a"), second.into())]);
+        assert!(router.dispatch(&message("a")));
+        router.subscribed(&ids, &[ReturnCode::Success(Qos::AtMostOnce)]);
+
+        assert!(router.dispatch(&message("a")));
+        assert!(first_receiver.try_recv().is_err());
+        assert_eq!(second_receiver.try_iter().count(), 2);
+
+        // a channel nobody receives from is dropped
+        drop(second_receiver);
+        assert!(!router.dispatch(&message("a")));
+        assert!(router.filters.is_empty());
+
+        let ids = router.register(vec![(filter("b"), Handler::callback(|_: &PublishData| {}))]);
+        router.unregister(&ids);
+        assert!(router.handlers.is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+use std::io;
+
+use crate::packet::*;
+use crate::qos::{Delivery, PacketIdAllocator, QosState};
+use super::{ClientError, SessionStore};
+use super::store::outbound_packet_identifier;
+
+/// The QoS state of a client, saved to its `SessionStore` as it changes, if it has one.
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    qos: QosState,
+    store: Option<Box<dyn SessionStore>>,
+}
+
+impl Session {
+    /// Pick up the session in `store`, whose packet identifiers are then in use.
+    pub(crate) fn restore(mut store: Box<dyn SessionStore>, packet_identifiers: &mut PacketIdAllocator) -> Result<Session, ClientError> {
+        let (outbound, inbound) = store.load().map_err(ClientError::StoreError)?;
+        let mut qos = QosState::new();
+        qos.restore(&outbound, &inbound)?;
+        for packet_identifier in outbound.iter().filter_map(outbound_packet_identifier) {
+            packet_identifiers.reserve(packet_identifier);
+        }
+        Ok(Session { qos, store: Some(store) })
+    }
+
+    pub(crate) fn is_in_flight(&self, packet_identifier: PacketIdentifier) -> bool {
+        self.qos.is_in_flight(packet_identifier)
+    }
+
+    pub(crate) fn publish(&mut self, publish: &PublishData) -> Result<(), ClientError> {
+        self.qos.publish(publish)?;
+        if publish.qos() != Qos::AtMostOnce {
+            self.save(|store| store.store_outbound(&Packet::Publish(publish.clone())))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn puback(&mut self, ack: &AckData) -> Result<(), ClientError> {
+        self.qos.puback(ack)?;
+        self.save(|store| store.remove_outbound(ack.packet_identifier()))
+    }
+
+    pub(crate) fn pubrec(&mut self, ack: &AckData) -> Result<Option<Packet>, ClientError> {
+        let pubrel = self.qos.pubrec(ack)?;
+        match pubrel {
+            Some(ref pubrel) => self.save(|store| store.store_outbound(pubrel))?,
+            None => self.save(|store| store.remove_outbound(ack.packet_identifier()))?,
+        }
+        Ok(pubrel)
+    }
+
+    pub(crate) fn pubcomp(&mut self, ack: &AckData) -> Result<(), ClientError> {
+        self.qos.pubcomp(ack)?;
+        self.save(|store| store.remove_outbound(ack.packet_identifier()))
+    }
+
+    pub(crate) fn retransmissions(&self) -> Vec<Packet> {
+        self.qos.retransmissions()
+    }
+
+    pub(crate) fn receive_publish(&mut self, publish: &PublishData) -> Result<Delivery, ClientError> {
+        let delivery = self.qos.receive_publish(publish)?;
+        if let (Delivery::Deliver(_), Some(packet_identifier)) = (&delivery, publish.packet_identifier()) {
+            if publish.qos() == Qos::ExactlyOnce {
+                self.save(|store| store.store_inbound(packet_identifier))?;
+            }
+        }
+        Ok(delivery)
+    }
+
+    pub(crate) fn pubrel(&mut self, ack: &AckData) -> Result<Packet, ClientError> {
+        let pubcomp = self.qos.pubrel(ack);
+        self.save(|store| store.remove_inbound(ack.packet_identifier()))?;
+        Ok(pubcomp)
+    }
+
+    pub(crate) fn clear_inbound(&mut self) -> Result<(), ClientError> {
+        self.qos.clear_inbound();
+        self.save(|store| store.clear_inbound())
+    }
+
+    fn save<F: FnOnce(&mut dyn SessionStore) -> io::Result<()>>(&mut self, save: F) -> Result<(), ClientError> {
+        match self.store {
+            Some(ref mut store) => save(store.as_mut()).map_err(ClientError::StoreError),
+            None => Ok(()),
+        }
+    }
+}
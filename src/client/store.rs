@@ -0,0 +1,246 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::codec::PacketDecoder;
+use crate::packet::*;
+
+/// Where a client keeps the QoS 1 and 2 message flows it is in the middle of,
+/// so that they survive the process and are picked up again when it restarts.
+///
+/// The client saves each change before sending the packet that follows from it.
+pub trait SessionStore: fmt::Debug + Send {
+    /// Keep an outbound PUBLISH until its flow completes, or the PUBREL taking
+    /// the place of the PUBLISH with the same packet identifier.
+    fn store_outbound(&mut self, packet: &Packet) -> io::Result<()>;
+
+    fn remove_outbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()>;
+
+    /// Keep the packet identifier of an inbound QoS 2 message delivered but not yet released.
+    fn store_inbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()>;
+
+    fn remove_inbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()>;
+
+    /// Forget the inbound packet identifiers, as when the server lost the session.
+    fn clear_inbound(&mut self) -> io::Result<()>;
+
+    /// the outbound packets in the order their flows started, and the inbound packet identifiers
+    fn load(&mut self) -> io::Result<(Vec<Packet>, Vec<PacketIdentifier>)>;
+}
+
+/// A session kept in memory, which survives reconnecting but not restarting.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySessionStore {
+    outbound: Vec<Packet>,
+    inbound: Vec<PacketIdentifier>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore::default()
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn store_outbound(&mut self, packet: &Packet) -> io::Result<()> {
+        let packet_identifier = outbound_packet_identifier(packet)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "only QoS 1 and 2 PUBLISH and PUBREL packets are in flight"))?;
+        match self.outbound.iter_mut().find(|stored| outbound_packet_identifier(stored) == Some(packet_identifier)) {
+            Some(stored) => *stored = packet.clone(),
+            None => self.outbound.push(packet.clone()),
+        }
+        Ok(())
+    }
+
+    fn remove_outbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()> {
+        self.outbound.retain(|stored| outbound_packet_identifier(stored) != Some(packet_identifier));
+        Ok(())
+    }
+
+    fn store_inbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()> {
+        if !self.inbound.contains(&packet_identifier) {
+            self.inbound.push(packet_identifier);
+        }
+        Ok(())
+    }
+
+    fn remove_inbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()> {
+        self.inbound.retain(|&stored| stored != packet_identifier);
+        Ok(())
+    }
+
+    fn clear_inbound(&mut self) -> io::Result<()> {
+        self.inbound.clear();
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<(Vec<Packet>, Vec<PacketIdentifier>)> {
+        Ok((self.outbound.clone(), self.inbound.clone()))
+    }
+}
+
+/// A session kept in a file, rewritten as a whole on every change.
+///
+/// The file holds the protocol level of the session followed by its packets as they go over
+/// the wire: the outbound PUBLISH and PUBREL packets, and a PUBREC for each inbound message.
+/// A new version is written next to it and renamed over it, so a crash leaves one or the other.
+#[derive(Debug)]
+pub struct FileSessionStore {
+    path: PathBuf,
+    session: MemorySessionStore,
+}
+
+impl FileSessionStore {
+    /// Open the session in the file at `path`, starting an empty one if there is no such file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileSessionStore> {
+        let path = path.as_ref().to_owned();
+        let session = match fs::read(&path) {
+            Ok(bytes) => read_session(&bytes)?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => MemorySessionStore::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(FileSessionStore { path, session })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn save(&self) -> io::Result<()> {
+        // MQTT 5 messages carry properties, even when there are none
+        let mqtt_5 = self.session.outbound.iter().any(|packet| match packet {
+            Packet::Publish(publish) => publish.properties().is_some(),
+            _ => false,
+        });
+        let protocol_version = if mqtt_5 { ProtocolVersion::V5 } else { ProtocolVersion::V311 };
+        let mut bytes = vec![protocol_version.level()];
+        for packet in &self.session.outbound {
+            packet.encode(&mut bytes)?;
+        }
+        for &packet_identifier in &self.session.inbound {
+            Packet::Pubrec(AckData::new(packet_identifier)).encode(&mut bytes)?;
+        }
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".new");
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn store_outbound(&mut self, packet: &Packet) -> io::Result<()> {
+        self.session.store_outbound(packet)?;
+        self.save()
+    }
+
+    fn remove_outbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()> {
+        self.session.remove_outbound(packet_identifier)?;
+        self.save()
+    }
+
+    fn store_inbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()> {
+        self.session.store_inbound(packet_identifier)?;
+        self.save()
+    }
+
+    fn remove_inbound(&mut self, packet_identifier: PacketIdentifier) -> io::Result<()> {
+        self.session.remove_inbound(packet_identifier)?;
+        self.save()
+    }
+
+    fn clear_inbound(&mut self) -> io::Result<()> {
+        self.session.clear_inbound()?;
+        self.save()
+    }
+
+    fn load(&mut self) -> io::Result<(Vec<Packet>, Vec<PacketIdentifier>)> {
+        self.session.load()
+    }
+}
+
+pub(crate) fn outbound_packet_identifier(packet: &Packet) -> Option<PacketIdentifier> {
+    match packet {
+        Packet::Publish(publish) => publish.packet_identifier(),
+        Packet::Pubrel(ack) => Some(ack.packet_identifier()),
+        _ => None,
+    }
+}
+
+fn read_session(bytes: &[u8]) -> io::Result<MemorySessionStore> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let (&level, packets) = bytes.split_first().ok_or_else(|| invalid("empty session file".to_owned()))?;
+    let protocol_version = ProtocolVersion::from_level(level).ok_or_else(|| invalid(format!("unknown protocol level {}", level)))?;
+
+    let mut decoder = PacketDecoder::with_context(DecodingContext::new(protocol_version));
+    decoder.feed(packets);
+    let mut session = MemorySessionStore::new();
+    while let Some(packet) = decoder.next_packet().map_err(|err| invalid(err.to_string()))? {
+        match packet {
+            Packet::Pubrec(ack) => session.store_inbound(ack.packet_identifier())?,
+            packet => session.store_outbound(&packet).map_err(|_| invalid(format!("unexpected packet {:?}", packet)))?,
+        }
+    }
+    if decoder.buffered() > 0 {
+        return Err(invalid("truncated session file".to_owned()));
+    }
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn message(packet_identifier: u16) -> Packet {
+        Packet::Publish(PublishData::builder("a/b", "payload").qos(Qos::ExactlyOnce).packet_identifier(PacketIdentifier(packet_identifier)).build().unwrap())
+    }
+
+    fn fill(store: &mut dyn SessionStore) {
+        store.store_outbound(&message(3)).unwrap();
+        store.store_outbound(&message(1)).unwrap();
+        store.store_outbound(&message(2)).unwrap();
+        store.store_outbound(&Packet::Pubrel(AckData::new(PacketIdentifier(1)))).unwrap();
+        store.remove_outbound(PacketIdentifier(3)).unwrap();
+        store.store_inbound(PacketIdentifier(7)).unwrap();
+        store.store_inbound(PacketIdentifier(8)).unwrap();
+        store.remove_inbound(PacketIdentifier(7)).unwrap();
+    }
+
+    fn expected() -> (Vec<Packet>, Vec<PacketIdentifier>) {
+        (vec![Packet::Pubrel(AckData::new(PacketIdentifier(1))), message(2)], vec![PacketIdentifier(8)])
+    }
+
+    #[test]
+    fn keeping_a_session_in_memory() {
+        let mut store = MemorySessionStore::new();
+        fill(&mut store);
+        assert_eq!(store.load().unwrap(), expected());
+        assert!(store.store_outbound(&Packet::Pingreq).is_err());
+
+        store.clear_inbound().unwrap();
+        assert_eq!(store.load().unwrap().1, vec![]);
+    }
+
+    #[test]
+    fn keeping_a_session_in_a_file() {
+        let path = env::temp_dir().join(format!("mqtt-session-{}", process::id()));
+        let mut store = FileSessionStore::open(&path).unwrap();
+        assert_eq!(store.load().unwrap(), (vec![], vec![]));
+        fill(&mut store);
+
+        let mut reopened = FileSessionStore::open(&path).unwrap();
+        assert_eq!(reopened.load().unwrap(), expected());
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.pop();
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(FileSessionStore::open(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use std::io;
+use std::io::Cursor;
+
+use crate::packet::{Decode, DecodingError, DecodingContext, Header, Packet, PacketRef};
+
+/// Incrementally decodes packets from byte chunks as they arrive,
+/// e.g. from a non-blocking socket.
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+    buffer: Vec<u8>,
+    /// bytes at the start of `buffer` lent out by `next_packet_ref`, dropped on the next call
+    consumed: usize,
+    state: DecodingContext,
+}
+
+impl PacketDecoder {
+    pub fn new() -> PacketDecoder {
+        PacketDecoder::default()
+    }
+
+    pub fn with_context(state: DecodingContext) -> PacketDecoder {
+        PacketDecoder { buffer: Vec::new(), consumed: 0, state }
+    }
+
+    /// buffer a chunk of received bytes
+    pub fn feed(&mut self, data: &[u8]) {
+        self.discard_consumed();
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn context(&self) -> &DecodingContext {
+        &self.state
+    }
+
+    /// A decoded CONNECT updates the protocol version automatically,
+    /// clients have to set it themselves.
+    pub fn context_mut(&mut self) -> &mut DecodingContext {
+        &mut self.state
+    }
+
+    /// number of bytes buffered but not yet decoded
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() - self.consumed
+    }
+
+    /// Decode the next packet if a complete frame has been buffered.
+    ///
+    /// Returns `Ok(None)` when more data is needed. A frame that fails to decode
+    /// is discarded, but the connection should generally be closed anyway. A fixed header
+    /// that fails to decode, or is above the maximum packet size, leaves no way to tell
+    /// where the next frame starts, so everything buffered is discarded with it.
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, DecodingError> {
+        let frame_length = match self.next_frame_length()? {
+            Some(frame_length) => frame_length,
+            None => return Ok(None),
+        };
+
+        let result = decode_frame(&self.buffer[..frame_length], &mut self.state);
+        self.buffer.drain(..frame_length);
+        result.map(Some)
+    }
+
+    /// Like `next_packet`, but the topic name and payload of a PUBLISH borrow
+    /// from the decoder's buffer instead of being copied out of it.
+    pub fn next_packet_ref(&mut self) -> Result<Option<PacketRef<'_>>, DecodingError> {
+        let frame_length = match self.next_frame_length()? {
+            Some(frame_length) => frame_length,
+            None => return Ok(None),
+        };
+
+        self.consumed = frame_length;
+        PacketRef::decode(&self.buffer[..frame_length], &mut self.state).map(Some)
+    }
+
+    /// the length of the next buffered frame, clearing the buffer if its header is bad
+    fn next_frame_length(&mut self) -> Result<Option<usize>, DecodingError> {
+        self.discard_consumed();
+        frame_length(&self.buffer, &mut self.state).inspect_err(|_| self.buffer.clear())
+    }
+
+    fn discard_consumed(&mut self) {
+        self.buffer.drain(..self.consumed);
+        self.consumed = 0;
+    }
+}
+
+/// The length of the frame at the start of `buffer`, header included, or `None` if
+/// more data is needed. Frames above the maximum packet size fail before they are buffered.
+pub(crate) fn frame_length(buffer: &[u8], state: &mut DecodingContext) -> Result<Option<usize>, DecodingError> {
+    let mut cursor = Cursor::new(buffer);
+    match Header::decode(&mut cursor, state) {
+        Ok(header) => {
+            let frame_length = cursor.position() as usize + header.remaining_length as usize;
+            if buffer.len() < frame_length {
+                Ok(None)
+            } else {
+                Ok(Some(frame_length))
+            }
+        },
+        Err(DecodingError::IoError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Decode a single complete frame, which in strict mode must not carry trailing data.
+pub(crate) fn decode_frame(frame: &[u8], state: &mut DecodingContext) -> Result<Packet, DecodingError> {
+    Packet::decode(&mut Cursor::new(frame), state).map_err(DecodingError::within_frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Field, ProtocolVersion, Strictness};
+
+    const CONNACK: [u8; 4] = [0x20, 0x02, 0x01, 0x00];
+    const PUBACK: [u8; 4] = [0x40, 0x02, 0x00, 0x07];
+
+    #[test]
+    fn decoding_a_complete_frame() {
+        let mut decoder = PacketDecoder::new();
+        decoder.feed(&CONNACK);
+        match decoder.next_packet().unwrap() {
+            Some(Packet::Connack(data)) => assert!(data.session_present()),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(decoder.next_packet().unwrap().is_none());
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn decoding_byte_by_byte() {
+        let mut decoder = PacketDecoder::new();
+        for byte in &PUBACK[..3] {
+            decoder.feed(&[*byte]);
+            assert!(decoder.next_packet().unwrap().is_none());
+        }
+        decoder.feed(&PUBACK[3..]);
+        match decoder.next_packet().unwrap() {
+            Some(Packet::Puback(data)) => assert_eq!(data.packet_identifier().0, 7),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decoding_several_frames_in_one_chunk() {
+        let mut decoder = PacketDecoder::new();
+        let mut data = CONNACK.to_vec();
+        data.extend_from_slice(&PUBACK);
+        data.extend_from_slice(&[0xd0, 0x00, 0x40]);
+        decoder.feed(&data);
+
+        assert!(matches!(decoder.next_packet().unwrap(), Some(Packet::Connack(_))));
+        assert!(matches!(decoder.next_packet().unwrap(), Some(Packet::Puback(_))));
+        assert!(matches!(decoder.next_packet().unwrap(), Some(Packet::Pingresp)));
+        assert!(decoder.next_packet().unwrap().is_none());
+        assert_eq!(decoder.buffered(), 1);
+    }
+
+    #[test]
+    fn decoding_a_malformed_frame() {
+        let mut decoder = PacketDecoder::new();
+        decoder.feed(&[0x20, 0x02, 0x05, 0x00]);
+        decoder.feed(&PUBACK);
+        assert!(decoder.next_packet().is_err());
+        assert!(matches!(decoder.next_packet().unwrap(), Some(Packet::Puback(_))));
+    }
+
+    #[test]
+    fn decoding_after_a_v5_connect() {
+        let mut decoder = PacketDecoder::new();
+        decoder.feed(&[0x10, 13, 0, 4, b'M', b'Q', b'T', b'T', 5, 0b0000_0010, 0, 10, 0, 0, 0]);
+        assert!(matches!(decoder.next_packet().unwrap(), Some(Packet::Connect(_))));
+
+        // an MQTT 5 PUBLISH has a properties section
+        decoder.feed(&[0x30, 5, 0, 1, b'a', 0, 0xff]);
+        match decoder.next_packet().unwrap() {
+            Some(Packet::Publish(data)) => assert_eq!(data.payload(), &[0xff]),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejecting_trailing_data() {
+        // an MQTT 5 CONNACK with a byte after its properties
+        let frame = [0x20, 0x04, 0x01, 0x00, 0x00, 0xff];
+        let mut decoder = PacketDecoder::with_context(DecodingContext::new(ProtocolVersion::V5));
+        decoder.feed(&frame);
+        let err = decoder.next_packet().unwrap_err();
+        assert!(matches!(err.root_cause(), DecodingError::Malformed(Field::RemainingLength)));
+        assert!(decoder.next_packet_ref().is_ok());
+
+        decoder.feed(&frame);
+        assert!(decoder.next_packet_ref().is_err());
+
+        let context = DecodingContext { strictness: Strictness::Lenient, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut decoder = PacketDecoder::with_context(context);
+        decoder.feed(&frame);
+        assert!(matches!(decoder.next_packet().unwrap(), Some(Packet::Connack(_))));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn decoding_with_a_v5_context() {
+        let mut decoder = PacketDecoder::with_context(DecodingContext::new(ProtocolVersion::V5));
+        decoder.feed(&[0x20, 0x03, 0x00, 0x00, 0x00]);
+        match decoder.next_packet().unwrap() {
+            Some(Packet::Connack(data)) => assert!(data.properties().is_some()),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(decoder.context().protocol_version, ProtocolVersion::V5);
+    }
+
+    #[test]
+    fn decoding_frames_above_the_maximum_packet_size() {
+        let context = DecodingContext { max_packet_size: Some(4), ..DecodingContext::default() };
+        let mut decoder = PacketDecoder::with_context(context);
+        decoder.feed(&PUBACK);
+        assert!(matches!(decoder.next_packet().unwrap(), Some(Packet::Puback(_))));
+
+        // only the header has arrived, but that is enough to know
+        decoder.feed(&[0x30, 0x05]);
+        assert!(matches!(decoder.next_packet(), Err(DecodingError::PacketTooLarge { size: 7, maximum: 4 })));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn decoding_borrowed_packets() {
+        let mut decoder = PacketDecoder::new();
+        decoder.feed(&[0x30, 5, 0, 1, b'a', 0xff, 0xfe]);
+        decoder.feed(&PUBACK);
+        match decoder.next_packet_ref().unwrap() {
+            Some(PacketRef::Publish(publish_ref)) => {
+                assert_eq!(publish_ref.topic_name(), "a");
+                assert_eq!(publish_ref.payload(), &[0xff, 0xfe]);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(decoder.buffered(), 4);
+        match decoder.next_packet_ref().unwrap() {
+            Some(PacketRef::Other(Packet::Puback(data))) => assert_eq!(data.packet_identifier().0, 7),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(decoder.next_packet_ref().unwrap().is_none());
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn decoding_a_malformed_remaining_length() {
+        let mut decoder = PacketDecoder::new();
+        decoder.feed(&[0x30, 0xff, 0xff, 0xff, 0xff]);
+        decoder.feed(&PUBACK);
+        assert!(decoder.next_packet().is_err());
+
+        // the decoder gives up on what it had rather than failing again and again
+        assert_eq!(decoder.buffered(), 0);
+        assert!(decoder.next_packet().unwrap().is_none());
+        decoder.feed(&PUBACK);
+        assert!(matches!(decoder.next_packet_ref().unwrap(), Some(PacketRef::Other(Packet::Puback(_)))));
+
+        decoder.feed(&[0x30, 0xff, 0xff, 0xff, 0xff]);
+        assert!(decoder.next_packet_ref().is_err());
+        assert_eq!(decoder.buffered(), 0);
+    }
+}
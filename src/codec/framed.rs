@@ -0,0 +1,112 @@
+use std::io;
+
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::packet::{DecodingError, DecodingContext, Encode, Packet};
+use super::decoder::{decode_frame, frame_length};
+
+/// A `tokio_util` codec, so a socket wrapped in `Framed` becomes a
+/// `Stream` of packets and a `Sink` for them.
+///
+/// The protocol level used for decoding follows the CONNECT packets that pass
+/// through the codec in either direction.
+#[derive(Debug, Default)]
+pub struct MqttCodec {
+    state: DecodingContext,
+}
+
+impl MqttCodec {
+    pub fn new() -> MqttCodec {
+        MqttCodec::default()
+    }
+
+    pub fn with_context(state: DecodingContext) -> MqttCodec {
+        MqttCodec { state }
+    }
+
+    pub fn context(&self) -> &DecodingContext {
+        &self.state
+    }
+
+    pub fn context_mut(&mut self) -> &mut DecodingContext {
+        &mut self.state
+    }
+}
+
+impl Decoder for MqttCodec {
+    type Item = Packet;
+    type Error = DecodingError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, DecodingError> {
+        let frame_length = match frame_length(src, &mut self.state)? {
+            Some(frame_length) => frame_length,
+            None => return Ok(None),
+        };
+
+        let frame = src.split_to(frame_length);
+        decode_frame(&frame, &mut self.state).map(Some)
+    }
+}
+
+impl Encoder<Packet> for MqttCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> io::Result<()> {
+        if let Packet::Connect(ref data) = packet {
+            if let Some(protocol_version) = data.protocol_version() {
+                self.state.protocol_version = protocol_version;
+            }
+        }
+        packet.encode(&mut dst.writer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{AckData, ConnectData, PacketIdentifier, ProtocolVersion};
+
+    #[test]
+    fn decoding_frames() {
+        let mut codec = MqttCodec::new();
+        let mut buffer = BytesMut::from(&[0x40, 0x02, 0x00][..]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+
+        buffer.extend_from_slice(&[0x07, 0x20, 0x02]);
+        match codec.decode(&mut buffer).unwrap() {
+            Some(Packet::Puback(data)) => assert_eq!(data.packet_identifier(), PacketIdentifier(7)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        assert_eq!(&buffer[..], &[0x20, 0x02]);
+    }
+
+    #[test]
+    fn decoding_malformed_frames() {
+        let mut codec = MqttCodec::new();
+        let mut buffer = BytesMut::from(&[0x20, 0x02, 0x05, 0x00][..]);
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn encoding_packets() {
+        let mut codec = MqttCodec::new();
+        let mut buffer = BytesMut::new();
+        codec.encode(Packet::Puback(AckData::new(PacketIdentifier(7))), &mut buffer).unwrap();
+        assert_eq!(&buffer[..], &[0x40, 0x02, 0x00, 0x07]);
+    }
+
+    #[test]
+    fn decoding_after_encoding_a_v5_connect() {
+        let mut codec = MqttCodec::new();
+        let connect_data = ConnectData::builder().protocol_version(ProtocolVersion::V5).client_id("a").build();
+        codec.encode(Packet::Connect(connect_data), &mut BytesMut::new()).unwrap();
+
+        let mut buffer = BytesMut::from(&[0x20, 0x03, 0x00, 0x00, 0x00][..]);
+        match codec.decode(&mut buffer).unwrap() {
+            Some(Packet::Connack(data)) => assert!(data.properties().is_some()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
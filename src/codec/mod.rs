@@ -0,0 +1,7 @@
+mod decoder;
+pub use self::decoder::*;
+
+#[cfg(feature = "tokio")]
+mod framed;
+#[cfg(feature = "tokio")]
+pub use self::framed::*;
@@ -1,4 +1,27 @@
 extern crate byteorder;
+#[cfg(feature = "tokio")]
+extern crate bytes;
+#[cfg(feature = "tokio")]
+extern crate tokio_util;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tokio")]
+extern crate futures_util;
 
 mod types;
 
+/// MQTT control packets and the traits used to encode and decode them.
+pub mod packet {
+    pub use crate::types::*;
+}
+
+pub mod codec;
+
+/// Validated topic names and filters.
+pub mod topic;
+
+/// Tracking QoS 1 and 2 message flows and their packet identifiers, for clients and servers alike.
+pub mod qos;
+
+/// Clients connecting to a broker.
+pub mod client;
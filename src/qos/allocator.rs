@@ -0,0 +1,147 @@
+use std::fmt;
+
+use crate::packet::PacketIdentifier;
+use super::QosError;
+
+/// Hands out the packet identifiers of SUBSCRIBE, UNSUBSCRIBE and QoS 1 and 2 PUBLISH packets,
+/// never 0 and never one still in use.
+///
+/// Identifiers are handed out in increasing order, wrapping around, so a released identifier
+/// is not reused right away and a late acknowledgement is less likely to be mistaken for a new one.
+#[derive(Clone)]
+pub struct PacketIdAllocator {
+    /// one bit per identifier
+    in_use: Box<[u64; 1024]>,
+    count: usize,
+    last: u16,
+}
+
+impl PacketIdAllocator {
+    pub fn new() -> PacketIdAllocator {
+        PacketIdAllocator { in_use: Box::new([0; 1024]), count: 0, last: 0 }
+    }
+
+    /// the next free identifier, which stays in use until it is released
+    pub fn allocate(&mut self) -> Result<PacketIdentifier, QosError> {
+        if self.count == usize::from(u16::MAX) {
+            return Err(QosError::PacketIdentifiersExhausted);
+        }
+        let mut candidate = self.last;
+        loop {
+            candidate = candidate.checked_add(1).unwrap_or(1);
+            if !self.is_in_use(PacketIdentifier(candidate)) {
+                break;
+            }
+        }
+        self.last = candidate;
+        self.reserve(PacketIdentifier(candidate));
+        Ok(PacketIdentifier(candidate))
+    }
+
+    /// Mark an identifier as in use, e.g. one restored with a session.
+    /// Returns false if it already was, or is 0.
+    pub fn reserve(&mut self, packet_identifier: PacketIdentifier) -> bool {
+        if packet_identifier.0 == 0 || self.is_in_use(packet_identifier) {
+            return false;
+        }
+        let (word, bit) = position(packet_identifier);
+        self.in_use[word] |= bit;
+        self.count += 1;
+        true
+    }
+
+    /// Make an identifier available again once its flow is complete.
+    /// Returns false if it was not in use.
+    pub fn release(&mut self, packet_identifier: PacketIdentifier) -> bool {
+        if !self.is_in_use(packet_identifier) {
+            return false;
+        }
+        let (word, bit) = position(packet_identifier);
+        self.in_use[word] &= !bit;
+        self.count -= 1;
+        true
+    }
+
+    pub fn is_in_use(&self, packet_identifier: PacketIdentifier) -> bool {
+        let (word, bit) = position(packet_identifier);
+        self.in_use[word] & bit != 0
+    }
+
+    /// the number of identifiers in use
+    pub fn in_use(&self) -> usize {
+        self.count
+    }
+
+    /// Release every identifier, as when a clean session starts.
+    pub fn clear(&mut self) {
+        *self.in_use = [0; 1024];
+        self.count = 0;
+    }
+}
+
+impl Default for PacketIdAllocator {
+    fn default() -> PacketIdAllocator {
+        PacketIdAllocator::new()
+    }
+}
+
+impl fmt::Debug for PacketIdAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PacketIdAllocator")
+            .field("in_use", &self.count)
+            .field("last", &self.last)
+            .finish()
+    }
+}
+
+fn position(packet_identifier: PacketIdentifier) -> (usize, u64) {
+    let index = usize::from(packet_identifier.0);
+    (index / 64, 1 << (index % 64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocating_in_order() {
+        let mut allocator = PacketIdAllocator::new();
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(1)));
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(2)));
+        assert!(allocator.release(PacketIdentifier(1)));
+        assert!(!allocator.release(PacketIdentifier(1)));
+        // released identifiers wait their turn
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(3)));
+        assert_eq!(allocator.in_use(), 2);
+    }
+
+    #[test]
+    fn skipping_identifiers_in_use_and_zero() {
+        let mut allocator = PacketIdAllocator::new();
+        assert!(allocator.reserve(PacketIdentifier(2)));
+        assert!(!allocator.reserve(PacketIdentifier(2)));
+        assert!(!allocator.reserve(PacketIdentifier(0)));
+        assert!(allocator.reserve(PacketIdentifier(u16::MAX)));
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(1)));
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(3)));
+
+        let mut allocator = PacketIdAllocator { last: u16::MAX - 1, ..PacketIdAllocator::new() };
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(u16::MAX)));
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(1)));
+    }
+
+    #[test]
+    fn running_out_of_identifiers() {
+        let mut allocator = PacketIdAllocator::new();
+        for _ in 0..u16::MAX {
+            allocator.allocate().unwrap();
+        }
+        assert_eq!(allocator.allocate(), Err(QosError::PacketIdentifiersExhausted));
+
+        assert!(allocator.release(PacketIdentifier(40_000)));
+        assert_eq!(allocator.allocate(), Ok(PacketIdentifier(40_000)));
+        allocator.clear();
+        assert_eq!(allocator.in_use(), 0);
+        assert!(!allocator.is_in_use(PacketIdentifier(40_000)));
+    }
+}
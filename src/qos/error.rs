@@ -0,0 +1,30 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::packet::PacketIdentifier;
+
+/// A message flow the peer or the caller got wrong.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QosError {
+    /// a QoS 1 or 2 message without a packet identifier
+    MissingPacketIdentifier,
+    /// an outbound message reusing the identifier of one still in flight
+    PacketIdentifierInUse(PacketIdentifier),
+    /// all 65,535 packet identifiers are in use
+    PacketIdentifiersExhausted,
+    /// an acknowledgement for no message in flight, or for one at a different step of its flow
+    UnexpectedAck(PacketIdentifier),
+}
+
+impl fmt::Display for QosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QosError::MissingPacketIdentifier => write!(f, "QoS 1 or 2 message without a packet identifier"),
+            QosError::PacketIdentifierInUse(packet_identifier) => write!(f, "packet identifier {} is still in use", packet_identifier.0),
+            QosError::PacketIdentifiersExhausted => write!(f, "all packet identifiers are in use"),
+            QosError::UnexpectedAck(packet_identifier) => write!(f, "unexpected acknowledgement for packet identifier {}", packet_identifier.0),
+        }
+    }
+}
+
+impl Error for QosError {}
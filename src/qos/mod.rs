@@ -0,0 +1,7 @@
+mod error;
+mod state;
+mod allocator;
+
+pub use self::error::*;
+pub use self::state::*;
+pub use self::allocator::*;
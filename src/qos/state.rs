@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::packet::{AckData, Packet, PacketIdentifier, PublishData, Qos};
+use super::QosError;
+
+/// The QoS 1 and 2 message flows of one session, in both directions, see section 4.3.
+///
+/// It does no I/O: the caller reports what it sends and receives, and sends
+/// the packets it gets back. Clients and servers use it the same way.
+#[derive(Debug, Clone, Default)]
+pub struct QosState {
+    /// outbound messages by packet identifier, with the order they were sent in
+    outbound: HashMap<PacketIdentifier, (u64, Outbound)>,
+    sent: u64,
+    /// inbound QoS 2 messages delivered but not yet released by a PUBREL
+    inbound: HashSet<PacketIdentifier>,
+}
+
+#[derive(Debug, Clone)]
+enum Outbound {
+    /// waiting for a PUBACK or a PUBREC
+    Published(PublishData),
+    /// a QoS 2 message the receiver has, waiting for the PUBCOMP
+    Released,
+}
+
+/// What to do with an incoming message, see `QosState::receive_publish`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// hand the message to the application, then send the acknowledgement if there is one
+    Deliver(Option<Packet>),
+    /// a QoS 2 message that was already delivered, only send the acknowledgement again
+    Duplicate(Packet),
+}
+
+impl QosState {
+    pub fn new() -> QosState {
+        QosState::default()
+    }
+
+    /// the number of outbound messages not yet completely acknowledged
+    pub fn in_flight(&self) -> usize {
+        self.outbound.len()
+    }
+
+    /// whether `packet_identifier` belongs to an outbound message in flight
+    pub fn is_in_flight(&self, packet_identifier: PacketIdentifier) -> bool {
+        self.outbound.contains_key(&packet_identifier)
+    }
+
+    /// Track an outbound message about to be sent. QoS 0 messages need no tracking.
+    pub fn publish(&mut self, publish: &PublishData) -> Result<(), QosError> {
+        if publish.qos() == Qos::AtMostOnce {
+            return Ok(());
+        }
+        let packet_identifier = publish.packet_identifier().ok_or(QosError::MissingPacketIdentifier)?;
+        if self.is_in_flight(packet_identifier) {
+            return Err(QosError::PacketIdentifierInUse(packet_identifier));
+        }
+        self.sent += 1;
+        self.outbound.insert(packet_identifier, (self.sent, Outbound::Published(publish.clone())));
+        Ok(())
+    }
+
+    /// A PUBACK completes a QoS 1 message, which is returned.
+    pub fn puback(&mut self, ack: &AckData) -> Result<PublishData, QosError> {
+        let packet_identifier = ack.packet_identifier();
+        match self.outbound.remove(&packet_identifier) {
+            Some((_, Outbound::Published(publish))) if publish.qos() == Qos::AtLeastOnce => Ok(publish),
+            Some(outbound) => {
+                self.outbound.insert(packet_identifier, outbound);
+                Err(QosError::UnexpectedAck(packet_identifier))
+            },
+            None => Err(QosError::UnexpectedAck(packet_identifier)),
+        }
+    }
+
+    /// A PUBREC means the receiver has a QoS 2 message, returns the PUBREL to send next.
+    ///
+    /// An MQTT 5 PUBREC with a failure reason code ends the flow instead, and there is nothing to send.
+    pub fn pubrec(&mut self, ack: &AckData) -> Result<Option<Packet>, QosError> {
+        let packet_identifier = ack.packet_identifier();
+        let pubrel = Packet::Pubrel(AckData::new(packet_identifier));
+        match self.outbound.remove(&packet_identifier) {
+            Some((_, Outbound::Published(ref publish))) if publish.qos() == Qos::ExactlyOnce && ack.reason_code().is_error() => Ok(None),
+            Some((sent, Outbound::Published(ref publish))) if publish.qos() == Qos::ExactlyOnce => {
+                self.outbound.insert(packet_identifier, (sent, Outbound::Released));
+                Ok(Some(pubrel))
+            },
+            // the PUBREL got lost, so the receiver sent the PUBREC again
+            Some(outbound @ (_, Outbound::Released)) => {
+                self.outbound.insert(packet_identifier, outbound);
+                Ok(Some(pubrel))
+            },
+            Some(outbound) => {
+                self.outbound.insert(packet_identifier, outbound);
+                Err(QosError::UnexpectedAck(packet_identifier))
+            },
+            None => Err(QosError::UnexpectedAck(packet_identifier)),
+        }
+    }
+
+    /// A PUBCOMP completes a QoS 2 message.
+    pub fn pubcomp(&mut self, ack: &AckData) -> Result<(), QosError> {
+        let packet_identifier = ack.packet_identifier();
+        match self.outbound.get(&packet_identifier) {
+            Some((_, Outbound::Released)) => {
+                self.outbound.remove(&packet_identifier);
+                Ok(())
+            },
+            _ => Err(QosError::UnexpectedAck(packet_identifier)),
+        }
+    }
+
+    /// The packets to resend in order after reconnecting to a session the peer kept:
+    /// unacknowledged messages flagged as redeliveries and PUBRELs not yet completed.
+    pub fn retransmissions(&self) -> Vec<Packet> {
+        let mut outbound: Vec<_> = self.outbound.iter().collect();
+        outbound.sort_by_key(|(_, (sent, _))| *sent);
+        outbound.into_iter().map(|(&packet_identifier, (_, outbound))| match outbound {
+            Outbound::Published(publish) => Packet::Publish(publish.clone().redelivery()),
+            Outbound::Released => Packet::Pubrel(AckData::new(packet_identifier)),
+        }).collect()
+    }
+
+    /// Pick up where an earlier session left off, from its outbound PUBLISH and PUBREL packets
+    /// in the order they were sent and the inbound messages it had not seen released.
+    pub fn restore(&mut self, outbound: &[Packet], inbound: &[PacketIdentifier]) -> Result<(), QosError> {
+        for packet in outbound {
+            match packet {
+                Packet::Publish(publish) => self.publish(publish)?,
+                Packet::Pubrel(ack) => {
+                    let packet_identifier = ack.packet_identifier();
+                    if self.is_in_flight(packet_identifier) {
+                        return Err(QosError::PacketIdentifierInUse(packet_identifier));
+                    }
+                    self.sent += 1;
+                    self.outbound.insert(packet_identifier, (self.sent, Outbound::Released));
+                },
+                // nothing else is ever in flight
+                _ => {},
+            }
+        }
+        self.inbound.extend(inbound);
+        Ok(())
+    }
+
+    /// Decide what to do with an incoming message.
+    ///
+    /// A QoS 2 message is delivered once, however often the sender repeats it
+    /// before releasing its packet identifier with a PUBREL.
+    pub fn receive_publish(&mut self, publish: &PublishData) -> Result<Delivery, QosError> {
+        let packet_identifier = match publish.qos() {
+            Qos::AtMostOnce => return Ok(Delivery::Deliver(None)),
+            _ => publish.packet_identifier().ok_or(QosError::MissingPacketIdentifier)?,
+        };
+        if publish.qos() == Qos::AtLeastOnce {
+            return Ok(Delivery::Deliver(Some(Packet::Puback(AckData::new(packet_identifier)))));
+        }
+
+        let pubrec = Packet::Pubrec(AckData::new(packet_identifier));
+        if self.inbound.insert(packet_identifier) {
+            Ok(Delivery::Deliver(Some(pubrec)))
+        } else {
+            Ok(Delivery::Duplicate(pubrec))
+        }
+    }
+
+    /// A PUBREL releases the identifier of an inbound QoS 2 message, returns the PUBCOMP to send.
+    ///
+    /// It is answered even for an unknown identifier, since it may be a repeat
+    /// of a PUBREL whose PUBCOMP got lost.
+    pub fn pubrel(&mut self, ack: &AckData) -> Packet {
+        self.inbound.remove(&ack.packet_identifier());
+        Packet::Pubcomp(AckData::new(ack.packet_identifier()))
+    }
+
+    /// Forget the inbound messages waiting for a PUBREL, as when the peer lost the session
+    /// while the outbound messages are still to be resent.
+    pub fn clear_inbound(&mut self) {
+        self.inbound.clear();
+    }
+
+    /// Forget everything, as when either side starts a clean session.
+    pub fn clear(&mut self) {
+        self.outbound.clear();
+        self.inbound.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Properties, ReasonCode};
+
+    fn message(qos: Qos, packet_identifier: u16) -> PublishData {
+        PublishData::builder("a/b", "payload").qos(qos).packet_identifier(PacketIdentifier(packet_identifier)).build().unwrap()
+    }
+
+    fn ack(packet_identifier: u16) -> AckData {
+        AckData::new(PacketIdentifier(packet_identifier))
+    }
+
+    #[test]
+    fn outbound_qos_1() {
+        let mut state = QosState::new();
+        state.publish(&message(Qos::AtLeastOnce, 1)).unwrap();
+        assert_eq!(state.publish(&message(Qos::ExactlyOnce, 1)), Err(QosError::PacketIdentifierInUse(PacketIdentifier(1))));
+        assert_eq!(state.pubrec(&ack(1)), Err(QosError::UnexpectedAck(PacketIdentifier(1))));
+
+        assert_eq!(state.puback(&ack(1)).unwrap(), message(Qos::AtLeastOnce, 1));
+        assert_eq!(state.in_flight(), 0);
+        assert!(state.puback(&ack(1)).is_err());
+    }
+
+    #[test]
+    fn outbound_qos_2() {
+        let mut state = QosState::new();
+        state.publish(&message(Qos::ExactlyOnce, 2)).unwrap();
+        assert!(state.puback(&ack(2)).is_err());
+        assert!(state.pubcomp(&ack(2)).is_err());
+        assert!(state.is_in_flight(PacketIdentifier(2)));
+
+        assert_eq!(state.pubrec(&ack(2)), Ok(Some(Packet::Pubrel(ack(2)))));
+        assert_eq!(state.pubrec(&ack(2)), Ok(Some(Packet::Pubrel(ack(2)))));
+        state.pubcomp(&ack(2)).unwrap();
+        assert!(!state.is_in_flight(PacketIdentifier(2)));
+
+        state.publish(&message(Qos::ExactlyOnce, 3)).unwrap();
+        let refused = AckData::with_reason(PacketIdentifier(3), ReasonCode::QUOTA_EXCEEDED, Properties::new());
+        assert_eq!(state.pubrec(&refused), Ok(None));
+        assert_eq!(state.in_flight(), 0);
+    }
+
+    #[test]
+    fn outbound_messages_need_packet_identifiers() {
+        let mut state = QosState::new();
+        state.publish(&PublishData::builder("a", "b").build().unwrap()).unwrap();
+        let publish = PublishData::builder("a", "b").qos(Qos::AtLeastOnce).build_unchecked();
+        assert_eq!(state.publish(&publish), Err(QosError::MissingPacketIdentifier));
+        assert_eq!(state.in_flight(), 0);
+    }
+
+    #[test]
+    fn retransmitting_in_order() {
+        let mut state = QosState::new();
+        for &(qos, packet_identifier) in &[(Qos::ExactlyOnce, 9), (Qos::AtLeastOnce, 4), (Qos::ExactlyOnce, 7), (Qos::AtLeastOnce, 1)] {
+            state.publish(&message(qos, packet_identifier)).unwrap();
+        }
+        state.pubrec(&ack(9)).unwrap();
+        state.puback(&ack(1)).unwrap();
+
+        assert_eq!(state.retransmissions(), vec![
+            Packet::Pubrel(ack(9)),
+            Packet::Publish(message(Qos::AtLeastOnce, 4).redelivery()),
+            Packet::Publish(message(Qos::ExactlyOnce, 7).redelivery()),
+        ]);
+    }
+
+    #[test]
+    fn restoring() {
+        let outbound = vec![Packet::Pubrel(ack(9)), Packet::Publish(message(Qos::AtLeastOnce, 4))];
+        let mut state = QosState::new();
+        state.restore(&outbound, &[PacketIdentifier(2)]).unwrap();
+        assert_eq!(state.retransmissions(), vec![Packet::Pubrel(ack(9)), Packet::Publish(message(Qos::AtLeastOnce, 4).redelivery())]);
+        assert_eq!(state.receive_publish(&message(Qos::ExactlyOnce, 2)), Ok(Delivery::Duplicate(Packet::Pubrec(ack(2)))));
+        state.pubcomp(&ack(9)).unwrap();
+
+        assert_eq!(state.restore(&outbound, &[]), Err(QosError::PacketIdentifierInUse(PacketIdentifier(4))));
+    }
+
+    #[test]
+    fn inbound_messages() {
+        let mut state = QosState::new();
+        let publish = PublishData::builder("a", "b").build().unwrap();
+        assert_eq!(state.receive_publish(&publish), Ok(Delivery::Deliver(None)));
+        assert_eq!(state.receive_publish(&message(Qos::AtLeastOnce, 1)), Ok(Delivery::Deliver(Some(Packet::Puback(ack(1))))));
+        assert_eq!(state.receive_publish(&message(Qos::AtLeastOnce, 1)), Ok(Delivery::Deliver(Some(Packet::Puback(ack(1))))));
+
+        assert_eq!(state.receive_publish(&message(Qos::ExactlyOnce, 1)), Ok(Delivery::Deliver(Some(Packet::Pubrec(ack(1))))));
+        let redelivery = message(Qos::ExactlyOnce, 1).redelivery();
+        assert_eq!(state.receive_publish(&redelivery), Ok(Delivery::Duplicate(Packet::Pubrec(ack(1)))));
+        assert_eq!(state.pubrel(&ack(1)), Packet::Pubcomp(ack(1)));
+        assert_eq!(state.pubrel(&ack(1)), Packet::Pubcomp(ack(1)));
+
+        // once released the identifier starts a new message
+        assert_eq!(state.receive_publish(&message(Qos::ExactlyOnce, 1)), Ok(Delivery::Deliver(Some(Packet::Pubrec(ack(1))))));
+
+        // a peer that lost the session sends it as a new message too
+        state.clear_inbound();
+        assert_eq!(state.receive_publish(&message(Qos::ExactlyOnce, 1)), Ok(Delivery::Deliver(Some(Packet::Pubrec(ack(1))))));
+    }
+}
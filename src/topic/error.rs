@@ -0,0 +1,43 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a string is not a valid topic name or topic filter, see section 4.7 of the specification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TopicError {
+    /// topic names and filters are at least one character long
+    Empty,
+    /// longer than the 65,535 bytes a string can hold
+    TooLong,
+    /// contains U+0000
+    NullCharacter,
+    /// a `+` or `#` in a topic name
+    WildcardInTopicName,
+    /// a `+` that does not take up a whole level
+    MisplacedSingleLevelWildcard,
+    /// a `#` that is not a whole level or not the last one
+    MisplacedMultiLevelWildcard,
+}
+
+impl fmt::Display for TopicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            TopicError::Empty => "empty topic",
+            TopicError::TooLong => "topic longer than 65,535 bytes",
+            TopicError::NullCharacter => "topic contains a null character",
+            TopicError::WildcardInTopicName => "wildcard in a topic name",
+            TopicError::MisplacedSingleLevelWildcard => "`+` does not take up a whole level",
+            TopicError::MisplacedMultiLevelWildcard => "`#` is not the whole last level",
+        };
+        f.write_str(description)
+    }
+}
+
+impl Error for TopicError {}
+
+/// the rules topic names and filters share
+pub(crate) fn validate_topic(topic: &str) -> Result<(), TopicError> {
+    if topic.is_empty() { return Err(TopicError::Empty) };
+    if topic.len() > usize::from(u16::MAX) { return Err(TopicError::TooLong) };
+    if topic.contains('\0') { return Err(TopicError::NullCharacter) };
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::validate_topic;
+use super::matching::topic_matches;
+use super::TopicError;
+
+/// A topic filter subscriptions are made with, checked against the rules of the specification.
+/// It may have `+` and `#` wildcards.
+///
+/// Not to be confused with `packet::TopicFilter`, the unchecked filter of a SUBSCRIBE along with
+/// its subscription options, which decoding and the clients validate by the same rules.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ValidTopicFilter(String);
+
+impl ValidTopicFilter {
+    pub fn new<S: Into<String>>(filter: S) -> Result<ValidTopicFilter, TopicError> {
+        let filter = filter.into();
+        validate_topic_filter(&filter)?;
+        Ok(ValidTopicFilter(filter))
+    }
+
+    pub fn as_str(&self) -> &str { &self.0 }
+
+    /// the parts of the filter between the `/` separators
+    pub fn levels(&self) -> ::std::str::Split<'_, char> {
+        self.0.split('/')
+    }
+
+    /// Whether messages published to `topic_name` are delivered to subscriptions with this filter.
+    pub fn matches<N: AsRef<str>>(&self, topic_name: N) -> bool {
+        topic_matches(&self.0, topic_name.as_ref())
+    }
+
+    pub fn has_wildcards(&self) -> bool {
+        self.0.contains(['+', '#'])
+    }
+}
+
+pub(crate) fn validate_topic_filter(filter: &str) -> Result<(), TopicError> {
+    validate_topic(filter)?;
+    let mut levels = filter.split('/').peekable();
+    while let Some(level) = levels.next() {
+        if level.contains('#') && (level != "#" || levels.peek().is_some()) { return Err(TopicError::MisplacedMultiLevelWildcard) };
+        if level.contains('+') && level != "+" { return Err(TopicError::MisplacedSingleLevelWildcard) };
+    }
+    Ok(())
+}
+
+impl FromStr for ValidTopicFilter {
+    type Err = TopicError;
+
+    fn from_str(filter: &str) -> Result<ValidTopicFilter, TopicError> {
+        ValidTopicFilter::new(filter)
+    }
+}
+
+impl AsRef<str> for ValidTopicFilter {
+    fn as_ref(&self) -> &str { &self.0 }
+}
+
+impl fmt::Display for ValidTopicFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<ValidTopicFilter> for String {
+    fn from(filter: ValidTopicFilter) -> String { filter.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topic::TopicName;
+
+    #[test]
+    fn validating_topic_filters() {
+        for filter in &["a/b", "#", "+", "a/#", "a/+/b", "+/+", "/+", "$SYS/#", "a//b"] {
+            assert!(ValidTopicFilter::new(*filter).is_ok(), "{} is valid", filter);
+        }
+
+        assert_eq!(ValidTopicFilter::new(""), Err(TopicError::Empty));
+        assert_eq!(ValidTopicFilter::new("a/#/b"), Err(TopicError::MisplacedMultiLevelWildcard));
+        assert_eq!(ValidTopicFilter::new("a#"), Err(TopicError::MisplacedMultiLevelWildcard));
+        assert_eq!(ValidTopicFilter::new("a/b#"), Err(TopicError::MisplacedMultiLevelWildcard));
+        assert_eq!(ValidTopicFilter::new("a+"), Err(TopicError::MisplacedSingleLevelWildcard));
+        assert_eq!(ValidTopicFilter::new("a/+b/c"), Err(TopicError::MisplacedSingleLevelWildcard));
+        assert_eq!(ValidTopicFilter::new("a/\0"), Err(TopicError::NullCharacter));
+    }
+
+    #[test]
+    fn matching_topic_names() {
+        let filter = ValidTopicFilter::new("a/+/c").unwrap();
+        assert!(filter.matches("a/b/c"));
+        assert!(filter.matches(TopicName::new("a/d/c").unwrap()));
+        assert!(!filter.matches("a/b/d"));
+    }
+
+    #[test]
+    fn finding_wildcards() {
+        assert!(!ValidTopicFilter::new("a/b").unwrap().has_wildcards());
+        assert!(ValidTopicFilter::new("a/+").unwrap().has_wildcards());
+        assert!(ValidTopicFilter::new("#").unwrap().has_wildcards());
+    }
+}
@@ -0,0 +1,64 @@
+/// Whether `topic_name` matches `filter`, which is taken to be valid.
+///
+/// Wildcards in the first level of a filter do not match topic names starting with `$`,
+/// which servers use for their own topics such as `$SYS/...`.
+pub(crate) fn topic_matches(filter: &str, topic_name: &str) -> bool {
+    if topic_name.starts_with('$') && filter.starts_with(['+', '#']) { return false };
+
+    let mut name_levels = topic_name.split('/');
+    for filter_level in filter.split('/') {
+        // `#` also matches the parent level, "a/#" matches "a"
+        if filter_level == "#" { return true };
+        match name_levels.next() {
+            Some(name_level) if filter_level == "+" || filter_level == name_level => {},
+            _ => return false,
+        }
+    }
+    name_levels.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_multi_level_wildcards() {
+        assert!(topic_matches("sport/tennis/player1/#", "sport/tennis/player1"));
+        assert!(topic_matches("sport/tennis/player1/#", "sport/tennis/player1/ranking"));
+        assert!(topic_matches("sport/tennis/player1/#", "sport/tennis/player1/score/wimbledon"));
+        assert!(topic_matches("sport/#", "sport"));
+        assert!(topic_matches("#", "sport/tennis"));
+        assert!(topic_matches("#", "/"));
+        assert!(!topic_matches("sport/tennis/#", "sport/football"));
+    }
+
+    #[test]
+    fn matching_single_level_wildcards() {
+        assert!(topic_matches("sport/tennis/+", "sport/tennis/player1"));
+        assert!(topic_matches("sport/tennis/+", "sport/tennis/"));
+        assert!(!topic_matches("sport/tennis/+", "sport/tennis/player1/ranking"));
+        assert!(!topic_matches("sport/+", "sport"));
+        assert!(topic_matches("sport/+", "sport/"));
+        assert!(topic_matches("+/+", "/finance"));
+        assert!(topic_matches("/+", "/finance"));
+        assert!(!topic_matches("+", "/finance"));
+        assert!(topic_matches("+/tennis/#", "sport/tennis/player1"));
+    }
+
+    #[test]
+    fn matching_without_wildcards() {
+        assert!(topic_matches("a/b", "a/b"));
+        assert!(!topic_matches("a/b", "a/b/"));
+        assert!(!topic_matches("a/b", "a"));
+        assert!(!topic_matches("a/b", "A/b"));
+    }
+
+    #[test]
+    fn matching_topics_starting_with_a_dollar() {
+        assert!(!topic_matches("#", "$SYS/uptime"));
+        assert!(!topic_matches("+/uptime", "$SYS/uptime"));
+        assert!(topic_matches("$SYS/#", "$SYS/uptime"));
+        assert!(topic_matches("$SYS/+", "$SYS/uptime"));
+        assert!(topic_matches("a/+", "a/$b"));
+    }
+}
@@ -0,0 +1,19 @@
+//! Topic names and topic filters, checked against the rules of the specification.
+//!
+//! `TopicName` is the validated topic name. The validated topic filter is `ValidTopicFilter`
+//! rather than `TopicFilter`, because `packet::TopicFilter` already names a filter of a SUBSCRIBE
+//! along with its subscription options, and its infallible constructors are public API.
+//! That one stays unchecked until it is decoded or sent: the decoders and the clients run it
+//! through the same validation as `ValidTopicFilter::new`.
+
+mod error;
+mod name;
+mod filter;
+mod matching;
+mod tree;
+
+pub use self::error::*;
+pub use self::name::*;
+pub use self::filter::*;
+pub use self::tree::*;
+pub(crate) use self::matching::*;
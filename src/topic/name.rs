@@ -0,0 +1,78 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::validate_topic;
+use super::TopicError;
+
+/// A topic name messages are published to, which has no wildcards.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TopicName(String);
+
+impl TopicName {
+    pub fn new<S: Into<String>>(name: S) -> Result<TopicName, TopicError> {
+        let name = name.into();
+        validate_topic_name(&name)?;
+        Ok(TopicName(name))
+    }
+
+    pub fn as_str(&self) -> &str { &self.0 }
+
+    /// the parts of the name between the `/` separators
+    pub fn levels(&self) -> ::std::str::Split<'_, char> {
+        self.0.split('/')
+    }
+}
+
+pub(crate) fn validate_topic_name(name: &str) -> Result<(), TopicError> {
+    validate_topic(name)?;
+    if name.contains(['+', '#']) { return Err(TopicError::WildcardInTopicName) };
+    Ok(())
+}
+
+impl FromStr for TopicName {
+    type Err = TopicError;
+
+    fn from_str(name: &str) -> Result<TopicName, TopicError> {
+        TopicName::new(name)
+    }
+}
+
+impl AsRef<str> for TopicName {
+    fn as_ref(&self) -> &str { &self.0 }
+}
+
+impl fmt::Display for TopicName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<TopicName> for String {
+    fn from(name: TopicName) -> String { name.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validating_topic_names() {
+        assert!(TopicName::new("a/b").is_ok());
+        assert!(TopicName::new("/").is_ok());
+        assert!(TopicName::new("a//b ").is_ok());
+        assert!(TopicName::new("$SYS/uptime").is_ok());
+
+        assert_eq!(TopicName::new(""), Err(TopicError::Empty));
+        assert_eq!(TopicName::new("a/+"), Err(TopicError::WildcardInTopicName));
+        assert_eq!(TopicName::new("a/#"), Err(TopicError::WildcardInTopicName));
+        assert_eq!(TopicName::new("a/b+c"), Err(TopicError::WildcardInTopicName));
+        assert_eq!(TopicName::new("a\0b"), Err(TopicError::NullCharacter));
+        assert_eq!(TopicName::new("a".repeat(65_536)), Err(TopicError::TooLong));
+    }
+
+    #[test]
+    fn splitting_topic_names_into_levels() {
+        let name: TopicName = "/a//b".parse().unwrap();
+        assert_eq!(name.levels().collect::<Vec<_>>(), vec!["", "a", "", "b"]);
+    }
+}
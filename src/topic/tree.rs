@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::slice;
+
+use super::ValidTopicFilter;
+
+/// Values attached to topic filters, stored by topic level so that finding
+/// the filters a topic name matches does not have to look at every one of them.
+#[derive(Debug, Clone)]
+pub struct SubscriptionTree<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    /// keyed by level, `+` and `#` included
+    children: HashMap<String, Node<T>>,
+    /// the values of filters ending at this level
+    values: Vec<T>,
+}
+
+impl<T> Node<T> {
+    fn new() -> Node<T> {
+        Node { children: HashMap::new(), values: Vec::new() }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.children.is_empty()
+    }
+
+    fn find(&self, filter: &ValidTopicFilter) -> Option<&Node<T>> {
+        filter.levels().try_fold(self, |node, level| node.children.get(level))
+    }
+
+    /// Apply `remove` to the values of the node at `levels`, then drop the nodes left empty.
+    fn remove<'l, L, F>(&mut self, mut levels: L, remove: F) -> Vec<T>
+        where L: Iterator<Item = &'l str>, F: FnOnce(&mut Vec<T>) -> Vec<T> {
+        let level = match levels.next() {
+            Some(level) => level,
+            None => return remove(&mut self.values),
+        };
+        let (removed, child_is_empty) = match self.children.get_mut(level) {
+            Some(child) => {
+                let removed = child.remove(levels, remove);
+                (removed, child.is_empty())
+            },
+            None => return Vec::new(),
+        };
+        if child_is_empty {
+            self.children.remove(level);
+        }
+        removed
+    }
+}
+
+impl<T> SubscriptionTree<T> {
+    pub fn new() -> SubscriptionTree<T> {
+        SubscriptionTree { root: Node::new(), len: 0 }
+    }
+
+    /// the number of values in the tree
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Attach `value` to `filter`, a filter can have any number of values.
+    pub fn insert(&mut self, filter: &ValidTopicFilter, value: T) {
+        let node = filter.levels().fold(&mut self.root, |node, level| {
+            node.children.entry(level.to_owned()).or_insert_with(Node::new)
+        });
+        node.values.push(value);
+        self.len += 1;
+    }
+
+    /// the values attached to exactly `filter`
+    pub fn get(&self, filter: &ValidTopicFilter) -> &[T] {
+        self.root.find(filter).map_or(&[], |node| &node.values)
+    }
+
+    /// Remove `filter` along with all of its values.
+    pub fn remove_all(&mut self, filter: &ValidTopicFilter) -> Vec<T> {
+        let removed = self.root.remove(filter.levels(), std::mem::take);
+        self.len -= removed.len();
+        removed
+    }
+
+    /// Detach the values matching `predicate` from `filter`.
+    pub fn remove_where<F>(&mut self, filter: &ValidTopicFilter, mut predicate: F) -> Vec<T>
+        where F: FnMut(&T) -> bool {
+        let removed = self.root.remove(filter.levels(), |values| {
+            let (removed, kept) = values.drain(..).partition(|value| predicate(value));
+            *values = kept;
+            removed
+        });
+        self.len -= removed.len();
+        removed
+    }
+
+    /// Detach `value` from `filter`, returns whether it was there.
+    pub fn remove(&mut self, filter: &ValidTopicFilter, value: &T) -> bool
+        where T: PartialEq {
+        let mut found = false;
+        let removed = self.remove_where(filter, |candidate| {
+            let matches = !found && candidate == value;
+            found |= matches;
+            matches
+        });
+        !removed.is_empty()
+    }
+
+    /// The values of every filter `topic_name` matches, following the same rules as `ValidTopicFilter::matches`.
+    /// A value attached to several matching filters is returned once for each.
+    pub fn matches<'a>(&'a self, topic_name: &'a str) -> Matches<'a, T> {
+        Matches {
+            levels: topic_name.split('/').collect(),
+            system_topic: topic_name.starts_with('$'),
+            nodes: vec![(&self.root, 0)],
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for SubscriptionTree<T> {
+    fn default() -> SubscriptionTree<T> {
+        SubscriptionTree::new()
+    }
+}
+
+/// Iterator over the values of the filters a topic name matches, see `SubscriptionTree::matches`.
+#[derive(Debug)]
+pub struct Matches<'a, T: 'a> {
+    levels: Vec<&'a str>,
+    system_topic: bool,
+    /// nodes still to visit, with the number of levels matched to reach them
+    nodes: Vec<(&'a Node<T>, usize)>,
+    values: Vec<slice::Iter<'a, T>>,
+}
+
+impl<'a, T> Matches<'a, T> {
+    fn visit(&mut self, node: &'a Node<T>, depth: usize) {
+        // wildcards in the first level do not match topic names starting with `$`
+        let wildcards = depth > 0 || !self.system_topic;
+        if wildcards {
+            // `#` also matches the parent level
+            if let Some(child) = node.children.get("#") {
+                self.values.push(child.values.iter());
+            }
+        }
+
+        match self.levels.get(depth) {
+            None => self.values.push(node.values.iter()),
+            Some(level) => {
+                if let Some(child) = node.children.get(*level) {
+                    self.nodes.push((child, depth + 1));
+                }
+                if wildcards {
+                    if let Some(child) = node.children.get("+") {
+                        self.nodes.push((child, depth + 1));
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl<'a, T> Iterator for Matches<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(values) = self.values.last_mut() {
+                match values.next() {
+                    Some(value) => return Some(value),
+                    None => {
+                        self.values.pop();
+                        continue;
+                    },
+                }
+            }
+            let (node, depth) = self.nodes.pop()?;
+            self.visit(node, depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(filter: &str) -> ValidTopicFilter {
+        ValidTopicFilter::new(filter).unwrap()
+    }
+
+    fn matches(tree: &SubscriptionTree<u32>, topic_name: &str) -> Vec<u32> {
+        let mut values: Vec<u32> = tree.matches(topic_name).cloned().collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn matching_filters() {
+        let mut tree = SubscriptionTree::new();
+        tree.insert(&filter("a/b/c"), 1);
+        tree.insert(&filter("a/+/c"), 2);
+        tree.insert(&filter("a/#"), 3);
+        tree.insert(&filter("#"), 4);
+        tree.insert(&filter("+/b/+"), 5);
+        tree.insert(&filter("a/b"), 6);
+        tree.insert(&filter("$SYS/#"), 7);
+
+        assert_eq!(matches(&tree, "a/b/c"), vec![1, 2, 3, 4, 5]);
+        assert_eq!(matches(&tree, "a/x/c"), vec![2, 3, 4]);
+        assert_eq!(matches(&tree, "a/b"), vec![3, 4, 6]);
+        assert_eq!(matches(&tree, "a"), vec![3, 4]);
+        assert_eq!(matches(&tree, "b"), vec![4]);
+        assert_eq!(matches(&tree, "$SYS/uptime"), vec![7]);
+        assert_eq!(tree.len(), 7);
+    }
+
+    #[test]
+    fn agreeing_with_topic_filter_matching() {
+        let filters = ["#", "+", "+/+", "/+", "a/#", "a/+", "a/+/#", "+/b/#", "a//b", "$SYS/+", "+/uptime"];
+        let topic_names = ["a", "a/b", "a/b/c", "/", "/a", "a//b", "$SYS/uptime", "x/uptime", "b"];
+
+        let mut tree = SubscriptionTree::new();
+        for (value, topic_filter) in filters.iter().enumerate() {
+            tree.insert(&filter(topic_filter), value as u32);
+        }
+        for topic_name in &topic_names {
+            let expected: Vec<u32> = (0..filters.len() as u32).filter(|&value| filter(filters[value as usize]).matches(topic_name)).collect();
+            assert_eq!(matches(&tree, topic_name), expected, "matching {}", topic_name);
+        }
+    }
+
+    #[test]
+    fn removing_values() {
+        let mut tree = SubscriptionTree::new();
+        tree.insert(&filter("a/+"), 1);
+        tree.insert(&filter("a/+"), 2);
+        tree.insert(&filter("a/+/c"), 3);
+
+        assert!(tree.remove(&filter("a/+"), &1));
+        assert!(!tree.remove(&filter("a/+"), &1));
+        assert!(!tree.remove(&filter("a/b"), &2));
+        assert_eq!(tree.get(&filter("a/+")), &[2]);
+        assert_eq!(matches(&tree, "a/b"), vec![2]);
+
+        assert_eq!(tree.remove_all(&filter("a/+/c")), vec![3]);
+        assert_eq!(tree.remove_where(&filter("a/+"), |&value| value == 2), vec![2]);
+        assert!(tree.is_empty());
+        assert!(tree.root.is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use super::*;
+use std::io;
+use std::io::{Read, Write};
+
+/// The body of PUBACK, PUBREC, PUBREL and PUBCOMP packets.
+/// The reason code and properties are only sent over MQTT 5 connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AckData {
+    packet_identifier: PacketIdentifier,
+    reason_code: ReasonCode,
+    properties: Properties,
+}
+
+impl Decode for AckData {
+    type DecoderState=DecodingContext;
+    type DecodingError=DecodingError;
+
+    fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+        state.check_header_flags()?;
+        let remaining_length = state.header.remaining_length;
+        if remaining_length < 2 { return Err(DecodingError::Malformed(Field::RemainingLength)) };
+
+        let packet_identifier = PacketIdentifier::decode(reader, state)?;
+        if state.protocol_version < ProtocolVersion::V5 {
+            if state.is_strict() && remaining_length != 2 { return Err(DecodingError::Malformed(Field::RemainingLength)) };
+            io::copy(&mut reader.take(u64::from(remaining_length - 2)), &mut io::sink())?;
+            return Ok(AckData::new(packet_identifier));
+        }
+
+        let (reason_code, properties) = decode_reason(reader, remaining_length - 2)?;
+        Ok(AckData { packet_identifier, reason_code, properties })
+    }
+}
+
+impl Encode for AckData {
+    fn encoded_length(&self) -> u32 {
+        self.packet_identifier.encoded_length() +
+        reason_encoded_length(self.reason_code, &self.properties)
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.packet_identifier.encode(writer)?;
+        encode_reason(self.reason_code, &self.properties, writer)
+    }
+}
+
+impl AckData {
+    pub fn new(packet_identifier: PacketIdentifier) -> AckData {
+        AckData { packet_identifier, reason_code: ReasonCode::SUCCESS, properties: Properties::new() }
+    }
+
+    pub fn with_reason(packet_identifier: PacketIdentifier, reason_code: ReasonCode, properties: Properties) -> AckData {
+        AckData { packet_identifier, reason_code, properties }
+    }
+
+    pub fn packet_identifier(&self) -> PacketIdentifier { self.packet_identifier }
+    pub fn reason_code(&self) -> ReasonCode { self.reason_code }
+    pub fn properties(&self) -> &Properties { &self.properties }
+}
+
+impl From<PacketIdentifier> for AckData {
+    fn from(packet_identifier: PacketIdentifier) -> AckData {
+        AckData::new(packet_identifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::types::Header;
+
+    #[test]
+    fn decoding_v3_acks() {
+        let header = Header { packet_type: 4, flags: 0, remaining_length: 2 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 7]);
+        let ack = AckData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(ack, AckData::new(PacketIdentifier(7)));
+
+        state.header.remaining_length = 3;
+        let mut cursor = Cursor::new(vec![0, 7, 0x10]);
+        assert!(AckData::decode(&mut cursor, &mut state).is_err());
+
+        state.strictness = Strictness::Lenient;
+        let mut cursor = Cursor::new(vec![0, 7, 0x10]);
+        let ack = AckData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(ack, AckData::new(PacketIdentifier(7)));
+        assert_eq!(cursor.position(), 3);
+    }
+
+    #[test]
+    fn decoding_v5_acks() {
+        let header = Header { packet_type: 4, flags: 0, remaining_length: 3 };
+        let mut state = DecodingContext { header, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0, 7, 0x10]);
+        let ack = AckData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(ack.reason_code(), ReasonCode::NO_MATCHING_SUBSCRIBERS);
+        assert!(ack.properties().is_empty());
+
+        state.header.remaining_length = 8;
+        let mut cursor = Cursor::new(vec![0, 7, 0x00, 4, 0x1F, 0, 1, b'a']);
+        let ack = AckData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(ack.reason_code(), ReasonCode::SUCCESS);
+        assert_eq!(ack.properties(), &Properties(vec![Property::ReasonString(String::from("a"))]));
+    }
+
+    #[test]
+    fn encoding_acks() {
+        let mut data: Vec<u8> = Vec::new();
+        AckData::new(PacketIdentifier(7)).encode(&mut data).unwrap();
+        assert_eq!(data, vec![0, 7]);
+        data.clear();
+
+        let ack = AckData::with_reason(PacketIdentifier(7), ReasonCode::PACKET_IDENTIFIER_NOT_FOUND, Properties::new());
+        ack.encode(&mut data).unwrap();
+        assert_eq!(data, vec![0, 7, 0x92]);
+        assert_eq!(ack.encoded_length(), 3);
+    }
+}
@@ -0,0 +1,68 @@
+use super::*;
+use std::io;
+use std::io::{Read, Write};
+
+/// The body of an MQTT 5 AUTH packet.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AuthData {
+    reason_code: ReasonCode,
+    properties: Properties,
+}
+
+impl Decode for AuthData {
+    type DecoderState=DecodingContext;
+    type DecodingError=DecodingError;
+
+    fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+        // AUTH does not exist before MQTT 5
+        if state.protocol_version < ProtocolVersion::V5 { return Err(DecodingError::Forbidden) };
+        state.check_header_flags()?;
+
+        let (reason_code, properties) = decode_reason(reader, state.header.remaining_length)?;
+        Ok(AuthData { reason_code, properties })
+    }
+}
+
+impl Encode for AuthData {
+    fn encoded_length(&self) -> u32 {
+        reason_encoded_length(self.reason_code, &self.properties)
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        encode_reason(self.reason_code, &self.properties, writer)
+    }
+}
+
+impl AuthData {
+    pub fn new(reason_code: ReasonCode, properties: Properties) -> AuthData {
+        AuthData { reason_code, properties }
+    }
+
+    pub fn reason_code(&self) -> ReasonCode { self.reason_code }
+    pub fn properties(&self) -> &Properties { &self.properties }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::types::Header;
+
+    #[test]
+    fn decoding_auth_data() {
+        let header = Header { packet_type: 15, flags: 0, remaining_length: 9 };
+        let mut state = DecodingContext { header, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0x18, 7, 0x15, 0, 4, b'S', b'C', b'R', b'M']);
+        let auth_data = AuthData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(auth_data.reason_code(), ReasonCode::CONTINUE_AUTHENTICATION);
+        assert_eq!(auth_data.properties(), &Properties(vec![Property::AuthenticationMethod(String::from("SCRM"))]));
+    }
+
+    #[test]
+    fn decoding_auth_data_before_v5() {
+        let header = Header { packet_type: 15, flags: 0, remaining_length: 0 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![]);
+        assert!(AuthData::decode(&mut cursor, &mut state).is_err());
+    }
+}
@@ -4,13 +4,14 @@ use std::io;
 use std::io::{Read, Write};
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnackData {
     session_present: bool,
-    return_code: ConnackReturnCode
+    return_code: ConnackReturnCode,
+    properties: Option<Properties>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ConnackReturnCode {
     Accepted,
     UnacceptableProtocolVersion,
@@ -18,6 +19,8 @@ pub enum ConnackReturnCode {
     ServerUnavailable,
     BadUsernameOrPassword,
     NotAuthorized,
+    /// an MQTT 5 reason code without a 3.1.1 equivalent
+    Refused(ReasonCode),
 }
 
 impl Decode for ConnackReturnCode {
@@ -32,7 +35,7 @@ impl Decode for ConnackReturnCode {
             3 => Ok(ConnackReturnCode::ServerUnavailable),
             4 => Ok(ConnackReturnCode::BadUsernameOrPassword),
             5 => Ok(ConnackReturnCode::NotAuthorized),
-            _ => Err(DecodingError::Malformed),
+            _ => Err(DecodingError::Malformed(Field::ReturnCode)),
         }
     }
 }
@@ -40,6 +43,7 @@ impl Decode for ConnackReturnCode {
 impl Encode for ConnackReturnCode {
     fn encoded_length(&self) -> u32 { 1 }
 
+    /// `Refused` fails with `InvalidInput`, as 3.1.1 has no way to express it
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         writer.write_u8(
             match self {
@@ -49,48 +53,114 @@ impl Encode for ConnackReturnCode {
                 ConnackReturnCode::ServerUnavailable => 3,
                 ConnackReturnCode::BadUsernameOrPassword => 4,
                 ConnackReturnCode::NotAuthorized => 5,
+                ConnackReturnCode::Refused(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "MQTT 5 reason code in a 3.1.1 CONNACK")),
             }
         )
     }
 }
 
+impl ConnackReturnCode {
+    pub fn from_reason_code(reason_code: ReasonCode) -> ConnackReturnCode {
+        match reason_code {
+            ReasonCode::SUCCESS => ConnackReturnCode::Accepted,
+            ReasonCode::UNSUPPORTED_PROTOCOL_VERSION => ConnackReturnCode::UnacceptableProtocolVersion,
+            ReasonCode::CLIENT_IDENTIFIER_NOT_VALID => ConnackReturnCode::IdentifierRejected,
+            ReasonCode::SERVER_UNAVAILABLE => ConnackReturnCode::ServerUnavailable,
+            ReasonCode::BAD_USER_NAME_OR_PASSWORD => ConnackReturnCode::BadUsernameOrPassword,
+            ReasonCode::NOT_AUTHORIZED => ConnackReturnCode::NotAuthorized,
+            reason_code => ConnackReturnCode::Refused(reason_code),
+        }
+    }
+
+    pub fn reason_code(self) -> ReasonCode {
+        match self {
+            ConnackReturnCode::Accepted => ReasonCode::SUCCESS,
+            ConnackReturnCode::UnacceptableProtocolVersion => ReasonCode::UNSUPPORTED_PROTOCOL_VERSION,
+            ConnackReturnCode::IdentifierRejected => ReasonCode::CLIENT_IDENTIFIER_NOT_VALID,
+            ConnackReturnCode::ServerUnavailable => ReasonCode::SERVER_UNAVAILABLE,
+            ConnackReturnCode::BadUsernameOrPassword => ReasonCode::BAD_USER_NAME_OR_PASSWORD,
+            ConnackReturnCode::NotAuthorized => ReasonCode::NOT_AUTHORIZED,
+            ConnackReturnCode::Refused(reason_code) => reason_code,
+        }
+    }
+}
+
 impl Decode for ConnackData {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
-        if state.header.flags != 0 { return Err(DecodingError::Malformed) };
-        if state.header.remaining_length != 2 { return Err(DecodingError::Malformed) };
+        state.check_header_flags()?;
+        if state.protocol_version < ProtocolVersion::V5 && state.header.remaining_length != 2 { return Err(DecodingError::Malformed(Field::RemainingLength)) };
+        if state.protocol_version >= ProtocolVersion::V5 && state.header.remaining_length < 3 { return Err(DecodingError::Malformed(Field::RemainingLength)) };
 
         let first_byte = reader.read_u8()?;
-        if first_byte > 1 { return Err(DecodingError::Malformed) };
+        // all but the lowest bit are reserved
+        if state.is_strict() && first_byte > 1 { return Err(DecodingError::ReservedBits(Field::ConnackFlags)) };
 
-        let session_present = first_byte == 1;
-        let return_code = ConnackReturnCode::decode(reader, &mut ())?;
-        Ok(ConnackData { session_present, return_code })
+        let session_present = first_byte & 1 == 1;
+        if state.protocol_version < ProtocolVersion::V5 {
+            let return_code = ConnackReturnCode::decode(reader, &mut ())?;
+            return Ok(ConnackData { session_present, return_code, properties: None });
+        }
+
+        let return_code = ConnackReturnCode::from_reason_code(ReasonCode::decode(reader, &mut ())?);
+        let properties = Properties::decode(reader, &mut ())?;
+        Ok(ConnackData { session_present, return_code, properties: Some(properties) })
     }
 }
 
 impl Encode for ConnackData {
-    fn encoded_length(&self) -> u32 { 2 }
+    fn encoded_length(&self) -> u32 { 2 + self.properties.encoded_length() }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.check_encodable()?;
         if self.session_present {
             writer.write_u8(1)?
         } else {
             writer.write_u8(0)?
         }
 
-        self.return_code.encode(writer)
+        match self.properties {
+            Some(ref properties) => {
+                self.return_code.reason_code().encode(writer)?;
+                properties.encode(writer)
+            },
+            None => self.return_code.encode(writer),
+        }
     }
 }
 
+impl ConnackData {
+    pub fn new(session_present: bool, return_code: ConnackReturnCode) -> ConnackData {
+        ConnackData { session_present, return_code, properties: None }
+    }
+
+    /// turns this into an MQTT 5 CONNACK
+    pub fn with_properties(mut self, properties: Properties) -> ConnackData {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn session_present(&self) -> bool { self.session_present }
+    pub fn return_code(&self) -> ConnackReturnCode { self.return_code }
+    pub fn properties(&self) -> Option<&Properties> { self.properties.as_ref() }
+
+    /// only an MQTT 5 CONNACK carries a `Refused` return code
+    pub(crate) fn check_encodable(&self) -> io::Result<()> {
+        match (self.return_code, &self.properties) {
+            (ConnackReturnCode::Refused(_), None) => Err(io::Error::new(io::ErrorKind::InvalidInput, "MQTT 5 reason code in a 3.1.1 CONNACK")),
+            _ => Ok(()),
+        }
+    }
+}
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use std::io::Cursor;
-    use types::Header;
+    use crate::types::Header;
 
     #[test]
     fn decoding_return_codes() {
@@ -129,7 +199,7 @@ mod tests {
     #[test]
     fn decoding_connack_data_1() {
         let header = Header { packet_type: 2, flags: 0, remaining_length: 2};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
         let mut cursor = Cursor::new(vec![1,0]);
         let connack_data = ConnackData::decode(&mut cursor, &mut state).unwrap();
         assert_eq!(connack_data.session_present, true);
@@ -139,7 +209,7 @@ mod tests {
     #[test]
     fn decoding_connack_data_2() {
         let header = Header { packet_type: 2, flags: 0, remaining_length: 2};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
         let mut cursor = Cursor::new(vec![0,2]);
         let connack_data = ConnackData::decode(&mut cursor, &mut state).unwrap();
         assert_eq!(connack_data.session_present, false);
@@ -149,7 +219,7 @@ mod tests {
     #[test]
     fn decoding_connack_data_3() {
         let header = Header { packet_type: 2, flags: 0, remaining_length: 2};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
         let mut cursor = Cursor::new(vec![1,5]);
         let connack_data = ConnackData::decode(&mut cursor, &mut state).unwrap();
         assert_eq!(connack_data.session_present, true);
@@ -159,7 +229,7 @@ mod tests {
     #[test]
     fn decoding_connack_data_error_1() {
         let header = Header { packet_type: 2, flags: 1, remaining_length: 2};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
         let mut cursor = Cursor::new(vec![1,5]);
         let result = ConnackData::decode(&mut cursor, &mut state);
         assert_eq!(result.is_err(), true);
@@ -168,7 +238,7 @@ mod tests {
     #[test]
     fn decoding_connack_data_error_2() {
         let header = Header { packet_type: 2, flags: 0, remaining_length: 4};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
         let mut cursor = Cursor::new(vec![1,5]);
         let result = ConnackData::decode(&mut cursor, &mut state);
         assert_eq!(result.is_err(), true);
@@ -177,9 +247,38 @@ mod tests {
     #[test]
     fn decoding_connack_data_error_3() {
         let header = Header { packet_type: 2, flags: 0, remaining_length: 2};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
         let mut cursor = Cursor::new(vec![2,5]);
         let result = ConnackData::decode(&mut cursor, &mut state);
         assert_eq!(result.is_err(), true);
     }
+
+    #[test]
+    fn decoding_v5_connack_data() {
+        let header = Header { packet_type: 2, flags: 0, remaining_length: 8};
+        let mut state = DecodingContext { header, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0, 0x9C, 5, 0x1C, 0, 2, b'h', b'2']);
+        let connack_data = ConnackData::decode(&mut cursor, &mut state).unwrap();
+        assert!(!connack_data.session_present());
+        assert_eq!(connack_data.return_code(), ConnackReturnCode::Refused(ReasonCode::USE_ANOTHER_SERVER));
+        assert_eq!(connack_data.properties(), Some(&Properties(vec![Property::ServerReference(String::from("h2"))])));
+    }
+
+    #[test]
+    fn encoding_v5_connack_data() {
+        let connack_data = ConnackData::new(true, ConnackReturnCode::NotAuthorized).with_properties(Properties::new());
+        let mut data: Vec<u8> = Vec::new();
+        connack_data.encode(&mut data).unwrap();
+        assert_eq!(data, vec![1, 0x87, 0]);
+        assert_eq!(connack_data.encoded_length(), 3);
+    }
+
+    #[test]
+    fn encoding_a_refused_v3_connack() {
+        let connack_data = ConnackData::new(false, ConnackReturnCode::Refused(ReasonCode::BANNED));
+        let mut data: Vec<u8> = Vec::new();
+        let err = Packet::Connack(connack_data).encode(&mut data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(data.is_empty());
+    }
 }
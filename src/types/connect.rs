@@ -1,10 +1,54 @@
 use std::io;
 use std::io::{Read, Write};
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use byteorder::{ReadBytesExt, BigEndian};
 use super::*;
+use crate::topic::validate_topic_name;
+
+/// The protocol versions a CONNECT can ask for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// MQTT 3.1, which calls itself "MQIsdp"
+    V31,
+    V311,
+    V5,
+}
 
+impl ProtocolVersion {
+    pub fn from_level(protocol_level: u8) -> Option<ProtocolVersion> {
+        match protocol_level {
+            3 => Some(ProtocolVersion::V31),
+            4 => Some(ProtocolVersion::V311),
+            5 => Some(ProtocolVersion::V5),
+            _ => None,
+        }
+    }
 
-#[derive(Debug)]
+    pub fn level(self) -> u8 {
+        match self {
+            ProtocolVersion::V31 => 3,
+            ProtocolVersion::V311 => 4,
+            ProtocolVersion::V5 => 5,
+        }
+    }
+
+    pub fn protocol_name(self) -> &'static str {
+        match self {
+            ProtocolVersion::V31 => "MQIsdp",
+            _ => "MQTT",
+        }
+    }
+
+    /// MQTT 3.1 servers must reject client identifiers longer than 23 bytes,
+    /// later versions leave the limit up to the server.
+    pub fn max_client_identifier_length(self) -> Option<usize> {
+        match self {
+            ProtocolVersion::V31 => Some(23),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectData {
     protocol_level: u8,
     keepalive: u16,
@@ -16,30 +60,42 @@ pub struct ConnectData {
     will_qos: Qos,
     user_name: Option<String>,
     password: Option<String>,
+    properties: Properties,
+    will_properties: Properties,
 }
 
 impl Decode for ConnectData {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
+    #[allow(clippy::needless_late_init)]
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
-        if state.header.flags != 0 { return Err(DecodingError::Malformed) };
+        state.check_header_flags()?;
 
         let protocol_name = String::decode(reader, &mut ())?;
-        if protocol_name != "MQTT" { return Err(DecodingError::Malformed) };
+        if protocol_name != "MQTT" && protocol_name != "MQIsdp" { return Err(DecodingError::Malformed(Field::ProtocolName)) };
 
-        // this should equal 4, but has to be handled in the response, so no error here
+        // this should equal 3, 4 or 5, anything else is handled in the response
         let protocol_level = reader.read_u8()?;
+        state.protocol_version = ProtocolVersion::from_level(protocol_level).ok_or(DecodingError::UnacceptableProtocolVersion(protocol_level))?;
+        // MQTT 3.1 is the only version going by "MQIsdp"
+        if state.is_strict() && (protocol_name == "MQIsdp") != (protocol_level == 3) { return Err(DecodingError::Malformed(Field::ProtocolLevel)) };
         let connect_flags = reader.read_u8()?;
         // validate that the first bit is set to zero, otherwise this must be an error
-        if connect_flags & 1 > 0 { return Err(DecodingError::Malformed) };
+        if state.is_strict() && connect_flags & 1 > 0 { return Err(DecodingError::ReservedBits(Field::ConnectFlags)) };
+        // [MQTT-3.1.2-13] and [MQTT-3.1.2-15] no will QoS or retain without a will
+        if state.is_strict() && connect_flags & 0b0000_0100 == 0 && connect_flags & 0b0011_1000 > 0 { return Err(DecodingError::Malformed(Field::ConnectFlags)) };
+        // [MQTT-3.1.2-22] no password without a user name, which MQTT 5 allows
+        if state.is_strict() && protocol_level < 5 && connect_flags & 0b1100_0000 == 0b0100_0000 { return Err(DecodingError::Malformed(Field::ConnectFlags)) };
 
         let clean_session = connect_flags & 0b0000_0010 > 0;
         let keepalive = reader.read_u16::<BigEndian>()?;
+        let properties = decode_properties(reader, protocol_level)?;
 
         let client_identifier = String::decode(reader, &mut ())?;
 
         // these will be instantiated later on
+        let will_properties;
         let will_topic;
         let will_message;
         let user_name;
@@ -47,9 +103,13 @@ impl Decode for ConnectData {
 
         // check will_flag
         if connect_flags & 0b0000_0100 > 0 {
-            will_topic = Some(String::decode(reader, &mut ())?);
+            will_properties = decode_properties(reader, protocol_level)?;
+            let topic = String::decode(reader, &mut ())?;
+            validate_topic_name(&topic)?;
+            will_topic = Some(topic);
             will_message = Some(String::decode(reader, &mut ())?);
         } else {
+            will_properties = Properties::new();
             will_topic = None;
             will_message= None;
         }
@@ -71,7 +131,8 @@ impl Decode for ConnectData {
         let will_retain = connect_flags & 0b0010_0000 > 0;
 
         // this is a sort-of decode method that does not actually decode like this method does
-        let will_qos = Qos::decode((connect_flags & 0b0001_1000) >> 3).ok_or(DecodingError::Malformed)?;
+        let will_qos = (connect_flags & 0b0001_1000) >> 3;
+        let will_qos = Qos::decode(will_qos).ok_or(DecodingError::InvalidQos(will_qos))?;
 
         Ok(ConnectData {
             protocol_level,
@@ -83,18 +144,31 @@ impl Decode for ConnectData {
             will_retain,
             will_qos,
             user_name,
-            password})
+            password,
+            properties,
+            will_properties})
+    }
+}
+
+/// properties are only present from MQTT 5 onwards
+fn decode_properties<R: Read>(reader: &mut R, protocol_level: u8) -> Result<Properties, DecodingError> {
+    if protocol_level >= 5 {
+        Properties::decode(reader, &mut ())
+    } else {
+        Ok(Properties::new())
     }
 }
 
 impl Encode for ConnectData {
     fn encoded_length(&self) -> u32 {
-        "MQTT".encoded_length() +
+        self.protocol_name().encoded_length() +
         self.protocol_level.encoded_length() +
         1 + // flags
         self.keepalive.encoded_length() +
+        self.encoded_properties().encoded_length() +
         // payload
         self.client_identifier.encoded_length() +
+        self.encoded_will_properties().encoded_length() +
         self.will_topic.encoded_length() +
         self.will_message.encoded_length() +
         self.user_name.encoded_length() +
@@ -102,21 +176,25 @@ impl Encode for ConnectData {
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        "MQTT".encode(writer)?;
+        self.protocol_name().encode(writer)?;
         self.protocol_level.encode(writer)?;
 
         let mut flags = 0u8;
-        if self.user_name.is_some() {flags &= 0b1000_0000}
-        if self.password.is_some() {flags &= 0b0100_0000}
-        if self.will_retain {flags &= 0b0010_0000}
-        if self.will_retain {flags &= 0b0010_0000}
-        flags &= self.will_qos.encode() << 3;
-        if self.will_topic.is_some() {flags &= 0b0000_0100}
-        if self.clean_session {flags &= 0b0000_0010}
+        if self.user_name.is_some() {flags |= 0b1000_0000}
+        if self.password.is_some() {flags |= 0b0100_0000}
+        // will QoS and retain have to be zero without a will
+        if self.will_topic.is_some() {
+            if self.will_retain {flags |= 0b0010_0000}
+            flags |= self.will_qos.encode() << 3;
+            flags |= 0b0000_0100;
+        }
+        if self.clean_session {flags |= 0b0000_0010}
         flags.encode(writer)?;
 
         self.keepalive.encode(writer)?;
+        self.encoded_properties().encode(writer)?;
         self.client_identifier.encode(writer)?;
+        self.encoded_will_properties().encode(writer)?;
         self.will_topic.encode(writer)?;
         self.will_message.encode(writer)?;
         self.user_name.encode(writer)?;
@@ -124,16 +202,156 @@ impl Encode for ConnectData {
     }
 }
 
+impl ConnectData {
+    pub fn builder() -> ConnectDataBuilder {
+        ConnectDataBuilder::default()
+    }
+
+    pub fn protocol_level(&self) -> u8 { self.protocol_level }
+    /// `None` for protocol levels this crate does not know
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> { ProtocolVersion::from_level(self.protocol_level) }
+    pub fn keepalive(&self) -> u16 { self.keepalive }
+    pub fn client_identifier(&self) -> &str { &self.client_identifier }
+    pub fn clean_session(&self) -> bool { self.clean_session }
+    pub fn will_topic(&self) -> Option<&str> { self.will_topic.as_deref() }
+    pub fn will_message(&self) -> Option<&str> { self.will_message.as_deref() }
+    pub fn will_retain(&self) -> bool { self.will_retain }
+    pub fn will_qos(&self) -> Qos { self.will_qos }
+    pub fn user_name(&self) -> Option<&str> { self.user_name.as_deref() }
+    pub fn password(&self) -> Option<&str> { self.password.as_deref() }
+    pub fn properties(&self) -> &Properties { &self.properties }
+    pub fn will_properties(&self) -> &Properties { &self.will_properties }
+
+    /// The same CONNECT asking to start a clean session or not, as when reconnecting to resume one.
+    pub fn with_clean_session(mut self, clean_session: bool) -> ConnectData {
+        self.clean_session = clean_session;
+        self
+    }
+
+    /// Whether a server should accept the client identifier, or respond with `IdentifierRejected`.
+    pub fn has_valid_client_identifier(&self) -> bool {
+        match self.protocol_version() {
+            Some(ProtocolVersion::V31) => !self.client_identifier.is_empty() && self.client_identifier.len() <= 23,
+            // an empty identifier asks the server to assign one, which only works for a clean session
+            Some(ProtocolVersion::V311) => !self.client_identifier.is_empty() || self.clean_session,
+            _ => true,
+        }
+    }
+
+    fn protocol_name(&self) -> &'static str {
+        ProtocolVersion::from_level(self.protocol_level)
+            .map_or("MQTT", ProtocolVersion::protocol_name)
+    }
+
+    fn encoded_properties(&self) -> Option<&Properties> {
+        if self.protocol_level >= 5 { Some(&self.properties) } else { None }
+    }
+
+    fn encoded_will_properties(&self) -> Option<&Properties> {
+        if self.protocol_level >= 5 && self.will_topic.is_some() { Some(&self.will_properties) } else { None }
+    }
+}
+
+/// Builds a `ConnectData`, defaulting to protocol level 4, a 60 second keepalive,
+/// a clean session and no will or credentials.
+#[derive(Debug)]
+pub struct ConnectDataBuilder {
+    data: ConnectData,
+}
+
+impl Default for ConnectDataBuilder {
+    fn default() -> Self {
+        ConnectDataBuilder {
+            data: ConnectData {
+                protocol_level: 4,
+                keepalive: 60,
+                client_identifier: String::new(),
+                clean_session: true,
+                will_topic: None,
+                will_message: None,
+                will_retain: false,
+                will_qos: Qos::AtMostOnce,
+                user_name: None,
+                password: None,
+                properties: Properties::new(),
+                will_properties: Properties::new(),
+            }
+        }
+    }
+}
+
+impl ConnectDataBuilder {
+    pub fn protocol_level(mut self, protocol_level: u8) -> Self {
+        self.data.protocol_level = protocol_level;
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.data.protocol_level = protocol_version.level();
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: u16) -> Self {
+        self.data.keepalive = keepalive;
+        self
+    }
+
+    pub fn client_id<S: Into<String>>(mut self, client_identifier: S) -> Self {
+        self.data.client_identifier = client_identifier.into();
+        self
+    }
+
+    pub fn clean_session(mut self, clean_session: bool) -> Self {
+        self.data.clean_session = clean_session;
+        self
+    }
+
+    pub fn will<T: Into<String>, M: Into<String>>(mut self, topic: T, message: M, qos: Qos, retain: bool) -> Self {
+        self.data.will_topic = Some(topic.into());
+        self.data.will_message = Some(message.into());
+        self.data.will_qos = qos;
+        self.data.will_retain = retain;
+        self
+    }
+
+    pub fn user_name<S: Into<String>>(mut self, user_name: S) -> Self {
+        self.data.user_name = Some(user_name.into());
+        self
+    }
+
+    pub fn password<S: Into<String>>(mut self, password: S) -> Self {
+        self.data.password = Some(password.into());
+        self
+    }
+
+    /// only encoded when the protocol level is 5
+    pub fn properties(mut self, properties: Properties) -> Self {
+        self.data.properties = properties;
+        self
+    }
+
+    /// only encoded when the protocol level is 5
+    pub fn will_properties(mut self, will_properties: Properties) -> Self {
+        self.data.will_properties = will_properties;
+        self
+    }
+
+    pub fn build(self) -> ConnectData {
+        self.data
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use std::io::Cursor;
-    use types::Header;
+    use crate::types::Header;
 
     #[test]
     fn decoding_connect_data_1() {
         let header = Header { packet_type: 1, flags: 0, remaining_length: 47};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
 
         let mut sample_data: Vec<u8> = vec![0,4];
         sample_data.extend_from_slice("MQTT".as_bytes());
@@ -189,7 +407,8 @@ mod tests {
     #[test]
     fn decoding_connect_data_2() {
         let header = Header { packet_type: 1, flags: 0, remaining_length: 47};
-        let mut state = DecodingInfo {header};
+        // a will QoS without a will is malformed when strict
+        let mut state = DecodingContext { header, strictness: Strictness::Lenient, ..DecodingContext::default() };
 
         let mut sample_data: Vec<u8> = vec![0,4];
         sample_data.extend_from_slice("MQTT".as_bytes());
@@ -225,7 +444,7 @@ mod tests {
     #[test]
     fn decoding_connect_data_3() {
         let header = Header { packet_type: 1, flags: 0, remaining_length: 47};
-        let mut state = DecodingInfo {header};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
 
         let mut sample_data: Vec<u8> = vec![0,4];
         sample_data.extend_from_slice("MQTT".as_bytes());
@@ -267,4 +486,160 @@ mod tests {
         assert_eq!(connect_data.user_name, Some(String::from("nudded")));
         assert_eq!(connect_data.password, Some(String::from("nudded")));
     }
+
+    #[test]
+    fn building_connect_data() {
+        let connect_data = ConnectData::builder()
+            .client_id("TOON")
+            .keepalive(30)
+            .clean_session(false)
+            .will("FEBE", "testing", Qos::AtLeastOnce, true)
+            .user_name("nudded")
+            .password("secret")
+            .build();
+
+        assert_eq!(connect_data.protocol_level(), 4);
+        assert_eq!(connect_data.keepalive(), 30);
+        assert_eq!(connect_data.client_identifier(), "TOON");
+        assert!(!connect_data.clean_session());
+        assert_eq!(connect_data.will_topic(), Some("FEBE"));
+        assert_eq!(connect_data.will_message(), Some("testing"));
+        assert!(connect_data.will_retain());
+        assert_eq!(connect_data.will_qos(), Qos::AtLeastOnce);
+        assert_eq!(connect_data.user_name(), Some("nudded"));
+        assert_eq!(connect_data.password(), Some("secret"));
+    }
+
+    #[test]
+    fn decoding_v5_connect_data() {
+        let header = Header { packet_type: 1, flags: 0, remaining_length: 35};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+
+        let mut sample_data: Vec<u8> = vec![0,4];
+        sample_data.extend_from_slice("MQTT".as_bytes());
+        // protocol level
+        sample_data.push(5);
+        // connect flags
+        sample_data.push(0b0000_0110);
+
+        // keepalive
+        sample_data.push(0);
+        sample_data.push(10);
+
+        // properties
+        sample_data.extend_from_slice(&[5, 0x11, 0, 0, 0, 30]);
+
+        // payload
+        // client identifier
+        sample_data.extend_from_slice(&[0, 4]);
+        sample_data.extend_from_slice("TOON".as_bytes());
+
+        // will properties
+        sample_data.extend_from_slice(&[2, 0x01, 1]);
+
+        // will topic
+        sample_data.extend_from_slice(&[0, 4]);
+        sample_data.extend_from_slice("FEBE".as_bytes());
+
+        // will message
+        sample_data.extend_from_slice(&[0, 2]);
+        sample_data.extend_from_slice("hi".as_bytes());
+
+        let mut cursor = Cursor::new(sample_data);
+        let connect_data = ConnectData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(state.protocol_version, ProtocolVersion::V5);
+        assert_eq!(connect_data.protocol_level(), 5);
+        assert_eq!(connect_data.properties(), &Properties(vec![Property::SessionExpiryInterval(30)]));
+        assert_eq!(connect_data.client_identifier(), "TOON");
+        assert_eq!(connect_data.will_properties(), &Properties(vec![Property::PayloadFormatIndicator(1)]));
+        assert_eq!(connect_data.will_topic(), Some("FEBE"));
+        assert_eq!(connect_data.will_message(), Some("hi"));
+    }
+
+    #[test]
+    fn encoded_length_of_v5_connect_data() {
+        let connect_data = ConnectData::builder()
+            .protocol_level(5)
+            .client_id("TOON")
+            .properties(Properties(vec![Property::SessionExpiryInterval(30)]))
+            .build();
+        let mut data: Vec<u8> = Vec::new();
+        connect_data.encode(&mut data).unwrap();
+        assert_eq!(data.len() as u32, connect_data.encoded_length());
+        assert_eq!(&data[10..16], &[5, 0x11, 0, 0, 0, 30]);
+    }
+
+    #[test]
+    fn decoding_v31_connect_data() {
+        let header = Header { packet_type: 1, flags: 0, remaining_length: 18};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+
+        let mut sample_data: Vec<u8> = vec![0,6];
+        sample_data.extend_from_slice("MQIsdp".as_bytes());
+        // protocol level
+        sample_data.push(3);
+        // connect flags
+        sample_data.push(0b0000_0010);
+
+        // keepalive
+        sample_data.push(0);
+        sample_data.push(10);
+
+        // payload
+        // client identifier
+        sample_data.push(0);
+        sample_data.push(4);
+        sample_data.extend_from_slice("TOON".as_bytes());
+
+        let mut cursor = Cursor::new(sample_data);
+        let connect_data = ConnectData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(connect_data.protocol_level(), 3);
+        assert_eq!(connect_data.protocol_version(), Some(ProtocolVersion::V31));
+        assert_eq!(connect_data.client_identifier(), "TOON");
+        assert!(connect_data.has_valid_client_identifier());
+        assert_eq!(state.protocol_version, ProtocolVersion::V31);
+    }
+
+    #[test]
+    fn decoding_mismatched_protocol_names() {
+        let header = Header { packet_type: 1, flags: 0, remaining_length: 18};
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+
+        let mut sample_data: Vec<u8> = vec![0,6];
+        sample_data.extend_from_slice("MQIsdp".as_bytes());
+        sample_data.extend_from_slice(&[4, 0b0000_0010, 0, 10, 0, 1, b'a']);
+        let mut cursor = Cursor::new(sample_data);
+        assert!(ConnectData::decode(&mut cursor, &mut state).is_err());
+
+        let mut sample_data: Vec<u8> = vec![0,4];
+        sample_data.extend_from_slice("MQTT".as_bytes());
+        sample_data.extend_from_slice(&[3, 0b0000_0010, 0, 10, 0, 1, b'a']);
+        let mut cursor = Cursor::new(sample_data);
+        assert!(ConnectData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn encoding_v31_connect_data() {
+        let connect_data = ConnectData::builder()
+            .protocol_version(ProtocolVersion::V31)
+            .client_id("TOON")
+            .build();
+        let mut data: Vec<u8> = Vec::new();
+        connect_data.encode(&mut data).unwrap();
+        assert_eq!(&data[..9], &[0, 6, b'M', b'Q', b'I', b's', b'd', b'p', 3]);
+        assert_eq!(data.len() as u32, connect_data.encoded_length());
+    }
+
+    #[test]
+    fn validating_client_identifiers() {
+        let too_long = "abcdefghijklmnopqrstuvwx";
+        let v31 = ConnectData::builder().protocol_version(ProtocolVersion::V31);
+        assert!(!v31.client_id(too_long).build().has_valid_client_identifier());
+        let v31 = ConnectData::builder().protocol_version(ProtocolVersion::V31);
+        assert!(!v31.client_id("").build().has_valid_client_identifier());
+
+        assert!(ConnectData::builder().client_id(too_long).build().has_valid_client_identifier());
+        assert!(ConnectData::builder().client_id("").build().has_valid_client_identifier());
+        assert!(!ConnectData::builder().client_id("").clean_session(false).build().has_valid_client_identifier());
+    }
 }
@@ -2,9 +2,11 @@ use std::io::Read;
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
 use super::*;
+use crate::topic::TopicError;
 
 use byteorder::{ReadBytesExt, BigEndian};
 
@@ -12,24 +14,167 @@ pub trait Decode: Sized {
     type DecoderState;
     type DecodingError: Error;
 
-    fn decode<R: Read>(&mut R, &mut Self::DecoderState) -> Result<Self, Self::DecodingError>;
+    fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, Self::DecodingError>;
 }
 
 #[derive(Debug)]
 pub enum DecodingError {
     IoError(io::Error),
-    Utf8Error(FromUtf8Error),
-    Malformed,
+    Utf8Error(Utf8Error),
+    /// a field holds a value the specification does not allow
+    Malformed(Field),
+    /// a field sets bits the specification reserves
+    ReservedBits(Field),
+    /// a QoS level other than 0, 1 or 2
+    InvalidQos(u8),
+    /// a topic name or filter that breaks the rules of section 4.7
+    InvalidTopic(TopicError),
+    /// a reserved packet type, or one the protocol level does not have
     Forbidden,
+    /// a CONNECT for a protocol level other than 3, 4 or 5, which a server answers with
+    /// `ConnackReturnCode::UnacceptableProtocolVersion` [MQTT-3.1.2-2]
+    UnacceptableProtocolVersion(u8),
+    /// the packet, fixed header included, is larger than the context allows
+    PacketTooLarge { size: u32, maximum: u32 },
+    /// an error in the body of a packet, `offset` bytes into it counting the fixed header
+    InPacket { packet_type: u8, offset: u64, source: Box<DecodingError> },
+}
+
+/// The part of a packet that failed to decode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Field {
+    HeaderFlags,
+    RemainingLength,
+    ProtocolName,
+    ProtocolLevel,
+    ConnectFlags,
+    ConnackFlags,
+    ReturnCode,
+    TopicName,
+    TopicAlias,
+    PacketIdentifier,
+    Payload,
+    Properties,
+    PropertyIdentifier,
+    SubscriptionOptions,
+    VariableByteInteger,
+}
+
+impl DecodingError {
+    /// the packet type the error occurred in, if it got past the fixed header
+    pub fn packet_type(&self) -> Option<u8> {
+        match self {
+            DecodingError::InPacket { packet_type, .. } => Some(*packet_type),
+            _ => None,
+        }
+    }
+
+    /// how far into the packet decoding got before failing, if it got past the fixed header
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            DecodingError::InPacket { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// the error without its position
+    pub fn root_cause(&self) -> &DecodingError {
+        match self {
+            DecodingError::InPacket { source, .. } => source.root_cause(),
+            err => err,
+        }
+    }
+
+    /// Record the position of an error, offsets of nested positions add up.
+    pub(crate) fn in_packet(self, packet_type: u8, offset: u64) -> DecodingError {
+        match self {
+            DecodingError::InPacket { offset: inner_offset, source, .. } => DecodingError::InPacket { packet_type, offset: offset + inner_offset, source },
+            err => DecodingError::InPacket { packet_type, offset, source: Box::new(err) },
+        }
+    }
+
+    /// Running out of bytes within a complete frame means its remaining length was wrong.
+    pub(crate) fn within_frame(self) -> DecodingError {
+        match self {
+            DecodingError::IoError(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => DecodingError::Malformed(Field::RemainingLength),
+            DecodingError::InPacket { packet_type, offset, source } => DecodingError::InPacket { packet_type, offset, source: Box::new(source.within_frame()) },
+            err => err,
+        }
+    }
 }
 
 impl fmt::Display for DecodingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Decoding failed")
+        match self {
+            DecodingError::IoError(err) => write!(f, "I/O error: {}", err),
+            DecodingError::Utf8Error(err) => write!(f, "invalid UTF-8 string: {}", err),
+            DecodingError::Malformed(field) => write!(f, "malformed {}", field),
+            DecodingError::ReservedBits(field) => write!(f, "reserved bits set in {}", field),
+            DecodingError::InvalidQos(qos) => write!(f, "invalid QoS {}", qos),
+            DecodingError::InvalidTopic(err) => write!(f, "invalid topic: {}", err),
+            DecodingError::Forbidden => write!(f, "packet type not allowed"),
+            DecodingError::UnacceptableProtocolVersion(level) => write!(f, "unacceptable protocol level {}", level),
+            DecodingError::PacketTooLarge { size, maximum } => write!(f, "packet of {} bytes exceeds the maximum of {} bytes", size, maximum),
+            DecodingError::InPacket { packet_type, offset, source } => write!(f, "{} at byte {}: {}", packet_name(*packet_type), offset, source),
+        }
     }
 }
 
-impl Error for DecodingError {}
+impl Error for DecodingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodingError::IoError(err) => Some(err),
+            DecodingError::Utf8Error(err) => Some(err),
+            DecodingError::InvalidTopic(err) => Some(err),
+            DecodingError::InPacket { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Field::HeaderFlags => "fixed header flags",
+            Field::RemainingLength => "remaining length",
+            Field::ProtocolName => "protocol name",
+            Field::ProtocolLevel => "protocol level",
+            Field::ConnectFlags => "connect flags",
+            Field::ConnackFlags => "connect acknowledge flags",
+            Field::ReturnCode => "return code",
+            Field::TopicName => "topic name",
+            Field::TopicAlias => "topic alias",
+            Field::PacketIdentifier => "packet identifier",
+            Field::Payload => "payload",
+            Field::Properties => "properties",
+            Field::PropertyIdentifier => "property identifier",
+            Field::SubscriptionOptions => "subscription options",
+            Field::VariableByteInteger => "variable byte integer",
+        };
+        f.write_str(name)
+    }
+}
+
+fn packet_name(packet_type: u8) -> &'static str {
+    match packet_type {
+        1 => "CONNECT",
+        2 => "CONNACK",
+        3 => "PUBLISH",
+        4 => "PUBACK",
+        5 => "PUBREC",
+        6 => "PUBREL",
+        7 => "PUBCOMP",
+        8 => "SUBSCRIBE",
+        9 => "SUBACK",
+        10 => "UNSUBSCRIBE",
+        11 => "UNSUBACK",
+        12 => "PINGREQ",
+        13 => "PINGRESP",
+        14 => "DISCONNECT",
+        15 => "AUTH",
+        _ => "reserved packet",
+    }
+}
 
 impl From<io::Error> for DecodingError {
     fn from(err: io::Error) -> Self {
@@ -37,14 +182,115 @@ impl From<io::Error> for DecodingError {
     }
 }
 
+impl From<TopicError> for DecodingError {
+    fn from(err: TopicError) -> Self {
+        DecodingError::InvalidTopic(err)
+    }
+}
+
+impl From<Utf8Error> for DecodingError {
+    fn from(err: Utf8Error) -> Self {
+        DecodingError::Utf8Error(err)
+    }
+}
+
 impl From<FromUtf8Error> for DecodingError {
     fn from(err: FromUtf8Error) -> Self {
-        DecodingError::Utf8Error(err)
+        DecodingError::Utf8Error(err.utf8_error())
+    }
+}
+
+/// Counts the bytes read through it, to locate decoding errors.
+pub(crate) struct CountingReader<'a, R: 'a> {
+    inner: &'a mut R,
+    count: u64,
+}
+
+impl<'a, R: Read> CountingReader<'a, R> {
+    pub(crate) fn new(inner: &'a mut R) -> CountingReader<'a, R> {
+        CountingReader { inner, count: 0 }
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<'a, R: Read> Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
     }
 }
 
-pub struct DecodingInfo {
+/// Per-connection state threaded through every decoder.
+#[derive(Debug, Clone)]
+pub struct DecodingContext {
+    /// the header of the packet being decoded
     pub header: Header,
+    /// the negotiated protocol version, updated when a CONNECT is decoded
+    pub protocol_version: ProtocolVersion,
+    /// the largest packet this side accepts, `None` for the protocol maximum
+    pub max_packet_size: Option<u32>,
+    /// the highest topic alias the peer may use in an MQTT 5 PUBLISH, 0 disables aliases
+    pub topic_alias_maximum: u16,
+    /// whether to reject reserved bits and trailing data, `Strict` unless set otherwise
+    pub strictness: Strictness,
+}
+
+/// How to treat packets that set reserved bits, carry trailing data or set flags that
+/// don't go together, which some older clients do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Strictness {
+    /// reject them as malformed, as the specification requires
+    Strict,
+    /// ignore the reserved bits, trailing data and stray flags
+    Lenient,
+}
+
+impl DecodingContext {
+    pub fn new(protocol_version: ProtocolVersion) -> DecodingContext {
+        DecodingContext {
+            header: Header::default(),
+            protocol_version,
+            max_packet_size: None,
+            topic_alias_maximum: 0,
+            strictness: Strictness::Strict,
+        }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strictness == Strictness::Strict
+    }
+
+    /// [MQTT-2.2.2-2] Every packet type but PUBLISH reserves the flags of the fixed header,
+    /// which strict decoding checks and lenient decoding ignores.
+    pub(crate) fn check_header_flags(&self) -> Result<(), DecodingError> {
+        let reserved = match self.header.packet_type {
+            3 => return Ok(()),
+            6 | 8 | 10 => 0b0010,
+            _ => 0,
+        };
+        if self.is_strict() && self.header.flags != reserved { return Err(DecodingError::ReservedBits(Field::HeaderFlags)) };
+        Ok(())
+    }
+
+    /// Check that a body took up exactly the remaining length, which lenient decoding
+    /// only requires of bodies that would run past it.
+    pub(crate) fn check_body_length(&self, body_length: u64) -> Result<(), DecodingError> {
+        let remaining_length = u64::from(self.header.remaining_length);
+        if body_length > remaining_length || (self.is_strict() && body_length < remaining_length) {
+            return Err(DecodingError::Malformed(Field::RemainingLength));
+        }
+        Ok(())
+    }
+}
+
+impl Default for DecodingContext {
+    fn default() -> DecodingContext {
+        DecodingContext::new(ProtocolVersion::V311)
+    }
 }
 
 impl Decode for String {
@@ -52,14 +298,38 @@ impl Decode for String {
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, _: &mut Self::DecoderState) -> Result<Self, DecodingError> {
-        let len = reader.read_u16::<BigEndian>().unwrap_or(0);
-        let mut buf = Vec::with_capacity(len as usize);
-
-        reader.take(u64::from(len)).read_to_end(&mut buf)?;
+        let buf = decode_binary_data(reader)?;
         String::from_utf8(buf).map_err(|err| err.into())
     }
 }
 
+pub(crate) fn decode_variable_byte_integer<R: Read>(reader: &mut R) -> Result<u32, DecodingError> {
+    let mut value: u32 = 0;
+    let mut multiplier: u32 = 1;
+    loop {
+        let next_byte = reader.read_u8()?;
+        value += u32::from(next_byte & 127) * multiplier;
+        if (next_byte & 128) == 0 {
+            return Ok(value);
+        }
+        // a variable byte integer is at most four bytes long
+        if multiplier == 128 * 128 * 128 {
+            return Err(DecodingError::Malformed(Field::VariableByteInteger));
+        }
+        multiplier *= 128;
+    }
+}
+
+pub(crate) fn decode_binary_data<R: Read>(reader: &mut R) -> Result<Vec<u8>, DecodingError> {
+    let len = reader.read_u16::<BigEndian>()?;
+    let mut buf = Vec::with_capacity(len as usize);
+    reader.take(u64::from(len)).read_to_end(&mut buf)?;
+    if buf.len() != len as usize {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,4 +349,26 @@ mod tests {
         let decoded_string = String::decode(&mut cursor, &mut ()).unwrap();
         assert_eq!(decoded_string, String::from("testing"));
     }
+
+    #[test]
+    fn displaying_positioned_errors() {
+        let err = DecodingError::ReservedBits(Field::ConnectFlags).in_packet(1, 10);
+        assert_eq!(err.to_string(), "CONNECT at byte 10: reserved bits set in connect flags");
+        assert_eq!(err.packet_type(), Some(1));
+        assert_eq!(err.offset(), Some(10));
+        assert!(matches!(err.root_cause(), DecodingError::ReservedBits(Field::ConnectFlags)));
+        assert_eq!(err.source().unwrap().to_string(), "reserved bits set in connect flags");
+    }
+
+    #[test]
+    fn nesting_positions() {
+        let err = DecodingError::InvalidQos(3).in_packet(3, 0).in_packet(3, 2);
+        assert_eq!(err.to_string(), "PUBLISH at byte 2: invalid QoS 3");
+    }
+
+    #[test]
+    fn running_out_of_bytes_within_a_frame() {
+        let err = DecodingError::from(io::Error::from(io::ErrorKind::UnexpectedEof)).in_packet(4, 3);
+        assert!(matches!(err.within_frame().root_cause(), DecodingError::Malformed(Field::RemainingLength)));
+    }
 }
@@ -0,0 +1,42 @@
+use super::*;
+use std::io;
+use std::io::{Read, Write};
+
+/// The body of a DISCONNECT packet, which is empty before MQTT 5.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DisconnectData {
+    reason_code: ReasonCode,
+    properties: Properties,
+}
+
+impl Decode for DisconnectData {
+    type DecoderState=DecodingContext;
+    type DecodingError=DecodingError;
+
+    fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+        state.check_header_flags()?;
+        if state.protocol_version < ProtocolVersion::V5 && state.header.remaining_length != 0 { return Err(DecodingError::Malformed(Field::RemainingLength)) };
+
+        let (reason_code, properties) = decode_reason(reader, state.header.remaining_length)?;
+        Ok(DisconnectData { reason_code, properties })
+    }
+}
+
+impl Encode for DisconnectData {
+    fn encoded_length(&self) -> u32 {
+        reason_encoded_length(self.reason_code, &self.properties)
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        encode_reason(self.reason_code, &self.properties, writer)
+    }
+}
+
+impl DisconnectData {
+    pub fn new(reason_code: ReasonCode, properties: Properties) -> DisconnectData {
+        DisconnectData { reason_code, properties }
+    }
+
+    pub fn reason_code(&self) -> ReasonCode { self.reason_code }
+    pub fn properties(&self) -> &Properties { &self.properties }
+}
@@ -8,7 +8,7 @@ pub trait Encode {
     fn encoded_length(&self) -> u32 {0}
 
     /// encode the mqtt data onto the writer
-    fn encode<W: Write>(&self, &mut W) -> io::Result<()>;
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()>;
 }
 
 impl Encode for str {
@@ -17,10 +17,7 @@ impl Encode for str {
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        let len = self.len() as u16;
-        writer.write_u16::<BigEndian>(len)?;
-        writer.write_all(self.as_bytes())?;
-        Ok(())
+        encode_binary_data(self.as_bytes(), writer)
     }
 }
 
@@ -30,10 +27,7 @@ impl Encode for String {
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        let len = self.len() as u16;
-        writer.write_u16::<BigEndian>(len)?;
-        writer.write_all(self.as_bytes())?;
-        Ok(())
+        encode_binary_data(self.as_bytes(), writer)
     }
 }
 
@@ -53,6 +47,17 @@ impl Encode for u16 {
     }
 }
 
+impl<T> Encode for &T
+    where T: Encode + ?Sized {
+    fn encoded_length(&self) -> u32 {
+        (*self).encoded_length()
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self).encode(writer)
+    }
+}
+
 impl<T> Encode for Option<T>
     where T: Encode {
     fn encoded_length(&self) -> u32 {
@@ -84,6 +89,44 @@ impl<T> Encode for Vec<T>
     }
 }
 
+/// the number of bytes `value` takes up as a variable byte integer
+pub(crate) fn variable_byte_integer_length(value: u32) -> u32 {
+    match value {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    }
+}
+
+pub(crate) fn encode_variable_byte_integer<W: Write>(value: u32, writer: &mut W) -> io::Result<()> {
+    if value > 268_435_455 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "value too large for a variable byte integer"));
+    }
+
+    let mut value = value;
+    loop {
+        let mut encoded_byte = (value % 128) as u8;
+        value /= 128;
+        if value > 0 {
+            encoded_byte |= 128;
+        }
+        writer.write_u8(encoded_byte)?;
+        if value == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// binary data is prefixed with its length, just like a string
+pub(crate) fn encode_binary_data<W: Write>(data: &[u8], writer: &mut W) -> io::Result<()> {
+    if data.len() > usize::from(u16::MAX) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "string or binary data longer than 65,535 bytes"));
+    }
+    writer.write_u16::<BigEndian>(data.len() as u16)?;
+    writer.write_all(data)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,4 +152,24 @@ mod tests {
         assert_eq!(expected, data);
         assert_eq!(test_string.encoded_length(), 9);
     }
+
+    #[test]
+    fn it_encodes_variable_byte_integers() {
+        let cases: Vec<(u32, Vec<u8>)> = vec![
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (16_383, vec![0xff, 0x7f]),
+            (16_384, vec![0x80, 0x80, 0x01]),
+            (2_097_152, vec![0x80, 0x80, 0x80, 0x01]),
+            (268_435_455, vec![0xff, 0xff, 0xff, 0x7f]),
+        ];
+        for (value, expected) in cases {
+            let mut data: Vec<u8> = Vec::new();
+            encode_variable_byte_integer(value, &mut data).unwrap();
+            assert_eq!(data, expected);
+            assert_eq!(variable_byte_integer_length(value), expected.len() as u32);
+        }
+        assert!(encode_variable_byte_integer(268_435_456, &mut Vec::new()).is_err());
+    }
 }
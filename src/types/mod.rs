@@ -4,6 +4,12 @@ mod publish;
 mod subscribe;
 mod suback;
 mod unsubscribe;
+mod ack;
+mod disconnect;
+mod auth;
+mod unsuback;
+mod properties;
+mod reason;
 mod packet;
 pub use self::connect::*;
 pub use self::connack::*;
@@ -11,6 +17,12 @@ pub use self::publish::*;
 pub use self::subscribe::*;
 pub use self::suback::*;
 pub use self::unsubscribe::*;
+pub use self::ack::*;
+pub use self::disconnect::*;
+pub use self::auth::*;
+pub use self::unsuback::*;
+pub use self::properties::*;
+pub use self::reason::*;
 pub use self::packet::*;
 
 mod decoding;
@@ -1,47 +1,51 @@
 use super::*;
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 use std::io;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct PacketIdentifier(pub u16);
 
-#[derive(Debug, Eq, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Qos {
     AtMostOnce,
     AtLeastOnce,
     ExactlyOnce
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub struct Header {
     pub packet_type: u8,
     pub flags: u8,
     pub remaining_length: u32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ReturnCode {
     Success(Qos),
     Failure,
+    /// an MQTT 5 failure other than the unspecified error
+    Refused(ReasonCode),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Packet {
     Connect(ConnectData),
     Connack(ConnackData),
     Publish(PublishData),
-    Puback(PacketIdentifier),
-    Pubrec(PacketIdentifier),
-    Pubrel(PacketIdentifier),
-    Pubcomp(PacketIdentifier),
+    Puback(AckData),
+    Pubrec(AckData),
+    Pubrel(AckData),
+    Pubcomp(AckData),
     Subscribe(SubscribeData),
     Suback(SubackData),
     Unsubscribe(UnsubscribeData),
-    Unsuback(PacketIdentifier),
+    Unsuback(UnsubackData),
     Pingreq,
     Pingresp,
-    Disconnect,
+    Disconnect(DisconnectData),
+    Auth(AuthData),
 }
 
 impl Qos {
@@ -73,22 +77,25 @@ impl Decode for ReturnCode {
             0x01 => Ok(ReturnCode::Success(Qos::AtLeastOnce)),
             0x02 => Ok(ReturnCode::Success(Qos::ExactlyOnce)),
             0x80 => Ok(ReturnCode::Failure),
-            _ => Err(DecodingError::Malformed),
+            _ => Err(DecodingError::Malformed(Field::ReturnCode)),
         }
     }
 }
 
 impl Encode for ReturnCode {
+    fn encoded_length(&self) -> u32 {1}
+
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         match self {
             ReturnCode::Success(qos) => writer.write_u8(qos.encode()),
             ReturnCode::Failure => writer.write_u8(0x80),
+            ReturnCode::Refused(reason_code) => reason_code.encode(writer),
         }
     }
 }
 
 impl Decode for Header {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, Self::DecodingError> {
@@ -99,12 +106,24 @@ impl Decode for Header {
         let remaining_length = decode_remaining_length(reader)?;
         let header = Header {packet_type, flags, remaining_length};
 
+        // checked before anything gets allocated for the rest of the packet
+        if let Some(maximum) = state.max_packet_size {
+            let size = 1 + variable_byte_integer_length(remaining_length) + remaining_length;
+            if size > maximum {
+                return Err(DecodingError::PacketTooLarge { size, maximum });
+            }
+        }
+
         state.header = header;
         Ok(header)
     }
 }
 
 impl Encode for Header {
+    fn encoded_length(&self) -> u32 {
+        1 + variable_byte_integer_length(self.remaining_length)
+    }
+
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         writer.write_u8((self.packet_type << 4) | self.flags)?;
         encode_remaining_length(self.remaining_length, writer)
@@ -127,17 +146,20 @@ impl Header {
             Packet::Unsuback(data) => Header {flags: 0, remaining_length: data.encoded_length(), packet_type: 11},
             Packet::Pingreq => Header {flags: 0, remaining_length: 0, packet_type: 12},
             Packet::Pingresp => Header {flags: 0, remaining_length: 0, packet_type: 13},
-            Packet::Disconnect => Header {flags: 0, remaining_length: 0, packet_type: 14},
+            Packet::Disconnect(data) => Header {flags: 0, remaining_length: data.encoded_length(), packet_type: 14},
+            Packet::Auth(data) => Header {flags: 0, remaining_length: data.encoded_length(), packet_type: 15},
         }
     }
 }
 
 impl Decode for PacketIdentifier {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, _state: &mut Self::DecoderState) -> Result<Self, Self::DecodingError> {
         let value = reader.read_u16::<BigEndian>()?;
+        // [MQTT-2.3.1-1]
+        if value == 0 { return Err(DecodingError::Malformed(Field::PacketIdentifier)) };
         Ok(PacketIdentifier(value))
     }
 }
@@ -145,43 +167,39 @@ impl Decode for PacketIdentifier {
 impl Encode for PacketIdentifier {
     fn encoded_length(&self) -> u32 {2}
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.check_encodable()?;
         writer.write_u16::<BigEndian>(self.0)
     }
 }
 
-fn encode_remaining_length<W: Write>(remaining_length: u32, writer: &mut W) -> io::Result<()> {
-    let mut encoded_byte;
-    let mut value = remaining_length;
-
-    while value > 0 {
-        encoded_byte = remaining_length % 128;
-        value = remaining_length / 128;
-        if value > 0 {
-            encoded_byte |= 128;
-        }
-        writer.write_u8(encoded_byte as u8)?;
+impl PacketIdentifier {
+    /// [MQTT-2.3.1-1] packet identifiers are non-zero
+    pub(crate) fn check_encodable(self) -> io::Result<()> {
+        if self.0 == 0 { return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet identifier 0")) };
+        Ok(())
     }
-    Ok(())
+}
+
+fn encode_remaining_length<W: Write>(remaining_length: u32, writer: &mut W) -> io::Result<()> {
+    encode_variable_byte_integer(remaining_length, writer)
 }
 
 fn decode_remaining_length<R: Read>(reader: &mut R) -> Result<u32, DecodingError> {
-    let mut value: u32 = 0;
-    let mut multiplier: u32 = 1;
-    let mut next_byte = reader.read_u8()?;
-    while (next_byte & 128) != 0 {
-        value += u32::from(next_byte & 127) * multiplier;
-        multiplier *= 128;
-        if multiplier > 128 * 128 * 128 {
-            return Err(DecodingError::Malformed);
-        }
-        next_byte = reader.read_u8()?;
+    match decode_variable_byte_integer(reader) {
+        Err(DecodingError::Malformed(Field::VariableByteInteger)) => Err(DecodingError::Malformed(Field::RemainingLength)),
+        result => result,
     }
-    Ok(value)
 }
 
 impl Encode for Packet {
+    /// the length of the whole packet, fixed header included
+    fn encoded_length(&self) -> u32 {
+        let header = Header::for_packet(self);
+        header.encoded_length() + header.remaining_length
+    }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.check_encodable()?;
         let header = Header::for_packet(self);
         match self {
             Packet::Connect(data) => Packet::encode_with_header(writer, data, header),
@@ -197,74 +215,142 @@ impl Encode for Packet {
             Packet::Unsuback(data) => Packet::encode_with_header(writer, data, header),
             Packet::Pingreq => header.encode(writer),
             Packet::Pingresp => header.encode(writer),
-            Packet::Disconnect => header.encode(writer),
+            Packet::Disconnect(data) => Packet::encode_with_header(writer, data, header),
+            Packet::Auth(data) => Packet::encode_with_header(writer, data, header),
         }
     }
 }
 
 impl Packet {
 
+    /// Refuse a packet that can't be sent as it is, before any of it is written.
+    fn check_encodable(&self) -> io::Result<()> {
+        match self {
+            Packet::Connack(data) => data.check_encodable(),
+            Packet::Publish(data) => data.check_encodable(),
+            Packet::Puback(data) | Packet::Pubrec(data) | Packet::Pubrel(data) | Packet::Pubcomp(data) => data.packet_identifier().check_encodable(),
+            Packet::Subscribe(data) => data.check_encodable(),
+            Packet::Suback(data) => data.packet_identifier().check_encodable(),
+            Packet::Unsubscribe(data) => data.check_encodable(),
+            Packet::Unsuback(data) => data.packet_identifier().check_encodable(),
+            _ => Ok(()),
+        }
+    }
+
     fn encode_with_header<W: Write, E: Encode>(writer: &mut W, encodable: &E, header: Header) -> io::Result<()> {
         header.encode(writer)?;
         encodable.encode(writer)
     }
 
-    fn decode_connect<R: Read>(reader: &mut R, state: &mut DecodingInfo) -> Result<Self, DecodingError> {
+    fn decode_connect<R: Read>(reader: &mut R, state: &mut DecodingContext) -> Result<Self, DecodingError> {
         let data = ConnectData::decode(reader, state)?;
         Ok(Packet::Connect(data))
     }
-    fn decode_connack<R: Read>(reader: &mut R, state: &mut DecodingInfo) -> Result<Self, DecodingError> {
+    fn decode_connack<R: Read>(reader: &mut R, state: &mut DecodingContext) -> Result<Self, DecodingError> {
         let data = ConnackData::decode(reader, state)?;
         Ok(Packet::Connack(data))
     }
-    fn decode_publish<R: Read>(reader: &mut R, state: &mut DecodingInfo) -> Result<Self, DecodingError> {
+    fn decode_publish<R: Read>(reader: &mut R, state: &mut DecodingContext) -> Result<Self, DecodingError> {
         let data = PublishData::decode(reader, state)?;
         Ok(Packet::Publish(data))
     }
-    fn decode_subscribe<R: Read>(reader: &mut R, state: &mut DecodingInfo) -> Result<Self, DecodingError> {
+    fn decode_subscribe<R: Read>(reader: &mut R, state: &mut DecodingContext) -> Result<Self, DecodingError> {
         let data = SubscribeData::decode(reader, state)?;
         Ok(Packet::Subscribe(data))
     }
-    fn decode_suback<R: Read>(reader: &mut R, state: &mut DecodingInfo) -> Result<Self, DecodingError> {
+    fn decode_suback<R: Read>(reader: &mut R, state: &mut DecodingContext) -> Result<Self, DecodingError> {
         let data = SubackData::decode(reader, state)?;
         Ok(Packet::Suback(data))
     }
-    fn decode_unsubscribe<R: Read>(reader: &mut R, state: &mut DecodingInfo) -> Result<Self, DecodingError> {
+    fn decode_unsubscribe<R: Read>(reader: &mut R, state: &mut DecodingContext) -> Result<Self, DecodingError> {
         let data = UnsubscribeData::decode(reader, state)?;
         Ok(Packet::Unsubscribe(data))
     }
 }
 
 impl Decode for Packet {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, Self::DecodingError> {
-        let header = Header::decode(reader, state)?;
-        match header.packet_type {
+        let mut reader = CountingReader::new(reader);
+        Header::decode(&mut reader, state)?;
+        let header_length = reader.count();
+        Packet::decode_body(&mut reader, state)
+            .and_then(|packet| {
+                let body_length = reader.count() - header_length;
+                state.check_body_length(body_length)?;
+                // skip the trailing data lenient decoding ignores
+                io::copy(&mut (&mut reader).take(u64::from(state.header.remaining_length) - body_length), &mut io::sink())?;
+                Ok(packet)
+            })
+            .map_err(|err| err.in_packet(state.header.packet_type, reader.count()))
+    }
+}
+
+/// A packet decoded from a complete frame, borrowing from it where that avoids a copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketRef<'a> {
+    Publish(PublishRef<'a>),
+    Other(Packet),
+}
+
+impl<'a> PacketRef<'a> {
+    /// Decode a complete frame, fixed header included.
+    pub fn decode(frame: &'a [u8], state: &mut DecodingContext) -> Result<PacketRef<'a>, DecodingError> {
+        let mut cursor = Cursor::new(frame);
+        let header = Header::decode(&mut cursor, state).map_err(DecodingError::within_frame)?;
+        let start = cursor.position();
+        let body = frame.get(start as usize..start as usize + header.remaining_length as usize)
+            .ok_or_else(|| DecodingError::Malformed(Field::RemainingLength).in_packet(header.packet_type, start))?;
+        if header.packet_type == 3 {
+            let publish_ref = PublishRef::decode(body, state).map_err(|err| err.in_packet(3, start))?;
+            return Ok(PacketRef::Publish(publish_ref));
+        }
+        let mut cursor = Cursor::new(body);
+        let packet = Packet::decode_body(&mut cursor, state)
+            .and_then(|packet| state.check_body_length(cursor.position()).map(|()| packet))
+            .map_err(|err| err.within_frame().in_packet(header.packet_type, start + cursor.position()))?;
+        Ok(PacketRef::Other(packet))
+    }
+
+    /// copy whatever borrows from the frame
+    pub fn into_owned(self) -> Packet {
+        match self {
+            PacketRef::Publish(publish_ref) => Packet::Publish(publish_ref.into_owned()),
+            PacketRef::Other(packet) => packet,
+        }
+    }
+}
+
+impl Packet {
+    /// decode everything after the fixed header in `state.header`
+    fn decode_body<R: Read>(reader: &mut R, state: &mut DecodingContext) -> Result<Packet, DecodingError> {
+        match state.header.packet_type {
             1 => Packet::decode_connect(reader, state),
             2 => Packet::decode_connack(reader, state),
             3 => Packet::decode_publish(reader, state),
-            4 => Ok(Packet::Puback(PacketIdentifier::decode(reader, state)?)),
-            5 => Ok(Packet::Pubrec(PacketIdentifier::decode(reader, state)?)),
-            6 => Ok(Packet::Pubrel(PacketIdentifier::decode(reader, state)?)),
-            7 => Ok(Packet::Pubcomp(PacketIdentifier::decode(reader, state)?)),
+            4 => Ok(Packet::Puback(AckData::decode(reader, state)?)),
+            5 => Ok(Packet::Pubrec(AckData::decode(reader, state)?)),
+            6 => Ok(Packet::Pubrel(AckData::decode(reader, state)?)),
+            7 => Ok(Packet::Pubcomp(AckData::decode(reader, state)?)),
             8 => Packet::decode_subscribe(reader, state),
             9 => Packet::decode_suback(reader, state),
             10 => Packet::decode_unsubscribe(reader, state),
-            11 => Ok(Packet::Unsuback(PacketIdentifier::decode(reader, state)?)),
-            12 => Ok(Packet::Pingreq),
-            13 => Ok(Packet::Pingresp),
-            14 => Ok(Packet::Disconnect),
+            11 => Ok(Packet::Unsuback(UnsubackData::decode(reader, state)?)),
+            12 => state.check_header_flags().map(|()| Packet::Pingreq),
+            13 => state.check_header_flags().map(|()| Packet::Pingresp),
+            14 => Ok(Packet::Disconnect(DisconnectData::decode(reader, state)?)),
+            15 => Ok(Packet::Auth(AuthData::decode(reader, state)?)),
             _ => Err(DecodingError::Forbidden),
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
 
     #[test]
     fn decoding_qos() {
@@ -316,5 +402,92 @@ mod tests {
         ReturnCode::Failure.encode(&mut data).unwrap();
         assert_eq!(data, vec![0x80]);
     }
+
+    #[test]
+    fn decoding_headers_above_the_maximum_packet_size() {
+        let mut state = DecodingContext { max_packet_size: Some(130), ..DecodingContext::default() };
+        let header = Header::decode(&mut Cursor::new(vec![0x30, 0x7f]), &mut state).unwrap();
+        assert_eq!(header.remaining_length, 127);
+
+        match Header::decode(&mut Cursor::new(vec![0x30, 0x80, 0x01]), &mut state) {
+            Err(DecodingError::PacketTooLarge { size, maximum }) => {
+                assert_eq!(size, 131);
+                assert_eq!(maximum, 130);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decoding_remaining_length() {
+        assert_eq!(decode_remaining_length(&mut Cursor::new(vec![0x00])).unwrap(), 0);
+        assert_eq!(decode_remaining_length(&mut Cursor::new(vec![0x7f])).unwrap(), 127);
+        assert_eq!(decode_remaining_length(&mut Cursor::new(vec![0x80, 0x01])).unwrap(), 128);
+        assert_eq!(decode_remaining_length(&mut Cursor::new(vec![0xff, 0xff, 0xff, 0x7f])).unwrap(), 268_435_455);
+        assert!(decode_remaining_length(&mut Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0x01])).is_err());
+    }
+
+    #[test]
+    fn locating_decoding_errors() {
+        let mut state = DecodingContext::default();
+        let data = vec![0x10, 12, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b0000_0011, 0, 10, 0, 0];
+        let err = Packet::decode(&mut Cursor::new(data), &mut state).unwrap_err();
+        assert!(matches!(err.root_cause(), DecodingError::ReservedBits(Field::ConnectFlags)));
+        assert_eq!(err.to_string(), "CONNECT at byte 10: reserved bits set in connect flags");
+
+        let err = Packet::decode(&mut Cursor::new(vec![0x36, 3, 0, 1, b'a']), &mut state).unwrap_err();
+        assert_eq!(err.to_string(), "PUBLISH at byte 2: invalid QoS 3");
+
+        let err = Packet::decode(&mut Cursor::new(vec![0x30, 0xff, 0xff, 0xff, 0xff]), &mut state).unwrap_err();
+        assert_eq!(err.to_string(), "malformed remaining length");
+    }
+
+    #[test]
+    fn locating_decoding_errors_in_borrowed_packets() {
+        let mut state = DecodingContext::default();
+        let err = PacketRef::decode(&[0x30, 4, 0, 2, b'a', 0xff], &mut state).unwrap_err();
+        assert_eq!(err.offset(), Some(4));
+        assert!(matches!(err.root_cause(), DecodingError::Utf8Error(_)));
+
+        let err = PacketRef::decode(&[0x40, 1, 0], &mut state).unwrap_err();
+        assert_eq!(err.to_string(), "PUBACK at byte 2: malformed remaining length");
+    }
+
+    #[test]
+    fn checking_reserved_header_flags() {
+        let frames = [
+            vec![0x21, 2, 0, 0], vec![0x41, 2, 0, 1], vec![0x52, 2, 0, 1], vec![0x60, 2, 0, 1], vec![0x78, 2, 0, 1],
+            vec![0x80, 6, 0, 1, 0, 1, b'a', 0], vec![0x94, 3, 0, 1, 0], vec![0xa0, 5, 0, 1, 0, 1, b'a'],
+            vec![0xb1, 2, 0, 1], vec![0xc1, 0], vec![0xd2, 0], vec![0xe8, 0],
+        ];
+        let mut state = DecodingContext::default();
+        for frame in &frames {
+            let err = Packet::decode(&mut Cursor::new(frame), &mut state).unwrap_err();
+            assert!(matches!(err.root_cause(), DecodingError::ReservedBits(Field::HeaderFlags)), "{:?}", frame);
+        }
+        assert_eq!(Packet::decode(&mut Cursor::new(vec![0x62, 2, 0, 1]), &mut state).unwrap(), Packet::Pubrel(AckData::new(PacketIdentifier(1))));
+
+        // every one of them is ignored when lenient
+        state.strictness = Strictness::Lenient;
+        for frame in &frames {
+            assert!(Packet::decode(&mut Cursor::new(frame), &mut state).is_ok(), "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn rejecting_trailing_data() {
+        // a PINGREQ with a body, followed by a PINGRESP
+        let data = vec![0xc0, 1, 0, 0xd0, 0];
+        let mut state = DecodingContext::default();
+        let err = Packet::decode(&mut Cursor::new(&data), &mut state).unwrap_err();
+        assert!(matches!(err.root_cause(), DecodingError::Malformed(Field::RemainingLength)));
+        assert_eq!(err.offset(), Some(2));
+
+        // skipped when lenient, so the next packet decodes
+        state.strictness = Strictness::Lenient;
+        let mut cursor = Cursor::new(&data);
+        assert_eq!(Packet::decode(&mut cursor, &mut state).unwrap(), Packet::Pingreq);
+        assert_eq!(Packet::decode(&mut cursor, &mut state).unwrap(), Packet::Pingresp);
+    }
 }
 
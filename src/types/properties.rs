@@ -0,0 +1,293 @@
+use super::*;
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+
+/// A single MQTT 5 property, see section 2.2.2.2 of the specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Property {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(String),
+    ResponseTopic(String),
+    CorrelationData(Vec<u8>),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(String),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(String),
+    AuthenticationData(Vec<u8>),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(String),
+    ServerReference(String),
+    ReasonString(String),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQos(u8),
+    RetainAvailable(u8),
+    UserProperty(String, String),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+/// The properties section of an MQTT 5 packet.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Properties(pub Vec<Property>);
+
+impl Property {
+    pub fn identifier(&self) -> u8 {
+        match self {
+            Property::PayloadFormatIndicator(_) => 0x01,
+            Property::MessageExpiryInterval(_) => 0x02,
+            Property::ContentType(_) => 0x03,
+            Property::ResponseTopic(_) => 0x08,
+            Property::CorrelationData(_) => 0x09,
+            Property::SubscriptionIdentifier(_) => 0x0B,
+            Property::SessionExpiryInterval(_) => 0x11,
+            Property::AssignedClientIdentifier(_) => 0x12,
+            Property::ServerKeepAlive(_) => 0x13,
+            Property::AuthenticationMethod(_) => 0x15,
+            Property::AuthenticationData(_) => 0x16,
+            Property::RequestProblemInformation(_) => 0x17,
+            Property::WillDelayInterval(_) => 0x18,
+            Property::RequestResponseInformation(_) => 0x19,
+            Property::ResponseInformation(_) => 0x1A,
+            Property::ServerReference(_) => 0x1C,
+            Property::ReasonString(_) => 0x1F,
+            Property::ReceiveMaximum(_) => 0x21,
+            Property::TopicAliasMaximum(_) => 0x22,
+            Property::TopicAlias(_) => 0x23,
+            Property::MaximumQos(_) => 0x24,
+            Property::RetainAvailable(_) => 0x25,
+            Property::UserProperty(_, _) => 0x26,
+            Property::MaximumPacketSize(_) => 0x27,
+            Property::WildcardSubscriptionAvailable(_) => 0x28,
+            Property::SubscriptionIdentifierAvailable(_) => 0x29,
+            Property::SharedSubscriptionAvailable(_) => 0x2A,
+        }
+    }
+}
+
+impl Decode for Property {
+    type DecoderState=();
+    type DecodingError=DecodingError;
+
+    fn decode<R: Read>(reader: &mut R, _state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+        let identifier = decode_variable_byte_integer(reader)?;
+        let property = match identifier {
+            0x01 => Property::PayloadFormatIndicator(reader.read_u8()?),
+            0x02 => Property::MessageExpiryInterval(reader.read_u32::<BigEndian>()?),
+            0x03 => Property::ContentType(String::decode(reader, &mut ())?),
+            0x08 => Property::ResponseTopic(String::decode(reader, &mut ())?),
+            0x09 => Property::CorrelationData(decode_binary_data(reader)?),
+            0x0B => Property::SubscriptionIdentifier(decode_variable_byte_integer(reader)?),
+            0x11 => Property::SessionExpiryInterval(reader.read_u32::<BigEndian>()?),
+            0x12 => Property::AssignedClientIdentifier(String::decode(reader, &mut ())?),
+            0x13 => Property::ServerKeepAlive(reader.read_u16::<BigEndian>()?),
+            0x15 => Property::AuthenticationMethod(String::decode(reader, &mut ())?),
+            0x16 => Property::AuthenticationData(decode_binary_data(reader)?),
+            0x17 => Property::RequestProblemInformation(reader.read_u8()?),
+            0x18 => Property::WillDelayInterval(reader.read_u32::<BigEndian>()?),
+            0x19 => Property::RequestResponseInformation(reader.read_u8()?),
+            0x1A => Property::ResponseInformation(String::decode(reader, &mut ())?),
+            0x1C => Property::ServerReference(String::decode(reader, &mut ())?),
+            0x1F => Property::ReasonString(String::decode(reader, &mut ())?),
+            0x21 => Property::ReceiveMaximum(reader.read_u16::<BigEndian>()?),
+            0x22 => Property::TopicAliasMaximum(reader.read_u16::<BigEndian>()?),
+            0x23 => Property::TopicAlias(reader.read_u16::<BigEndian>()?),
+            0x24 => Property::MaximumQos(reader.read_u8()?),
+            0x25 => Property::RetainAvailable(reader.read_u8()?),
+            0x26 => Property::UserProperty(String::decode(reader, &mut ())?, String::decode(reader, &mut ())?),
+            0x27 => Property::MaximumPacketSize(reader.read_u32::<BigEndian>()?),
+            0x28 => Property::WildcardSubscriptionAvailable(reader.read_u8()?),
+            0x29 => Property::SubscriptionIdentifierAvailable(reader.read_u8()?),
+            0x2A => Property::SharedSubscriptionAvailable(reader.read_u8()?),
+            _ => return Err(DecodingError::Malformed(Field::PropertyIdentifier)),
+        };
+        Ok(property)
+    }
+}
+
+impl Encode for Property {
+    fn encoded_length(&self) -> u32 {
+        let value_length = match self {
+            Property::PayloadFormatIndicator(_) |
+            Property::RequestProblemInformation(_) |
+            Property::RequestResponseInformation(_) |
+            Property::MaximumQos(_) |
+            Property::RetainAvailable(_) |
+            Property::WildcardSubscriptionAvailable(_) |
+            Property::SubscriptionIdentifierAvailable(_) |
+            Property::SharedSubscriptionAvailable(_) => 1,
+            Property::ServerKeepAlive(_) |
+            Property::ReceiveMaximum(_) |
+            Property::TopicAliasMaximum(_) |
+            Property::TopicAlias(_) => 2,
+            Property::MessageExpiryInterval(_) |
+            Property::SessionExpiryInterval(_) |
+            Property::WillDelayInterval(_) |
+            Property::MaximumPacketSize(_) => 4,
+            Property::SubscriptionIdentifier(value) => variable_byte_integer_length(*value),
+            Property::ContentType(value) |
+            Property::ResponseTopic(value) |
+            Property::AssignedClientIdentifier(value) |
+            Property::AuthenticationMethod(value) |
+            Property::ResponseInformation(value) |
+            Property::ServerReference(value) |
+            Property::ReasonString(value) => value.encoded_length(),
+            Property::CorrelationData(value) |
+            Property::AuthenticationData(value) => value.len() as u32 + 2,
+            Property::UserProperty(key, value) => key.encoded_length() + value.encoded_length(),
+        };
+        // all identifiers fit in a single byte
+        1 + value_length
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        encode_variable_byte_integer(u32::from(self.identifier()), writer)?;
+        match self {
+            Property::PayloadFormatIndicator(value) |
+            Property::RequestProblemInformation(value) |
+            Property::RequestResponseInformation(value) |
+            Property::MaximumQos(value) |
+            Property::RetainAvailable(value) |
+            Property::WildcardSubscriptionAvailable(value) |
+            Property::SubscriptionIdentifierAvailable(value) |
+            Property::SharedSubscriptionAvailable(value) => writer.write_u8(*value),
+            Property::ServerKeepAlive(value) |
+            Property::ReceiveMaximum(value) |
+            Property::TopicAliasMaximum(value) |
+            Property::TopicAlias(value) => writer.write_u16::<BigEndian>(*value),
+            Property::MessageExpiryInterval(value) |
+            Property::SessionExpiryInterval(value) |
+            Property::WillDelayInterval(value) |
+            Property::MaximumPacketSize(value) => writer.write_u32::<BigEndian>(*value),
+            Property::SubscriptionIdentifier(value) => encode_variable_byte_integer(*value, writer),
+            Property::ContentType(value) |
+            Property::ResponseTopic(value) |
+            Property::AssignedClientIdentifier(value) |
+            Property::AuthenticationMethod(value) |
+            Property::ResponseInformation(value) |
+            Property::ServerReference(value) |
+            Property::ReasonString(value) => value.encode(writer),
+            Property::CorrelationData(value) |
+            Property::AuthenticationData(value) => encode_binary_data(value, writer),
+            Property::UserProperty(key, value) => {
+                key.encode(writer)?;
+                value.encode(writer)
+            },
+        }
+    }
+}
+
+impl Properties {
+    pub fn new() -> Properties {
+        Properties::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Property> {
+        self.0.iter()
+    }
+
+    pub fn push(&mut self, property: Property) {
+        self.0.push(property)
+    }
+
+    /// length of the properties themselves, without the length prefix
+    fn properties_length(&self) -> u32 {
+        self.0.encoded_length()
+    }
+}
+
+impl Decode for Properties {
+    type DecoderState=();
+    type DecodingError=DecodingError;
+
+    fn decode<R: Read>(reader: &mut R, _state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+        let mut remaining_length = decode_variable_byte_integer(reader)?;
+        let mut properties = Vec::new();
+
+        while remaining_length > 0 {
+            let property = Property::decode(reader, &mut ())?;
+            remaining_length = remaining_length.checked_sub(property.encoded_length())
+                .ok_or(DecodingError::Malformed(Field::Properties))?;
+            properties.push(property);
+        }
+
+        Ok(Properties(properties))
+    }
+}
+
+impl Encode for Properties {
+    fn encoded_length(&self) -> u32 {
+        let properties_length = self.properties_length();
+        variable_byte_integer_length(properties_length) + properties_length
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        encode_variable_byte_integer(self.properties_length(), writer)?;
+        self.0.encode(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn decoding_empty_properties() {
+        let mut cursor = Cursor::new(vec![0]);
+        let properties = Properties::decode(&mut cursor, &mut ()).unwrap();
+        assert!(properties.is_empty());
+    }
+
+    #[test]
+    fn decoding_properties() {
+        let mut data: Vec<u8> = vec![15, 0x11, 0, 0, 0, 10, 0x26, 0, 1, b'k', 0, 1, b'v', 0x0B, 0x80, 0x01];
+        data.push(0xff);
+        let mut cursor = Cursor::new(data);
+        let properties = Properties::decode(&mut cursor, &mut ()).unwrap();
+        assert_eq!(properties, Properties(vec![
+            Property::SessionExpiryInterval(10),
+            Property::UserProperty(String::from("k"), String::from("v")),
+            Property::SubscriptionIdentifier(128),
+        ]));
+        // the trailing byte is not part of the properties
+        assert_eq!(cursor.position(), 16);
+    }
+
+    #[test]
+    fn decoding_unknown_properties() {
+        let mut cursor = Cursor::new(vec![2, 0x7f, 0]);
+        assert!(Properties::decode(&mut cursor, &mut ()).is_err());
+    }
+
+    #[test]
+    fn decoding_properties_overrunning_their_length() {
+        let mut cursor = Cursor::new(vec![3, 0x11, 0, 0, 0, 10]);
+        assert!(Properties::decode(&mut cursor, &mut ()).is_err());
+    }
+
+    #[test]
+    fn encoding_properties() {
+        let properties = Properties(vec![
+            Property::ReceiveMaximum(20),
+            Property::CorrelationData(vec![1, 2]),
+            Property::ContentType(String::from("a")),
+        ]);
+        let mut data: Vec<u8> = Vec::new();
+        properties.encode(&mut data).unwrap();
+        assert_eq!(data, vec![12, 0x21, 0, 20, 0x09, 0, 2, 1, 2, 0x03, 0, 1, b'a']);
+        assert_eq!(properties.encoded_length(), 13);
+    }
+}
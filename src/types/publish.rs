@@ -1,58 +1,452 @@
 use super::*;
+use crate::topic::validate_topic_name;
+use std::error::Error;
+use std::fmt;
 use std::io;
-use std::io::{Write, Read};
+use std::io::{Cursor, Write, Read};
+use std::str;
+use byteorder::{BigEndian, ByteOrder};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PublishData {
     qos: Qos,
     retain: bool,
     dup: bool,
     packet_identifier: Option<PacketIdentifier>,
     topic_name: String,
-    message: String,
+    properties: Option<Properties>,
+    payload: Vec<u8>,
 }
 
 impl Decode for PublishData {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
-        let flags = state.header.flags;
-        let dup = flags & 0b0000_1000 > 0;
-        let retain = flags & 0b0000_0001 > 0;
-        let qos = Qos::decode((flags & 0b0000_0110) >> 1).ok_or(DecodingError::Malformed)?;
+        let (qos, retain, dup) = decode_flags(state)?;
 
         let topic_name = String::decode(reader, &mut ())?;
-        let packet_identifier = if qos == Qos::AtLeastOnce || qos == Qos::ExactlyOnce {
-            Some(PacketIdentifier::decode(reader, state)?)
-        } else {
-            None
-        };
-        let message = String::decode(reader, &mut ())?;
-        Ok(PublishData { qos, retain, dup, packet_identifier, topic_name, message})
+        let (packet_identifier, properties) = decode_variable_header(reader, state, qos, &topic_name)?;
+
+        // the payload is whatever is left of the packet after the variable header
+        let variable_header_length = topic_name.encoded_length() +
+            packet_identifier.encoded_length() +
+            properties.encoded_length();
+        let payload_length = state.header.remaining_length.checked_sub(variable_header_length)
+            .ok_or(DecodingError::Malformed(Field::RemainingLength))?;
+
+        let mut payload = Vec::new();
+        reader.take(u64::from(payload_length)).read_to_end(&mut payload)?;
+        if payload.len() as u32 != payload_length {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(PublishData { qos, retain, dup, packet_identifier, topic_name, properties, payload})
+    }
+}
+
+fn decode_flags(state: &DecodingContext) -> Result<(Qos, bool, bool), DecodingError> {
+    let flags = state.header.flags;
+    let dup = flags & 0b0000_1000 > 0;
+    let retain = flags & 0b0000_0001 > 0;
+    let qos = (flags & 0b0000_0110) >> 1;
+    let qos = Qos::decode(qos).ok_or(DecodingError::InvalidQos(qos))?;
+    // [MQTT-3.3.1-2] a QoS 0 message is never redelivered, so the flag is dropped when lenient
+    if state.is_strict() && dup && qos == Qos::AtMostOnce { return Err(DecodingError::Malformed(Field::HeaderFlags)) };
+    Ok((qos, retain, dup && qos != Qos::AtMostOnce))
+}
+
+/// the part of the variable header that follows the topic name, which is checked along with it
+fn decode_variable_header<R: Read>(reader: &mut R, state: &mut DecodingContext, qos: Qos, topic_name: &str) -> Result<(Option<PacketIdentifier>, Option<Properties>), DecodingError> {
+    let packet_identifier = if qos == Qos::AtLeastOnce || qos == Qos::ExactlyOnce {
+        Some(PacketIdentifier::decode(reader, state)?)
+    } else {
+        None
+    };
+    let properties = if state.protocol_version >= ProtocolVersion::V5 {
+        Some(Properties::decode(reader, &mut ())?)
+    } else {
+        None
+    };
+    // topic aliases run from 1 up to the maximum this side announced
+    let topic_alias = properties.iter().flat_map(Properties::iter).filter_map(|property| match property {
+        Property::TopicAlias(topic_alias) => Some(*topic_alias),
+        _ => None,
+    }).next();
+    if let Some(topic_alias) = topic_alias {
+        if topic_alias == 0 || topic_alias > state.topic_alias_maximum { return Err(DecodingError::Malformed(Field::TopicAlias)) };
     }
+    // a topic alias can stand in for the topic name
+    if !(topic_name.is_empty() && topic_alias.is_some()) {
+        validate_topic_name(topic_name)?;
+    }
+    Ok((packet_identifier, properties))
 }
 
 impl Encode for PublishData {
 
     fn encoded_length(&self) -> u32 {
         self.topic_name.encoded_length() +
-        self.packet_identifier.encoded_length() +
-        self.message.encoded_length()
+        self.encoded_packet_identifier().encoded_length() +
+        self.properties.encoded_length() +
+        self.payload.len() as u32
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.check_encodable()?;
         self.topic_name.encode(writer)?;
-        self.packet_identifier.encode(writer)?;
-        self.message.encode(writer)
+        self.encoded_packet_identifier().encode(writer)?;
+        self.properties.encode(writer)?;
+        writer.write_all(&self.payload)
     }
 }
 impl PublishData {
+    pub fn builder<T: Into<String>, P: Into<Vec<u8>>>(topic_name: T, payload: P) -> PublishDataBuilder {
+        PublishDataBuilder {
+            data: PublishData {
+                qos: Qos::AtMostOnce,
+                retain: false,
+                dup: false,
+                packet_identifier: None,
+                topic_name: topic_name.into(),
+                properties: None,
+                payload: payload.into(),
+            }
+        }
+    }
+
+    pub fn qos(&self) -> Qos { self.qos }
+    pub fn retain(&self) -> bool { self.retain }
+    pub fn dup(&self) -> bool { self.dup }
+    pub fn packet_identifier(&self) -> Option<PacketIdentifier> { self.packet_identifier }
+    pub fn topic_name(&self) -> &str { &self.topic_name }
+    pub fn properties(&self) -> Option<&Properties> { self.properties.as_ref() }
+    pub fn payload(&self) -> &[u8] { &self.payload }
+    /// whether this message is delivered to subscriptions with `topic_filter`
+    pub fn matches(&self, topic_filter: &TopicFilter) -> bool { topic_filter.matches(&self.topic_name) }
+
+    /// the same message with the packet identifier it is sent with
+    pub fn with_packet_identifier(mut self, packet_identifier: PacketIdentifier) -> PublishData {
+        self.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    /// the same message flagged as a redelivery, for resending it unacknowledged
+    pub fn redelivery(mut self) -> PublishData {
+        self.dup = true;
+        self
+    }
+
     pub fn flags(&self) -> u8 {
         let mut flags = 0u8;
-        if self.dup { flags &= 0b0000_1000 };
-        if self.retain { flags &= 1 };
-        flags &= self.qos.encode() << 1;
+        if self.dup { flags |= 0b0000_1000 };
+        if self.retain { flags |= 1 };
+        flags |= self.qos.encode() << 1;
         flags
     }
+
+    /// Whether the packet identifier and the DUP flag go with the QoS, as they must to be sent.
+    pub fn check(&self) -> Result<(), PublishError> {
+        match (self.qos, self.packet_identifier) {
+            // [MQTT-3.3.1-2]
+            (Qos::AtMostOnce, _) if self.dup => Err(PublishError::DuplicateAtMostOnce),
+            // [MQTT-2.3.1-5]
+            (Qos::AtMostOnce, Some(_)) => Err(PublishError::UnexpectedPacketIdentifier),
+            (Qos::AtLeastOnce, None) | (Qos::ExactlyOnce, None) => Err(PublishError::MissingPacketIdentifier),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuse what can't be encoded as it is, before anything is written.
+    pub(crate) fn check_encodable(&self) -> io::Result<()> {
+        match self.check() {
+            // it is just left out
+            Ok(()) | Err(PublishError::UnexpectedPacketIdentifier) => {},
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidInput, err)),
+        }
+        self.encoded_packet_identifier().map_or(Ok(()), PacketIdentifier::check_encodable)
+    }
+
+    /// [MQTT-2.3.1-5] a QoS 0 message goes without its packet identifier
+    fn encoded_packet_identifier(&self) -> Option<PacketIdentifier> {
+        if self.qos == Qos::AtMostOnce { None } else { self.packet_identifier }
+    }
+}
+
+/// A PUBLISH whose QoS, packet identifier and DUP flag don't go together.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PublishError {
+    /// QoS 1 and 2 messages need a packet identifier
+    MissingPacketIdentifier,
+    /// QoS 0 messages have none
+    UnexpectedPacketIdentifier,
+    /// QoS 0 messages are never redelivered
+    DuplicateAtMostOnce,
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PublishError::MissingPacketIdentifier => write!(f, "a QoS 1 or 2 PUBLISH needs a packet identifier"),
+            PublishError::UnexpectedPacketIdentifier => write!(f, "a QoS 0 PUBLISH has no packet identifier"),
+            PublishError::DuplicateAtMostOnce => write!(f, "a QoS 0 PUBLISH can't be a duplicate"),
+        }
+    }
+}
+
+impl Error for PublishError {}
+
+/// A PUBLISH whose topic name and payload borrow from the receive buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishRef<'a> {
+    qos: Qos,
+    retain: bool,
+    dup: bool,
+    packet_identifier: Option<PacketIdentifier>,
+    topic_name: &'a str,
+    properties: Option<Properties>,
+    payload: &'a [u8],
+}
+
+impl<'a> PublishRef<'a> {
+    /// Decode the body of a PUBLISH, which must be exactly `state.header.remaining_length` long.
+    ///
+    /// Error offsets count from the start of the body.
+    pub fn decode(body: &'a [u8], state: &mut DecodingContext) -> Result<PublishRef<'a>, DecodingError> {
+        if body.len() != state.header.remaining_length as usize { return Err(DecodingError::Malformed(Field::RemainingLength)) };
+        let (qos, retain, dup) = decode_flags(state).map_err(|err| err.in_packet(3, 0))?;
+
+        if body.len() < 2 { return Err(DecodingError::Malformed(Field::RemainingLength).in_packet(3, 0)) };
+        let topic_end = 2 + BigEndian::read_u16(body) as usize;
+        let topic_name = body.get(2..topic_end).ok_or_else(|| DecodingError::Malformed(Field::RemainingLength).in_packet(3, 2))?;
+        let topic_name = str::from_utf8(topic_name).map_err(|err| DecodingError::from(err).in_packet(3, 2))?;
+
+        let mut cursor = Cursor::new(&body[topic_end..]);
+        let (packet_identifier, properties) = decode_variable_header(&mut cursor, state, qos, topic_name)
+            .map_err(|err| err.within_frame().in_packet(3, topic_end as u64 + cursor.position()))?;
+        let payload = &body[topic_end + cursor.position() as usize..];
+
+        Ok(PublishRef { qos, retain, dup, packet_identifier, topic_name, properties, payload })
+    }
+
+    pub fn qos(&self) -> Qos { self.qos }
+    pub fn retain(&self) -> bool { self.retain }
+    pub fn dup(&self) -> bool { self.dup }
+    pub fn packet_identifier(&self) -> Option<PacketIdentifier> { self.packet_identifier }
+    pub fn topic_name(&self) -> &'a str { self.topic_name }
+    pub fn properties(&self) -> Option<&Properties> { self.properties.as_ref() }
+    pub fn payload(&self) -> &'a [u8] { self.payload }
+    /// whether this message is delivered to subscriptions with `topic_filter`
+    pub fn matches(&self, topic_filter: &TopicFilter) -> bool { topic_filter.matches(self.topic_name) }
+
+    /// copy the topic name and payload out of the receive buffer
+    pub fn into_owned(self) -> PublishData {
+        PublishData {
+            qos: self.qos,
+            retain: self.retain,
+            dup: self.dup,
+            packet_identifier: self.packet_identifier,
+            topic_name: self.topic_name.to_owned(),
+            properties: self.properties,
+            payload: self.payload.to_vec(),
+        }
+    }
+}
+
+/// Builds a `PublishData`, defaulting to QoS 0 without the retain or dup flags.
+#[derive(Debug)]
+pub struct PublishDataBuilder {
+    data: PublishData,
+}
+
+impl PublishDataBuilder {
+    /// QoS 1 and 2 messages also need a packet identifier.
+    pub fn qos(mut self, qos: Qos) -> Self {
+        self.data.qos = qos;
+        self
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: PacketIdentifier) -> Self {
+        self.data.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    pub fn retain(mut self, retain: bool) -> Self {
+        self.data.retain = retain;
+        self
+    }
+
+    pub fn dup(mut self, dup: bool) -> Self {
+        self.data.dup = dup;
+        self
+    }
+
+    /// turns this into an MQTT 5 PUBLISH
+    pub fn properties(mut self, properties: Properties) -> Self {
+        self.data.properties = Some(properties);
+        self
+    }
+
+    /// Fails unless QoS 1 and 2 messages have a packet identifier and QoS 0 messages
+    /// have neither a packet identifier nor the DUP flag.
+    pub fn build(self) -> Result<PublishData, PublishError> {
+        self.data.check()?;
+        Ok(self.data)
+    }
+
+    /// For the clients, which give a QoS 1 or 2 message its packet identifier once one is free.
+    pub(crate) fn build_unchecked(self) -> PublishData {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::types::Header;
+    use crate::topic::TopicError;
+
+    #[test]
+    fn building_publish_data() {
+        let publish_data = PublishData::builder("a/b", "testing")
+            .qos(Qos::AtLeastOnce)
+            .packet_identifier(PacketIdentifier(10))
+            .retain(true)
+            .build().unwrap();
+
+        assert_eq!(publish_data.qos(), Qos::AtLeastOnce);
+        assert!(publish_data.retain());
+        assert!(!publish_data.dup());
+        assert_eq!(publish_data.packet_identifier(), Some(PacketIdentifier(10)));
+        assert_eq!(publish_data.topic_name(), "a/b");
+        assert_eq!(publish_data.payload(), b"testing");
+    }
+
+    #[test]
+    fn matching_topic_filters() {
+        let publish_data = PublishData::builder("$SYS/uptime", "1").build().unwrap();
+        assert!(publish_data.matches(&TopicFilter::new("$SYS/+", Qos::AtMostOnce)));
+        assert!(!publish_data.matches(&TopicFilter::new("#", Qos::AtMostOnce)));
+    }
+
+    #[test]
+    fn decoding_binary_payload() {
+        let header = Header { packet_type: 3, flags: 0b0000_0010, remaining_length: 10 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 3, b'a', b'/', b'b', 0, 7, 0xff, 0x00, 0x80]);
+        let publish_data = PublishData::decode(&mut cursor, &mut state).unwrap();
+
+        assert_eq!(publish_data.qos(), Qos::AtLeastOnce);
+        assert_eq!(publish_data.topic_name(), "a/b");
+        assert_eq!(publish_data.packet_identifier(), Some(PacketIdentifier(7)));
+        assert_eq!(publish_data.payload(), &[0xff, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn decoding_empty_payload() {
+        let header = Header { packet_type: 3, flags: 0, remaining_length: 5 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 3, b'a', b'/', b'b']);
+        let publish_data = PublishData::decode(&mut cursor, &mut state).unwrap();
+        assert!(publish_data.payload().is_empty());
+    }
+
+    #[test]
+    fn decoding_truncated_payload() {
+        let header = Header { packet_type: 3, flags: 0, remaining_length: 10 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 3, b'a', b'/', b'b', 1]);
+        assert!(PublishData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_borrowed_publish_data() {
+        let header = Header { packet_type: 3, flags: 0b0000_0011, remaining_length: 9 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let body = vec![0, 3, b'a', b'/', b'b', 0, 7, 0xff, 0x00];
+        let publish_ref = PublishRef::decode(&body, &mut state).unwrap();
+
+        assert_eq!(publish_ref.qos(), Qos::AtLeastOnce);
+        assert!(publish_ref.retain());
+        assert_eq!(publish_ref.packet_identifier(), Some(PacketIdentifier(7)));
+        assert_eq!(publish_ref.topic_name(), "a/b");
+        assert_eq!(publish_ref.payload(), &[0xff, 0x00]);
+        assert_eq!(publish_ref.payload().as_ptr(), body[7..].as_ptr());
+
+        let publish_data = publish_ref.into_owned();
+        assert_eq!(publish_data.topic_name(), "a/b");
+        assert_eq!(publish_data.payload(), &[0xff, 0x00]);
+    }
+
+    #[test]
+    fn decoding_truncated_borrowed_publish_data() {
+        let header = Header { packet_type: 3, flags: 0b0000_0010, remaining_length: 6 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        assert!(PublishRef::decode(&[0, 3, b'a', b'/', b'b', 0], &mut state).is_err());
+
+        state.header.remaining_length = 4;
+        assert!(PublishRef::decode(&[0, 3, b'a', b'/'], &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_wildcards_in_topic_names() {
+        let header = Header { packet_type: 3, flags: 0, remaining_length: 5 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 3, b'a', b'/', b'+']);
+        let err = PublishData::decode(&mut cursor, &mut state).unwrap_err();
+        assert!(matches!(err, DecodingError::InvalidTopic(TopicError::WildcardInTopicName)));
+        assert!(PublishRef::decode(&[0, 3, b'a', b'/', b'+'], &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_empty_topic_names() {
+        let header = Header { packet_type: 3, flags: 0, remaining_length: 2 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        assert!(PublishData::decode(&mut Cursor::new(vec![0, 0]), &mut state).is_err());
+
+        // unless a topic alias stands in for it
+        let header = Header { packet_type: 3, flags: 0, remaining_length: 6 };
+        let mut state = DecodingContext { header, topic_alias_maximum: 1, ..DecodingContext::new(ProtocolVersion::V5) };
+        let publish_data = PublishData::decode(&mut Cursor::new(vec![0, 0, 3, 0x23, 0, 1]), &mut state).unwrap();
+        assert_eq!(publish_data.topic_name(), "");
+    }
+
+    #[test]
+    fn encoding_binary_payload() {
+        let publish_data = PublishData::builder("a/b", vec![0xff, 0x00]).build().unwrap();
+        let mut data: Vec<u8> = Vec::new();
+        publish_data.encode(&mut data).unwrap();
+        assert_eq!(data, vec![0, 3, b'a', b'/', b'b', 0xff, 0x00]);
+        assert_eq!(publish_data.encoded_length(), 7);
+    }
+
+    #[test]
+    fn decoding_v5_publish_data() {
+        let header = Header { packet_type: 3, flags: 0, remaining_length: 11 };
+        let mut state = DecodingContext { header, topic_alias_maximum: 1, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0, 3, b'a', b'/', b'b', 3, 0x23, 0, 1, 0xca, 0xfe]);
+        let publish_data = PublishData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(publish_data.properties(), Some(&Properties(vec![Property::TopicAlias(1)])));
+        assert_eq!(publish_data.payload(), &[0xca, 0xfe]);
+    }
+
+    #[test]
+    fn decoding_topic_aliases_above_the_maximum() {
+        let header = Header { packet_type: 3, flags: 0, remaining_length: 11 };
+        let mut state = DecodingContext { header, topic_alias_maximum: 1, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0, 3, b'a', b'/', b'b', 3, 0x23, 0, 2, 0xca, 0xfe]);
+        assert!(PublishData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn encoding_v5_publish_data() {
+        let publish_data = PublishData::builder("a/b", vec![0xca])
+            .properties(Properties::new())
+            .build().unwrap();
+        let mut data: Vec<u8> = Vec::new();
+        publish_data.encode(&mut data).unwrap();
+        assert_eq!(data, vec![0, 3, b'a', b'/', b'b', 0, 0xca]);
+        assert_eq!(publish_data.encoded_length(), 7);
+    }
 }
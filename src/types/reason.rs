@@ -0,0 +1,160 @@
+use super::*;
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+/// An MQTT 5 reason code. Several codes share a value, e.g. `SUCCESS`,
+/// `NORMAL_DISCONNECTION` and `GRANTED_QOS_0` are all `0x00`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ReasonCode(pub u8);
+
+impl ReasonCode {
+    pub const SUCCESS: ReasonCode = ReasonCode(0x00);
+    pub const NORMAL_DISCONNECTION: ReasonCode = ReasonCode(0x00);
+    pub const GRANTED_QOS_0: ReasonCode = ReasonCode(0x00);
+    pub const GRANTED_QOS_1: ReasonCode = ReasonCode(0x01);
+    pub const GRANTED_QOS_2: ReasonCode = ReasonCode(0x02);
+    pub const DISCONNECT_WITH_WILL_MESSAGE: ReasonCode = ReasonCode(0x04);
+    pub const NO_MATCHING_SUBSCRIBERS: ReasonCode = ReasonCode(0x10);
+    pub const NO_SUBSCRIPTION_EXISTED: ReasonCode = ReasonCode(0x11);
+    pub const CONTINUE_AUTHENTICATION: ReasonCode = ReasonCode(0x18);
+    pub const RE_AUTHENTICATE: ReasonCode = ReasonCode(0x19);
+    pub const UNSPECIFIED_ERROR: ReasonCode = ReasonCode(0x80);
+    pub const MALFORMED_PACKET: ReasonCode = ReasonCode(0x81);
+    pub const PROTOCOL_ERROR: ReasonCode = ReasonCode(0x82);
+    pub const IMPLEMENTATION_SPECIFIC_ERROR: ReasonCode = ReasonCode(0x83);
+    pub const UNSUPPORTED_PROTOCOL_VERSION: ReasonCode = ReasonCode(0x84);
+    pub const CLIENT_IDENTIFIER_NOT_VALID: ReasonCode = ReasonCode(0x85);
+    pub const BAD_USER_NAME_OR_PASSWORD: ReasonCode = ReasonCode(0x86);
+    pub const NOT_AUTHORIZED: ReasonCode = ReasonCode(0x87);
+    pub const SERVER_UNAVAILABLE: ReasonCode = ReasonCode(0x88);
+    pub const SERVER_BUSY: ReasonCode = ReasonCode(0x89);
+    pub const BANNED: ReasonCode = ReasonCode(0x8A);
+    pub const SERVER_SHUTTING_DOWN: ReasonCode = ReasonCode(0x8B);
+    pub const BAD_AUTHENTICATION_METHOD: ReasonCode = ReasonCode(0x8C);
+    pub const KEEP_ALIVE_TIMEOUT: ReasonCode = ReasonCode(0x8D);
+    pub const SESSION_TAKEN_OVER: ReasonCode = ReasonCode(0x8E);
+    pub const TOPIC_FILTER_INVALID: ReasonCode = ReasonCode(0x8F);
+    pub const TOPIC_NAME_INVALID: ReasonCode = ReasonCode(0x90);
+    pub const PACKET_IDENTIFIER_IN_USE: ReasonCode = ReasonCode(0x91);
+    pub const PACKET_IDENTIFIER_NOT_FOUND: ReasonCode = ReasonCode(0x92);
+    pub const RECEIVE_MAXIMUM_EXCEEDED: ReasonCode = ReasonCode(0x93);
+    pub const TOPIC_ALIAS_INVALID: ReasonCode = ReasonCode(0x94);
+    pub const PACKET_TOO_LARGE: ReasonCode = ReasonCode(0x95);
+    pub const MESSAGE_RATE_TOO_HIGH: ReasonCode = ReasonCode(0x96);
+    pub const QUOTA_EXCEEDED: ReasonCode = ReasonCode(0x97);
+    pub const ADMINISTRATIVE_ACTION: ReasonCode = ReasonCode(0x98);
+    pub const PAYLOAD_FORMAT_INVALID: ReasonCode = ReasonCode(0x99);
+    pub const RETAIN_NOT_SUPPORTED: ReasonCode = ReasonCode(0x9A);
+    pub const QOS_NOT_SUPPORTED: ReasonCode = ReasonCode(0x9B);
+    pub const USE_ANOTHER_SERVER: ReasonCode = ReasonCode(0x9C);
+    pub const SERVER_MOVED: ReasonCode = ReasonCode(0x9D);
+    pub const SHARED_SUBSCRIPTIONS_NOT_SUPPORTED: ReasonCode = ReasonCode(0x9E);
+    pub const CONNECTION_RATE_EXCEEDED: ReasonCode = ReasonCode(0x9F);
+    pub const MAXIMUM_CONNECT_TIME: ReasonCode = ReasonCode(0xA0);
+    pub const SUBSCRIPTION_IDENTIFIERS_NOT_SUPPORTED: ReasonCode = ReasonCode(0xA1);
+    pub const WILDCARD_SUBSCRIPTIONS_NOT_SUPPORTED: ReasonCode = ReasonCode(0xA2);
+
+    /// codes of 0x80 and up indicate failure
+    pub fn is_error(self) -> bool {
+        self.0 >= 0x80
+    }
+}
+
+impl Default for ReasonCode {
+    fn default() -> ReasonCode {
+        ReasonCode::SUCCESS
+    }
+}
+
+impl Decode for ReasonCode {
+    type DecoderState=();
+    type DecodingError=DecodingError;
+
+    fn decode<R: Read>(reader: &mut R, _state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+        Ok(ReasonCode(reader.read_u8()?))
+    }
+}
+
+impl Encode for ReasonCode {
+    fn encoded_length(&self) -> u32 { 1 }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u8(self.0)
+    }
+}
+
+// Acks, DISCONNECT and AUTH end in a reason code followed by properties, both of which
+// may be left out: no reason code means success and no properties means none were sent.
+
+pub(crate) fn reason_encoded_length(reason_code: ReasonCode, properties: &Properties) -> u32 {
+    if !properties.is_empty() {
+        reason_code.encoded_length() + properties.encoded_length()
+    } else if reason_code != ReasonCode::SUCCESS {
+        reason_code.encoded_length()
+    } else {
+        0
+    }
+}
+
+pub(crate) fn encode_reason<W: Write>(reason_code: ReasonCode, properties: &Properties, writer: &mut W) -> io::Result<()> {
+    if !properties.is_empty() {
+        reason_code.encode(writer)?;
+        properties.encode(writer)
+    } else if reason_code != ReasonCode::SUCCESS {
+        reason_code.encode(writer)
+    } else {
+        Ok(())
+    }
+}
+
+/// `remaining_length` is the number of bytes left in the packet
+pub(crate) fn decode_reason<R: Read>(reader: &mut R, remaining_length: u32) -> Result<(ReasonCode, Properties), DecodingError> {
+    let reason_code = if remaining_length > 0 {
+        ReasonCode::decode(reader, &mut ())?
+    } else {
+        ReasonCode::SUCCESS
+    };
+    let properties = if remaining_length > 1 {
+        Properties::decode(reader, &mut ())?
+    } else {
+        Properties::new()
+    };
+    Ok((reason_code, properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn decoding_omitted_reasons() {
+        let mut cursor = Cursor::new(vec![]);
+        let (reason_code, properties) = decode_reason(&mut cursor, 0).unwrap();
+        assert_eq!(reason_code, ReasonCode::SUCCESS);
+        assert!(properties.is_empty());
+
+        cursor = Cursor::new(vec![0x87]);
+        let (reason_code, properties) = decode_reason(&mut cursor, 1).unwrap();
+        assert_eq!(reason_code, ReasonCode::NOT_AUTHORIZED);
+        assert!(reason_code.is_error());
+        assert!(properties.is_empty());
+    }
+
+    #[test]
+    fn encoding_reasons() {
+        let mut data: Vec<u8> = Vec::new();
+        encode_reason(ReasonCode::SUCCESS, &Properties::new(), &mut data).unwrap();
+        assert!(data.is_empty());
+
+        encode_reason(ReasonCode::NO_MATCHING_SUBSCRIBERS, &Properties::new(), &mut data).unwrap();
+        assert_eq!(data, vec![0x10]);
+        data.clear();
+
+        let properties = Properties(vec![Property::ReasonString(String::from("a"))]);
+        encode_reason(ReasonCode::SUCCESS, &properties, &mut data).unwrap();
+        assert_eq!(data, vec![0x00, 4, 0x1F, 0, 1, b'a']);
+        assert_eq!(reason_encoded_length(ReasonCode::SUCCESS, &properties), 6);
+    }
+}
@@ -1,30 +1,50 @@
 use super::*;
 use std::io;
 use std::io::{Read, Write};
+use byteorder::ReadBytesExt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SubackData {
     packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
     return_codes: Vec<ReturnCode>
 }
 
 impl Decode for SubackData {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
-        if state.header.flags != 0 { return Err(DecodingError::Malformed) };
+        state.check_header_flags()?;
         let packet_identifier = PacketIdentifier::decode(reader, state)?;
+        let properties = if state.protocol_version >= ProtocolVersion::V5 {
+            Some(Properties::decode(reader, &mut ())?)
+        } else {
+            None
+        };
 
-        let mut remaining_bytes = state.header.remaining_length - 2;
-        let mut return_codes = Vec::with_capacity(remaining_bytes as usize);
+        let mut remaining_bytes = state.header.remaining_length
+            .checked_sub(packet_identifier.encoded_length() + properties.encoded_length())
+            .ok_or(DecodingError::Malformed(Field::RemainingLength))?;
+        // the remaining length is untrusted, so grow as return codes actually arrive
+        let mut return_codes = Vec::new();
 
         while remaining_bytes > 0 {
-            return_codes.push(ReturnCode::decode(reader, &mut ())?);
+            return_codes.push(decode_return_code(reader, state.protocol_version)?);
             remaining_bytes -= 1;
         }
 
-        Ok(SubackData { packet_identifier, return_codes })
+        Ok(SubackData { packet_identifier, properties, return_codes })
+    }
+}
+
+/// MQTT 5 adds more failure codes than the single one 3.1.1 knows
+fn decode_return_code<R: Read>(reader: &mut R, protocol_version: ProtocolVersion) -> Result<ReturnCode, DecodingError> {
+    let byte = reader.read_u8()?;
+    if protocol_version >= ProtocolVersion::V5 && byte > 0x80 {
+        Ok(ReturnCode::Refused(ReasonCode(byte)))
+    } else {
+        ReturnCode::decode(&mut &[byte][..], &mut ())
     }
 }
 
@@ -32,12 +52,85 @@ impl Encode for SubackData {
 
     fn encoded_length(&self) -> u32 {
         self.packet_identifier.encoded_length() +
+        self.properties.encoded_length() +
         self.return_codes.encoded_length()
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         self.packet_identifier.encode(writer)?;
+        self.properties.encode(writer)?;
         self.return_codes.encode(writer)
     }
 
 }
+
+impl SubackData {
+    pub fn new(packet_identifier: PacketIdentifier, return_codes: Vec<ReturnCode>) -> SubackData {
+        SubackData { packet_identifier, properties: None, return_codes }
+    }
+
+    /// turns this into an MQTT 5 SUBACK
+    pub fn with_properties(mut self, properties: Properties) -> SubackData {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn packet_identifier(&self) -> PacketIdentifier { self.packet_identifier }
+    pub fn properties(&self) -> Option<&Properties> { self.properties.as_ref() }
+    pub fn return_codes(&self) -> &[ReturnCode] { &self.return_codes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::types::Header;
+
+    #[test]
+    fn decoding_v3_suback_data() {
+        let header = Header { packet_type: 9, flags: 0, remaining_length: 4 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1, 0x01, 0x80]);
+        let suback_data = SubackData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(suback_data.properties(), None);
+        assert_eq!(suback_data.return_codes(), &[ReturnCode::Success(Qos::AtLeastOnce), ReturnCode::Failure]);
+
+        let mut cursor = Cursor::new(vec![0, 1, 0x01, 0x87]);
+        assert!(SubackData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_v5_suback_data() {
+        let header = Header { packet_type: 9, flags: 0, remaining_length: 5 };
+        let mut state = DecodingContext { header, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0, 1, 0, 0x02, 0x87]);
+        let suback_data = SubackData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(suback_data.properties(), Some(&Properties::new()));
+        assert_eq!(suback_data.return_codes(), &[ReturnCode::Success(Qos::ExactlyOnce), ReturnCode::Refused(ReasonCode::NOT_AUTHORIZED)]);
+    }
+
+    #[test]
+    fn decoding_suback_data_with_a_short_remaining_length() {
+        let header = Header { packet_type: 9, flags: 0, remaining_length: 1 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1]);
+        assert!(SubackData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_suback_data_with_a_huge_remaining_length() {
+        let header = Header { packet_type: 9, flags: 0, remaining_length: 268_435_455 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1, 0x01]);
+        assert!(SubackData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn encoding_suback_data() {
+        let suback_data = SubackData::new(PacketIdentifier(1), vec![ReturnCode::Success(Qos::AtMostOnce), ReturnCode::Failure]);
+        let mut data: Vec<u8> = Vec::new();
+        suback_data.encode(&mut data).unwrap();
+        assert_eq!(data, vec![0, 1, 0x00, 0x80]);
+        assert_eq!(suback_data.encoded_length(), 4);
+    }
+}
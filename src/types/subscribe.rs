@@ -1,26 +1,54 @@
 use super::*;
+use crate::topic::{topic_matches, validate_topic_filter};
 use std::io;
 use std::io::{Read, Write};
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
-#[derive(Debug)]
+/// A topic filter of a SUBSCRIBE along with its subscription options.
+///
+/// Not to be confused with `topic::ValidTopicFilter`: the filter is only checked when decoded
+/// and by the clients before sending it.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TopicFilter {
     filter: String,
-    qos: Qos
+    qos: Qos,
+    no_local: bool,
+    retain_as_published: bool,
+    retain_handling: RetainHandling,
+}
+
+/// Whether retained messages are sent when a subscription is made, MQTT 5 only.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RetainHandling {
+    SendAtSubscribe,
+    SendAtNewSubscribe,
+    DoNotSend,
 }
 
 impl Decode for TopicFilter {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
-    fn decode<R: Read>(reader: &mut R, _state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+    fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
         let filter = String::decode(reader, &mut ())?;
+        validate_topic_filter(&filter)?;
 
-        let qos_byte = reader.read_u8()?;
-        if qos_byte > 3 { return Err(DecodingError::Malformed) };
-        let qos = Qos::decode(qos_byte).ok_or(DecodingError::Malformed)?;
+        let mut options = reader.read_u8()?;
+        // before MQTT 5 everything but the qos is reserved
+        let reserved = if state.protocol_version < ProtocolVersion::V5 { 0b1111_1100 } else { 0b1100_0000 };
+        if state.is_strict() && options & reserved > 0 { return Err(DecodingError::ReservedBits(Field::SubscriptionOptions)) };
+        options &= !reserved;
+        let qos = Qos::decode(options & 0b0000_0011).ok_or(DecodingError::InvalidQos(options & 0b0000_0011))?;
+        let no_local = options & 0b0000_0100 > 0;
+        let retain_as_published = options & 0b0000_1000 > 0;
+        let retain_handling = match (options & 0b0011_0000) >> 4 {
+            0 => RetainHandling::SendAtSubscribe,
+            1 => RetainHandling::SendAtNewSubscribe,
+            2 => RetainHandling::DoNotSend,
+            _ => return Err(DecodingError::Malformed(Field::SubscriptionOptions)),
+        };
 
-        Ok(TopicFilter {filter, qos})
+        Ok(TopicFilter {filter, qos, no_local, retain_as_published, retain_handling})
     }
 }
 
@@ -31,45 +59,192 @@ impl Encode for TopicFilter {
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         self.filter.encode(writer)?;
-        writer.write_u8(self.qos.encode())
+        writer.write_u8(self.options())
     }
 }
 
-#[derive(Debug)]
+impl TopicFilter {
+    pub fn new<S: Into<String>>(filter: S, qos: Qos) -> TopicFilter {
+        TopicFilter {
+            filter: filter.into(),
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: RetainHandling::SendAtSubscribe,
+        }
+    }
+
+    /// a topic filter with the MQTT 5 subscription options
+    pub fn with_options<S: Into<String>>(filter: S, qos: Qos, no_local: bool, retain_as_published: bool, retain_handling: RetainHandling) -> TopicFilter {
+        TopicFilter { filter: filter.into(), qos, no_local, retain_as_published, retain_handling }
+    }
+
+    pub fn filter(&self) -> &str { &self.filter }
+    /// whether a message published to `topic_name` matches this filter
+    pub fn matches(&self, topic_name: &str) -> bool { topic_matches(&self.filter, topic_name) }
+    pub fn qos(&self) -> Qos { self.qos }
+    pub fn no_local(&self) -> bool { self.no_local }
+    pub fn retain_as_published(&self) -> bool { self.retain_as_published }
+    pub fn retain_handling(&self) -> RetainHandling { self.retain_handling }
+
+    fn options(&self) -> u8 {
+        let mut options = self.qos.encode();
+        if self.no_local { options |= 0b0000_0100 };
+        if self.retain_as_published { options |= 0b0000_1000 };
+        let retain_handling = match self.retain_handling {
+            RetainHandling::SendAtSubscribe => 0,
+            RetainHandling::SendAtNewSubscribe => 1,
+            RetainHandling::DoNotSend => 2,
+        };
+        options | retain_handling << 4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SubscribeData {
     packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
     topic_filters: Vec<TopicFilter>
 }
 
 impl Decode for SubscribeData {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
-        if state.header.flags != 2 {return Err(DecodingError::Malformed)};
+        state.check_header_flags()?;
 
         let packet_identifier = PacketIdentifier::decode(reader, state)?;
+        let properties = if state.protocol_version >= ProtocolVersion::V5 {
+            Some(Properties::decode(reader, &mut ())?)
+        } else {
+            None
+        };
         let mut topic_filters = Vec::new();
 
-        let mut remaining_length = state.header.remaining_length - 2;
+        let mut remaining_length = state.header.remaining_length
+            .checked_sub(packet_identifier.encoded_length() + properties.encoded_length())
+            .ok_or(DecodingError::Malformed(Field::RemainingLength))?;
         while remaining_length > 0 {
             let filter = TopicFilter::decode(reader, state)?;
-            remaining_length -= filter.encoded_length();
+            remaining_length = remaining_length.checked_sub(filter.encoded_length())
+                .ok_or(DecodingError::Malformed(Field::RemainingLength))?;
             topic_filters.push(filter)
         }
+        // [MQTT-3.8.3-3]
+        if topic_filters.is_empty() { return Err(DecodingError::Malformed(Field::Payload)) };
 
-        Ok(SubscribeData { packet_identifier, topic_filters})
+        Ok(SubscribeData { packet_identifier, properties, topic_filters})
     }
 }
 
 impl Encode for SubscribeData {
     fn encoded_length(&self) -> u32 {
         self.packet_identifier.encoded_length() +
+        self.properties.encoded_length() +
         self.topic_filters.encoded_length()
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.check_encodable()?;
         self.packet_identifier.encode(writer)?;
+        self.properties.encode(writer)?;
         self.topic_filters.encode(writer)
     }
 }
+
+impl SubscribeData {
+    pub fn new(packet_identifier: PacketIdentifier, topic_filters: Vec<TopicFilter>) -> SubscribeData {
+        SubscribeData { packet_identifier, properties: None, topic_filters }
+    }
+
+    /// turns this into an MQTT 5 SUBSCRIBE
+    pub fn with_properties(mut self, properties: Properties) -> SubscribeData {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn packet_identifier(&self) -> PacketIdentifier { self.packet_identifier }
+    pub fn properties(&self) -> Option<&Properties> { self.properties.as_ref() }
+    pub fn topic_filters(&self) -> &[TopicFilter] { &self.topic_filters }
+
+    /// [MQTT-3.8.3-3] a SUBSCRIBE has at least one topic filter
+    pub(crate) fn check_encodable(&self) -> io::Result<()> {
+        if self.topic_filters.is_empty() { return Err(io::Error::new(io::ErrorKind::InvalidInput, "SUBSCRIBE without topic filters")) };
+        self.packet_identifier.check_encodable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::types::Header;
+    use crate::topic::TopicError;
+
+    #[test]
+    fn decoding_v3_topic_filter_options() {
+        let mut state = DecodingContext::default();
+        let mut cursor = Cursor::new(vec![0, 1, b'a', 0b0000_0100]);
+        assert!(TopicFilter::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_invalid_topic_filters() {
+        let mut state = DecodingContext::default();
+        let mut cursor = Cursor::new(vec![0, 3, b'a', b'#', b'b', 0]);
+        let err = TopicFilter::decode(&mut cursor, &mut state).unwrap_err();
+        assert!(matches!(err, DecodingError::InvalidTopic(TopicError::MisplacedMultiLevelWildcard)));
+
+        let mut cursor = Cursor::new(vec![0, 0, 0]);
+        assert!(TopicFilter::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_lenient_topic_filter_options() {
+        let mut state = DecodingContext { strictness: Strictness::Lenient, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1, b'a', 0b0000_0101]);
+        let topic_filter = TopicFilter::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(topic_filter.qos(), Qos::AtLeastOnce);
+        assert!(!topic_filter.no_local());
+    }
+
+    #[test]
+    fn decoding_v5_subscribe_data() {
+        let header = Header { packet_type: 8, flags: 2, remaining_length: 9 };
+        let mut state = DecodingContext { header, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0, 1, 2, 0x0B, 5, 0, 1, b'a', 0b0010_1101]);
+        let subscribe_data = SubscribeData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(subscribe_data.properties(), Some(&Properties(vec![Property::SubscriptionIdentifier(5)])));
+
+        let topic_filter = &subscribe_data.topic_filters()[0];
+        assert_eq!(topic_filter.filter(), "a");
+        assert_eq!(topic_filter.qos(), Qos::AtLeastOnce);
+        assert!(topic_filter.no_local());
+        assert!(topic_filter.retain_as_published());
+        assert_eq!(topic_filter.retain_handling(), RetainHandling::DoNotSend);
+    }
+
+    #[test]
+    fn decoding_subscribe_data_with_a_short_remaining_length() {
+        let header = Header { packet_type: 8, flags: 2, remaining_length: 1 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1, 0, 1, b'a', 0]);
+        assert!(SubscribeData::decode(&mut cursor, &mut state).is_err());
+
+        // the topic filter runs past the end of the packet
+        state.header.remaining_length = 4;
+        let mut cursor = Cursor::new(vec![0, 1, 0, 1, b'a', 0]);
+        assert!(SubscribeData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn encoding_v5_subscribe_data() {
+        let topic_filter = TopicFilter::with_options("a", Qos::ExactlyOnce, true, false, RetainHandling::SendAtNewSubscribe);
+        let subscribe_data = SubscribeData::new(PacketIdentifier(1), vec![topic_filter]).with_properties(Properties::new());
+        let mut data: Vec<u8> = Vec::new();
+        subscribe_data.encode(&mut data).unwrap();
+        assert_eq!(data, vec![0, 1, 0, 0, 1, b'a', 0b0001_0110]);
+        assert_eq!(subscribe_data.encoded_length(), 7);
+    }
+}
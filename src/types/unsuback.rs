@@ -0,0 +1,94 @@
+use super::*;
+use std::io;
+use std::io::{Read, Write};
+
+/// The body of an UNSUBACK packet. Only MQTT 5 sends properties and
+/// a reason code for each topic filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsubackData {
+    packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
+    reason_codes: Vec<ReasonCode>,
+}
+
+impl Decode for UnsubackData {
+    type DecoderState=DecodingContext;
+    type DecodingError=DecodingError;
+
+    fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
+        state.check_header_flags()?;
+        if state.protocol_version < ProtocolVersion::V5 && state.header.remaining_length != 2 { return Err(DecodingError::Malformed(Field::RemainingLength)) };
+
+        let packet_identifier = PacketIdentifier::decode(reader, state)?;
+        if state.protocol_version < ProtocolVersion::V5 {
+            return Ok(UnsubackData::new(packet_identifier));
+        }
+
+        let properties = Properties::decode(reader, &mut ())?;
+        let mut remaining_length = state.header.remaining_length
+            .checked_sub(packet_identifier.encoded_length() + properties.encoded_length())
+            .ok_or(DecodingError::Malformed(Field::RemainingLength))?;
+        let mut reason_codes = Vec::new();
+        while remaining_length > 0 {
+            reason_codes.push(ReasonCode::decode(reader, &mut ())?);
+            remaining_length -= 1;
+        }
+
+        Ok(UnsubackData { packet_identifier, properties: Some(properties), reason_codes })
+    }
+}
+
+impl Encode for UnsubackData {
+    fn encoded_length(&self) -> u32 {
+        self.packet_identifier.encoded_length() +
+        self.properties.encoded_length() +
+        self.reason_codes.encoded_length()
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.packet_identifier.encode(writer)?;
+        self.properties.encode(writer)?;
+        self.reason_codes.encode(writer)
+    }
+}
+
+impl UnsubackData {
+    pub fn new(packet_identifier: PacketIdentifier) -> UnsubackData {
+        UnsubackData { packet_identifier, properties: None, reason_codes: Vec::new() }
+    }
+
+    /// an MQTT 5 UNSUBACK
+    pub fn with_reason_codes(packet_identifier: PacketIdentifier, reason_codes: Vec<ReasonCode>, properties: Properties) -> UnsubackData {
+        UnsubackData { packet_identifier, properties: Some(properties), reason_codes }
+    }
+
+    pub fn packet_identifier(&self) -> PacketIdentifier { self.packet_identifier }
+    pub fn properties(&self) -> Option<&Properties> { self.properties.as_ref() }
+    pub fn reason_codes(&self) -> &[ReasonCode] { &self.reason_codes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::types::Header;
+
+    #[test]
+    fn decoding_v3_unsuback_data() {
+        let header = Header { packet_type: 11, flags: 0, remaining_length: 2 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 9]);
+        let unsuback_data = UnsubackData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(unsuback_data, UnsubackData::new(PacketIdentifier(9)));
+    }
+
+    #[test]
+    fn decoding_v5_unsuback_data() {
+        let header = Header { packet_type: 11, flags: 0, remaining_length: 5 };
+        let mut state = DecodingContext { header, ..DecodingContext::new(ProtocolVersion::V5) };
+        let mut cursor = Cursor::new(vec![0, 9, 0, 0x00, 0x11]);
+        let unsuback_data = UnsubackData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(unsuback_data.properties(), Some(&Properties::new()));
+        assert_eq!(unsuback_data.reason_codes(), &[ReasonCode::SUCCESS, ReasonCode::NO_SUBSCRIPTION_EXISTED]);
+    }
+}
@@ -1,42 +1,117 @@
 use super::*;
+use crate::topic::validate_topic_filter;
 use std::io;
 use std::io::{Write, Read};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UnsubscribeData {
     packet_identifier: PacketIdentifier,
+    properties: Option<Properties>,
     topic_filters: Vec<String>
 }
 
 impl Decode for UnsubscribeData {
-    type DecoderState=DecodingInfo;
+    type DecoderState=DecodingContext;
     type DecodingError=DecodingError;
 
     fn decode<R: Read>(reader: &mut R, state: &mut Self::DecoderState) -> Result<Self, DecodingError> {
-        if state.header.flags != 2 {return Err(DecodingError::Malformed)};
+        state.check_header_flags()?;
 
         let packet_identifier = PacketIdentifier::decode(reader, state)?;
-        let mut remaining_length = state.header.remaining_length - 2;
+        let properties = if state.protocol_version >= ProtocolVersion::V5 {
+            Some(Properties::decode(reader, &mut ())?)
+        } else {
+            None
+        };
+        let mut remaining_length = state.header.remaining_length
+            .checked_sub(packet_identifier.encoded_length() + properties.encoded_length())
+            .ok_or(DecodingError::Malformed(Field::RemainingLength))?;
         let mut topic_filters = Vec::new();
 
         while remaining_length > 0 {
             let filter = String::decode(reader, &mut ())?;
-            remaining_length -= 2 + filter.len() as u32;
+            validate_topic_filter(&filter)?;
+            remaining_length = remaining_length.checked_sub(filter.encoded_length())
+                .ok_or(DecodingError::Malformed(Field::RemainingLength))?;
             topic_filters.push(filter);
         }
+        // [MQTT-3.10.3-2]
+        if topic_filters.is_empty() { return Err(DecodingError::Malformed(Field::Payload)) };
 
-        Ok(UnsubscribeData {packet_identifier, topic_filters})
+        Ok(UnsubscribeData {packet_identifier, properties, topic_filters})
     }
 }
 
 impl Encode for UnsubscribeData {
     fn encoded_length(&self) -> u32 {
         self.packet_identifier.encoded_length() +
+        self.properties.encoded_length() +
         self.topic_filters.encoded_length()
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.check_encodable()?;
         self.packet_identifier.encode(writer)?;
+        self.properties.encode(writer)?;
         self.topic_filters.encode(writer)
     }
 }
+
+impl UnsubscribeData {
+    pub fn new(packet_identifier: PacketIdentifier, topic_filters: Vec<String>) -> UnsubscribeData {
+        UnsubscribeData { packet_identifier, properties: None, topic_filters }
+    }
+
+    /// turns this into an MQTT 5 UNSUBSCRIBE
+    pub fn with_properties(mut self, properties: Properties) -> UnsubscribeData {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn packet_identifier(&self) -> PacketIdentifier { self.packet_identifier }
+    pub fn properties(&self) -> Option<&Properties> { self.properties.as_ref() }
+    pub fn topic_filters(&self) -> &[String] { &self.topic_filters }
+
+    /// [MQTT-3.10.3-2] an UNSUBSCRIBE has at least one topic filter
+    pub(crate) fn check_encodable(&self) -> io::Result<()> {
+        if self.topic_filters.is_empty() { return Err(io::Error::new(io::ErrorKind::InvalidInput, "UNSUBSCRIBE without topic filters")) };
+        self.packet_identifier.check_encodable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::types::Header;
+
+    #[test]
+    fn decoding_unsubscribe_data() {
+        let header = Header { packet_type: 10, flags: 2, remaining_length: 9 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1, 0, 1, b'a', 0, 2, b'b', b'c']);
+        let unsubscribe_data = UnsubscribeData::decode(&mut cursor, &mut state).unwrap();
+        assert_eq!(unsubscribe_data.packet_identifier(), PacketIdentifier(1));
+        assert_eq!(unsubscribe_data.topic_filters(), &["a".to_owned(), "bc".to_owned()]);
+    }
+
+    #[test]
+    fn decoding_unsubscribe_data_with_a_short_remaining_length() {
+        let header = Header { packet_type: 10, flags: 2, remaining_length: 1 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1, 0, 1, b'a']);
+        assert!(UnsubscribeData::decode(&mut cursor, &mut state).is_err());
+
+        state.header.remaining_length = 4;
+        let mut cursor = Cursor::new(vec![0, 1, 0, 1, b'a']);
+        assert!(UnsubscribeData::decode(&mut cursor, &mut state).is_err());
+    }
+
+    #[test]
+    fn decoding_unsubscribe_data_past_the_end_of_the_input() {
+        let header = Header { packet_type: 10, flags: 2, remaining_length: 268_435_455 };
+        let mut state = DecodingContext { header, ..DecodingContext::default() };
+        let mut cursor = Cursor::new(vec![0, 1]);
+        assert!(UnsubscribeData::decode(&mut cursor, &mut state).is_err());
+    }
+}
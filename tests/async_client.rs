@@ -0,0 +1,495 @@
+//! The tokio client against a scripted broker running on another thread.
+
+#![cfg(feature = "tokio")]
+
+extern crate mqtt;
+extern crate tokio;
+
+mod common;
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::common::FakeBroker;
+use mqtt::client::{AsyncClient, Backoff, ClientError, Handler, OverflowPolicy, SessionStore};
+use mqtt::packet::*;
+use mqtt::topic::TopicError;
+
+fn connect_data(protocol_version: ProtocolVersion) -> ConnectData {
+    ConnectData::builder().protocol_version(protocol_version).client_id("client").build()
+}
+
+#[tokio::test]
+async fn refused_connections() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        broker.accept().handshake(false, ConnackReturnCode::BadUsernameOrPassword);
+    });
+
+    match AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await {
+        Err(ClientError::ConnectionRefused(ConnackReturnCode::BadUsernameOrPassword)) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_requests_from_several_tasks() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        // both messages are in flight before either is acknowledged
+        let mut packet_identifiers = Vec::new();
+        for _ in 0..2 {
+            match connection.receive() {
+                Packet::Publish(publish) => packet_identifiers.push((publish.qos(), publish.packet_identifier().unwrap())),
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+        }
+        packet_identifiers.sort_by_key(|&(qos, _)| qos.encode());
+        let (qos, packet_identifier) = packet_identifiers[1];
+        assert_eq!(qos, Qos::ExactlyOnce);
+        connection.send(Packet::Pubrec(AckData::new(packet_identifier)));
+        assert_eq!(connection.receive(), Packet::Pubrel(AckData::new(packet_identifier)));
+        connection.send(Packet::Pubcomp(AckData::new(packet_identifier)));
+
+        let (qos, packet_identifier) = packet_identifiers[0];
+        assert_eq!(qos, Qos::AtLeastOnce);
+        connection.send(Packet::Puback(AckData::new(packet_identifier)));
+
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let (client, event_loop, _messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await.unwrap();
+    let event_loop = tokio::spawn(event_loop.run());
+
+    let publishes = vec![(Qos::AtLeastOnce, "one"), (Qos::ExactlyOnce, "two")].into_iter().map(|(qos, payload)| {
+        let client = client.clone();
+        tokio::spawn(async move { client.publish("a/b", payload, qos, false).await })
+    }).collect::<Vec<_>>();
+    for publish in publishes {
+        publish.await.unwrap().unwrap();
+    }
+
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn subscribing_and_receiving_messages_over_mqtt_5() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let packet_identifier = match connection.receive() {
+            Packet::Subscribe(subscribe) => {
+                assert_eq!(subscribe.properties(), Some(&Properties::new()));
+                subscribe.packet_identifier()
+            },
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        let return_codes = vec![ReturnCode::Success(Qos::AtLeastOnce), ReturnCode::Refused(ReasonCode::NOT_AUTHORIZED)];
+        connection.send(Packet::Suback(SubackData::new(packet_identifier, return_codes).with_properties(Properties::new())));
+
+        let publish = PublishData::builder("a/b", "hello").qos(Qos::AtLeastOnce).packet_identifier(PacketIdentifier(3))
+            .properties(Properties::new()).build().unwrap();
+        connection.send(Packet::Publish(publish));
+        assert_eq!(connection.receive(), Packet::Puback(AckData::new(PacketIdentifier(3))));
+
+        match connection.receive() {
+            Packet::Unsubscribe(unsubscribe) => {
+                let unsuback = UnsubackData::with_reason_codes(unsubscribe.packet_identifier(), vec![ReasonCode::SUCCESS], Properties::new());
+                connection.send(Packet::Unsuback(unsuback));
+            },
+            packet => panic!("expected UNSUBSCRIBE, got {:?}", packet),
+        }
+        connection.send(Packet::Disconnect(DisconnectData::new(ReasonCode::SESSION_TAKEN_OVER, Properties::new())));
+    });
+
+    let (client, event_loop, mut messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V5)).await.unwrap();
+    assert_eq!(event_loop.protocol_version(), ProtocolVersion::V5);
+    let event_loop = tokio::spawn(event_loop.run());
+
+    let topic_filters = vec![TopicFilter::new("a/+", Qos::AtLeastOnce), TopicFilter::new("b", Qos::AtLeastOnce)];
+    let return_codes = client.subscribe(topic_filters).await.unwrap();
+    assert_eq!(return_codes, vec![ReturnCode::Success(Qos::AtLeastOnce), ReturnCode::Refused(ReasonCode::NOT_AUTHORIZED)]);
+
+    let message = messages.recv().await.unwrap();
+    assert_eq!(message.topic_name(), "a/b");
+    assert_eq!(message.payload(), b"hello");
+
+    client.unsubscribe(vec!["a/+".to_owned()]).await.unwrap();
+    match event_loop.await.unwrap() {
+        Err(ClientError::Disconnected(ReasonCode::SESSION_TAKEN_OVER)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(messages.recv().await.is_none());
+    match client.publish("a/b", "too late", Qos::AtMostOnce, false).await {
+        Err(ClientError::ConnectionClosed) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn routing_messages_to_handlers() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let packet_identifier = match connection.receive() {
+            Packet::Subscribe(subscribe) => subscribe.packet_identifier(),
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        let return_codes = vec![ReturnCode::Success(Qos::AtMostOnce), ReturnCode::Success(Qos::AtMostOnce)];
+        connection.send(Packet::Suback(SubackData::new(packet_identifier, return_codes)));
+        for topic_name in &["a/b", "a/b/c", "c"] {
+            connection.send(Packet::Publish(PublishData::builder(*topic_name, "hello").build().unwrap()));
+        }
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let (client, event_loop, mut messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await.unwrap();
+    let event_loop = tokio::spawn(event_loop.run());
+
+    let (single_level, mut single_level_messages) = tokio::sync::mpsc::unbounded_channel();
+    let (multi_level, mut multi_level_messages) = tokio::sync::mpsc::unbounded_channel();
+    let subscriptions = vec![
+        (TopicFilter::new("a/+", Qos::AtMostOnce), Handler::from(single_level)),
+        (TopicFilter::new("a/#", Qos::AtMostOnce), Handler::from(multi_level)),
+    ];
+    client.subscribe_with_handlers(subscriptions).await.unwrap();
+
+    assert_eq!(single_level_messages.recv().await.unwrap().topic_name(), "a/b");
+    assert_eq!(multi_level_messages.recv().await.unwrap().topic_name(), "a/b");
+    assert_eq!(multi_level_messages.recv().await.unwrap().topic_name(), "a/b/c");
+    // the message without a handler is the only one left for `Messages`
+    assert_eq!(messages.recv().await.unwrap().topic_name(), "c");
+    assert!(single_level_messages.try_recv().is_err());
+
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn routing_retained_messages_sent_before_the_suback() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let packet_identifier = match connection.receive() {
+            Packet::Subscribe(subscribe) => subscribe.packet_identifier(),
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        connection.send(Packet::Publish(PublishData::builder("a/b", "retained").retain(true).build().unwrap()));
+        connection.send(Packet::Suback(SubackData::new(packet_identifier, vec![ReturnCode::Success(Qos::AtMostOnce)])));
+        connection.send(Packet::Publish(PublishData::builder("c", "unhandled").build().unwrap()));
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let (client, event_loop, mut messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await.unwrap();
+    let event_loop = tokio::spawn(event_loop.run());
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    client.subscribe_with_handlers(vec![(TopicFilter::new("a/+", Qos::AtMostOnce), Handler::from(sender))]).await.unwrap();
+
+    assert_eq!(receiver.recv().await.unwrap().payload(), b"retained");
+    assert_eq!(messages.recv().await.unwrap().payload(), b"unhandled");
+
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+/// A session of QoS 1 messages taking up every packet identifier, which saves nothing.
+#[derive(Debug)]
+struct FullSession;
+
+impl SessionStore for FullSession {
+    fn store_outbound(&mut self, _: &Packet) -> io::Result<()> { Ok(()) }
+    fn remove_outbound(&mut self, _: PacketIdentifier) -> io::Result<()> { Ok(()) }
+    fn store_inbound(&mut self, _: PacketIdentifier) -> io::Result<()> { Ok(()) }
+    fn remove_inbound(&mut self, _: PacketIdentifier) -> io::Result<()> { Ok(()) }
+    fn clear_inbound(&mut self) -> io::Result<()> { Ok(()) }
+
+    fn load(&mut self) -> io::Result<(Vec<Packet>, Vec<PacketIdentifier>)> {
+        let outbound = (1..=u16::MAX).map(|packet_identifier| {
+            let publish = PublishData::builder("a/b", "restored").qos(Qos::AtLeastOnce).packet_identifier(PacketIdentifier(packet_identifier));
+            Packet::Publish(publish.build().unwrap())
+        }).collect();
+        Ok((outbound, vec![]))
+    }
+}
+
+#[tokio::test]
+async fn completing_restored_messages() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.handshake(true, ConnackReturnCode::Accepted);
+        for packet_identifier in 1..=u16::MAX {
+            match connection.receive() {
+                Packet::Publish(publish) => {
+                    assert!(publish.dup());
+                    assert_eq!(publish.packet_identifier(), Some(PacketIdentifier(packet_identifier)));
+                },
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+        }
+        for packet_identifier in 1..=u16::MAX {
+            connection.send(Packet::Puback(AckData::new(PacketIdentifier(packet_identifier))));
+        }
+        // arrives once the client has handled every PUBACK
+        connection.send(Packet::Publish(PublishData::builder("c", "acknowledged").build().unwrap()));
+
+        match connection.receive() {
+            Packet::Publish(publish) => connection.send(Packet::Puback(AckData::new(publish.packet_identifier().unwrap()))),
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        }
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let connect = ConnectData::builder().client_id("client").clean_session(false).build();
+    let (client, event_loop, mut messages) = AsyncClient::connect_with_store(address, connect, FullSession).await.unwrap();
+    let event_loop = tokio::spawn(event_loop.run());
+    assert_eq!(messages.recv().await.unwrap().payload(), b"acknowledged");
+
+    // the restored messages gave their packet identifiers back
+    client.publish("a/b", "fresh", Qos::AtLeastOnce, false).await.unwrap();
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn refusing_invalid_topics() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        // nothing reaches the server
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let (client, event_loop, _messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await.unwrap();
+    let event_loop = tokio::spawn(event_loop.run());
+    match client.publish("a/#", "wildcard", Qos::AtMostOnce, false).await {
+        Err(ClientError::TopicError(TopicError::WildcardInTopicName)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match client.subscribe(vec![TopicFilter::new("a+", Qos::AtMostOnce)]).await {
+        Err(ClientError::TopicError(TopicError::MisplacedSingleLevelWildcard)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match client.subscribe_with_handlers(vec![]).await {
+        Err(ClientError::NoTopicFilters) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match client.unsubscribe(vec![]).await {
+        Err(ClientError::NoTopicFilters) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn reconnecting_to_a_silent_server() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let (connecting, waiting) = oneshot::channel();
+    let server = thread::spawn(move || {
+        broker.accept().accept_connect();
+
+        // never answers the CONNECT, until the client gives up on it
+        let mut connection = broker.accept();
+        assert!(matches!(connection.receive(), Packet::Connect(_)));
+        connecting.send(()).unwrap();
+        assert_eq!(connection.try_receive(), None);
+
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        assert_eq!(connection.receive(), Packet::Publish(PublishData::builder("a/b", "hello").build().unwrap()));
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let connect = ConnectData::builder().client_id("client").keepalive(1).build();
+    let (client, mut event_loop, _messages) = AsyncClient::connect(address, connect).await.unwrap();
+    event_loop.set_reconnect(Some(Backoff::new(Duration::from_millis(10), Duration::from_millis(100)).with_jitter(0.0)));
+    let event_loop = tokio::spawn(event_loop.run());
+
+    // waits in the request channel while the event loop waits for the CONNACK
+    waiting.await.unwrap();
+    client.publish("a/b", "hello", Qos::AtMostOnce, false).await.unwrap();
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn pinging_an_idle_server() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        assert_eq!(connection.receive(), Packet::Pingreq);
+        connection.send(Packet::Pingresp);
+        assert_eq!(connection.receive(), Packet::Pingreq);
+        assert_eq!(connection.try_receive(), None);
+    });
+
+    let connect = ConnectData::builder().client_id("client").keepalive(1).build();
+    let (_client, event_loop, _messages) = AsyncClient::connect(address, connect).await.unwrap();
+    match event_loop.run().await {
+        Err(ClientError::KeepAliveTimeout) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn resuming_pending_requests_after_reconnecting() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        let packet_identifier = match connection.receive() {
+            Packet::Publish(publish) => publish.packet_identifier().unwrap(),
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        };
+        connection.send(Packet::Pubrec(AckData::new(packet_identifier)));
+        assert_eq!(connection.receive(), Packet::Pubrel(AckData::new(packet_identifier)));
+        connection.close();
+
+        let mut connection = broker.accept();
+        connection.handshake(true, ConnackReturnCode::Accepted);
+        assert_eq!(connection.receive(), Packet::Pubrel(AckData::new(packet_identifier)));
+        connection.send(Packet::Pubcomp(AckData::new(packet_identifier)));
+        let subscribe = match connection.receive() {
+            Packet::Subscribe(subscribe) => subscribe,
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        connection.close();
+
+        // the SUBSCRIBE is sent again as it was, with nothing to resubscribe to
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        assert_eq!(connection.receive(), Packet::Subscribe(subscribe.clone()));
+        connection.send(Packet::Suback(SubackData::new(subscribe.packet_identifier(), vec![ReturnCode::Success(Qos::AtMostOnce)])));
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let (client, mut event_loop, _messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await.unwrap();
+    event_loop.set_reconnect(Some(Backoff::new(Duration::from_millis(10), Duration::from_millis(100))));
+    let event_loop = tokio::spawn(event_loop.run());
+
+    client.publish("a/b", "exactly once", Qos::ExactlyOnce, false).await.unwrap();
+    let return_codes = client.subscribe(vec![TopicFilter::new("a/+", Qos::AtMostOnce)]).await.unwrap();
+    assert_eq!(return_codes, vec![ReturnCode::Success(Qos::AtMostOnce)]);
+
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+/// Accept a connection and drop it, then fail the first attempt to reconnect,
+/// signalling `reconnecting` once the client is waiting to try again.
+fn lose_the_connection(broker: &FakeBroker, reconnecting: oneshot::Sender<()>) {
+    broker.accept().accept_connect();
+    // read the CONNECT so that closing is not a reset, but don't answer it
+    let mut connection = broker.accept();
+    assert!(matches!(connection.receive(), Packet::Connect(_)));
+    connection.close();
+    reconnecting.send(()).unwrap();
+}
+
+#[tokio::test]
+async fn publishing_while_reconnecting() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let (reconnecting, waiting) = oneshot::channel();
+    let server = thread::spawn(move || {
+        lose_the_connection(&broker, reconnecting);
+
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        for payload in &["b", "c"] {
+            assert_eq!(connection.receive(), Packet::Publish(PublishData::builder("a/b", *payload).build().unwrap()));
+        }
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let (client, mut event_loop, _messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await.unwrap();
+    event_loop.set_reconnect(Some(Backoff::new(Duration::from_millis(200), Duration::from_secs(1)).with_jitter(0.0)));
+    event_loop.set_outbox(2, 1024, OverflowPolicy::DropOldest);
+    let event_loop = tokio::spawn(event_loop.run());
+
+    waiting.await.unwrap();
+    let (a, b, c) = tokio::join!(
+        client.publish("a/b", "a", Qos::AtMostOnce, false),
+        client.publish("a/b", "b", Qos::AtMostOnce, false),
+        client.publish("a/b", "c", Qos::AtMostOnce, false),
+    );
+    match a {
+        Err(ClientError::OutboxFull) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    b.unwrap();
+    c.unwrap();
+
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn publishing_too_large_a_message_while_reconnecting() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let (reconnecting, waiting) = oneshot::channel();
+    let server = thread::spawn(move || {
+        lose_the_connection(&broker, reconnecting);
+
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        assert_eq!(connection.receive(), Packet::Publish(PublishData::builder("a/b", "small").build().unwrap()));
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let (client, mut event_loop, _messages) = AsyncClient::connect(address, connect_data(ProtocolVersion::V311)).await.unwrap();
+    event_loop.set_reconnect(Some(Backoff::new(Duration::from_millis(200), Duration::from_secs(1)).with_jitter(0.0)));
+    event_loop.set_outbox(2, 16, OverflowPolicy::Block);
+    let event_loop = tokio::spawn(event_loop.run());
+
+    waiting.await.unwrap();
+    // waiting for room would block the outbox for good
+    match client.publish("a/b", "larger than the whole outbox", Qos::AtMostOnce, false).await {
+        Err(ClientError::OutboxFull) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    client.publish("a/b", "small", Qos::AtMostOnce, false).await.unwrap();
+
+    client.disconnect().await.unwrap();
+    event_loop.await.unwrap().unwrap();
+    server.join().unwrap();
+}
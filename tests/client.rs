@@ -0,0 +1,522 @@
+//! The blocking client against a scripted broker running on another thread.
+
+extern crate mqtt;
+
+mod common;
+
+use std::env;
+use std::io;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::common::FakeBroker;
+use mqtt::client::{Backoff, Client, ClientError, FileSessionStore, Handler, SessionStore};
+use mqtt::packet::*;
+use mqtt::topic::TopicError;
+
+fn connect_data(protocol_version: ProtocolVersion) -> ConnectData {
+    ConnectData::builder().protocol_version(protocol_version).client_id("client").build()
+}
+
+#[test]
+fn connecting() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        let connect = connection.handshake(true, ConnackReturnCode::Accepted);
+        assert_eq!(connect.client_identifier(), "client");
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    assert!(client.session_present());
+    client.disconnect().unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn refused_connections() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        broker.accept().handshake(false, ConnackReturnCode::NotAuthorized);
+    });
+
+    match Client::connect(address, connect_data(ProtocolVersion::V311)) {
+        Err(ClientError::ConnectionRefused(ConnackReturnCode::NotAuthorized)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn publishing_at_each_qos() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        match connection.receive() {
+            Packet::Publish(publish) => {
+                assert_eq!(publish.qos(), Qos::AtMostOnce);
+                assert_eq!(publish.packet_identifier(), None);
+            },
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        }
+
+        let packet_identifier = match connection.receive() {
+            Packet::Publish(publish) => publish.packet_identifier().unwrap(),
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        };
+        connection.send(Packet::Puback(AckData::new(packet_identifier)));
+
+        let packet_identifier = match connection.receive() {
+            Packet::Publish(publish) => {
+                assert_eq!(publish.qos(), Qos::ExactlyOnce);
+                assert!(publish.retain());
+                publish.packet_identifier().unwrap()
+            },
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        };
+        connection.send(Packet::Pubrec(AckData::new(packet_identifier)));
+        assert_eq!(connection.receive(), Packet::Pubrel(AckData::new(packet_identifier)));
+        connection.send(Packet::Pubcomp(AckData::new(packet_identifier)));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    client.publish("a/b", "zero", Qos::AtMostOnce, false).unwrap();
+    client.publish("a/b", "one", Qos::AtLeastOnce, false).unwrap();
+    client.publish("a/b", "two", Qos::ExactlyOnce, true).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn subscribing_and_receiving_messages() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let packet_identifier = match connection.receive() {
+            Packet::Subscribe(subscribe) => {
+                assert_eq!(subscribe.topic_filters()[0].filter(), "a/+");
+                subscribe.packet_identifier()
+            },
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        // a message sent before the SUBACK is kept until the client polls
+        let publish = PublishData::builder("a/b", "first").qos(Qos::AtLeastOnce).packet_identifier(PacketIdentifier(7)).build().unwrap();
+        connection.send(Packet::Publish(publish));
+        let return_codes = vec![ReturnCode::Success(Qos::ExactlyOnce), ReturnCode::Failure];
+        connection.send(Packet::Suback(SubackData::new(packet_identifier, return_codes)));
+        assert_eq!(connection.receive(), Packet::Puback(AckData::new(PacketIdentifier(7))));
+
+        let publish = PublishData::builder("a/c", "second").qos(Qos::ExactlyOnce).packet_identifier(PacketIdentifier(8)).build().unwrap();
+        connection.send(Packet::Publish(publish));
+        assert_eq!(connection.receive(), Packet::Pubrec(AckData::new(PacketIdentifier(8))));
+
+        let packet_identifier = match connection.receive() {
+            Packet::Unsubscribe(unsubscribe) => {
+                assert_eq!(unsubscribe.topic_filters(), &["a/+".to_owned()]);
+                unsubscribe.packet_identifier()
+            },
+            packet => panic!("expected UNSUBSCRIBE, got {:?}", packet),
+        };
+        // the client completes the QoS 2 flow while waiting for the UNSUBACK
+        connection.send(Packet::Pubrel(AckData::new(PacketIdentifier(8))));
+        assert_eq!(connection.receive(), Packet::Pubcomp(AckData::new(PacketIdentifier(8))));
+        connection.send(Packet::Unsuback(UnsubackData::new(packet_identifier)));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    let topic_filters = vec![TopicFilter::new("a/+", Qos::ExactlyOnce), TopicFilter::new("$SYS/#", Qos::AtMostOnce)];
+    let return_codes = client.subscribe(topic_filters).unwrap();
+    assert_eq!(return_codes, vec![ReturnCode::Success(Qos::ExactlyOnce), ReturnCode::Failure]);
+
+    assert_eq!(client.poll().unwrap().payload(), b"first");
+    let publish = client.poll().unwrap();
+    assert_eq!(publish.topic_name(), "a/c");
+    assert_eq!(publish.payload(), b"second");
+
+    client.unsubscribe(vec!["a/+".to_owned()]).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn routing_messages_to_handlers() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let packet_identifier = match connection.receive() {
+            Packet::Subscribe(subscribe) => subscribe.packet_identifier(),
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        let return_codes = vec![ReturnCode::Success(Qos::AtMostOnce), ReturnCode::Success(Qos::AtMostOnce), ReturnCode::Failure];
+        connection.send(Packet::Suback(SubackData::new(packet_identifier, return_codes)));
+        for topic_name in &["a/b", "a/b/c", "b", "c"] {
+            connection.send(Packet::Publish(PublishData::builder(*topic_name, "first").build().unwrap()));
+        }
+
+        match connection.receive() {
+            Packet::Unsubscribe(unsubscribe) => connection.send(Packet::Unsuback(UnsubackData::new(unsubscribe.packet_identifier()))),
+            packet => panic!("expected UNSUBSCRIBE, got {:?}", packet),
+        }
+        connection.send(Packet::Publish(PublishData::builder("a/b/c", "second").build().unwrap()));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    match client.subscribe_with_handlers(vec![(TopicFilter::new("a/#/b", Qos::AtMostOnce), Handler::callback(|_: &PublishData| {}))]) {
+        Err(ClientError::TopicError(_)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let callback = {
+        let received = received.clone();
+        Handler::callback(move |publish: &PublishData| received.lock().unwrap().push(publish.topic_name().to_owned()))
+    };
+    let subscriptions = vec![
+        (TopicFilter::new("a/+", Qos::AtMostOnce), Handler::from(sender)),
+        (TopicFilter::new("a/#", Qos::AtMostOnce), callback),
+        (TopicFilter::new("b", Qos::AtMostOnce), Handler::callback(|_: &PublishData| panic!("refused by the server"))),
+    ];
+    client.subscribe_with_handlers(subscriptions).unwrap();
+
+    // only messages without a handler make it to `poll`
+    assert_eq!(client.poll().unwrap().topic_name(), "b");
+    assert_eq!(client.poll().unwrap().topic_name(), "c");
+    assert_eq!(receiver.try_iter().map(|publish| publish.topic_name().to_owned()).collect::<Vec<_>>(), vec!["a/b"]);
+    assert_eq!(*received.lock().unwrap(), vec!["a/b", "a/b/c"]);
+
+    client.unsubscribe(vec!["a/#".to_owned()]).unwrap();
+    assert_eq!(client.poll().unwrap().payload(), b"second");
+    assert_eq!(received.lock().unwrap().len(), 2);
+    server.join().unwrap();
+}
+
+#[test]
+fn routing_retained_messages_sent_before_the_suback() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let packet_identifier = match connection.receive() {
+            Packet::Subscribe(subscribe) => subscribe.packet_identifier(),
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        connection.send(Packet::Publish(PublishData::builder("a/b", "retained").retain(true).build().unwrap()));
+        connection.send(Packet::Suback(SubackData::new(packet_identifier, vec![ReturnCode::Success(Qos::AtMostOnce)])));
+        connection.send(Packet::Publish(PublishData::builder("c", "unhandled").build().unwrap()));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    client.subscribe_with_handlers(vec![(TopicFilter::new("a/+", Qos::AtMostOnce), Handler::from(sender))]).unwrap();
+
+    assert_eq!(receiver.try_recv().unwrap().payload(), b"retained");
+    assert_eq!(client.poll().unwrap().payload(), b"unhandled");
+    server.join().unwrap();
+}
+
+#[test]
+fn subscribing_after_a_publish_timed_out() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let publish_identifier = match connection.receive() {
+            Packet::Publish(publish) => publish.packet_identifier().unwrap(),
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        };
+        let subscribe_identifier = match connection.receive() {
+            Packet::Subscribe(subscribe) => subscribe.packet_identifier(),
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        };
+        // the PUBACK arrives too late for the publish
+        connection.send(Packet::Puback(AckData::new(publish_identifier)));
+        connection.send(Packet::Suback(SubackData::new(subscribe_identifier, vec![ReturnCode::Success(Qos::AtMostOnce)])));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(100)));
+    match client.publish("a/b", "late", Qos::AtLeastOnce, false) {
+        Err(ClientError::IoError(ref err)) if err.kind() == io::ErrorKind::TimedOut => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    let return_codes = client.subscribe(vec![TopicFilter::new("a/b", Qos::AtMostOnce)]).unwrap();
+    assert_eq!(return_codes, vec![ReturnCode::Success(Qos::AtMostOnce)]);
+    server.join().unwrap();
+}
+
+#[test]
+fn refusing_invalid_topics() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        // nothing reaches the server
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    match client.publish("a/+", "wildcard", Qos::AtLeastOnce, false) {
+        Err(err @ ClientError::TopicError(TopicError::WildcardInTopicName)) => assert_eq!(err.to_string(), "invalid topic: wildcard in a topic name"),
+        other => panic!("unexpected {:?}", other),
+    }
+    match client.subscribe(vec![TopicFilter::new("a", Qos::AtMostOnce), TopicFilter::new("a/#/b", Qos::AtMostOnce)]) {
+        Err(ClientError::TopicError(TopicError::MisplacedMultiLevelWildcard)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match client.unsubscribe(vec!["".to_owned()]) {
+        Err(ClientError::TopicError(TopicError::Empty)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    // [MQTT-3.8.3-3] and [MQTT-3.10.3-2]
+    match client.subscribe(vec![]) {
+        Err(ClientError::NoTopicFilters) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match client.unsubscribe(vec![]) {
+        Err(ClientError::NoTopicFilters) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    client.disconnect().unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn rejected_publishes_over_mqtt_5() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        match connection.receive() {
+            Packet::Publish(publish) => {
+                assert_eq!(publish.properties(), Some(&Properties::new()));
+                let puback = AckData::with_reason(publish.packet_identifier().unwrap(), ReasonCode::NOT_AUTHORIZED, Properties::new());
+                connection.send(Packet::Puback(puback));
+            },
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        }
+        connection.send(Packet::Disconnect(DisconnectData::new(ReasonCode::SERVER_SHUTTING_DOWN, Properties::new())));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V5)).unwrap();
+    assert_eq!(client.protocol_version(), ProtocolVersion::V5);
+    match client.publish("a/b", "denied", Qos::AtLeastOnce, false) {
+        Err(ClientError::Rejected(ReasonCode::NOT_AUTHORIZED)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match client.poll() {
+        Err(ClientError::Disconnected(ReasonCode::SERVER_SHUTTING_DOWN)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn losing_the_connection() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        connection.close();
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    server.join().unwrap();
+    match client.poll() {
+        Err(ClientError::ConnectionClosed) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn receiving_repeated_qos_2_messages_once() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+
+        let publish = PublishData::builder("a/b", "once").qos(Qos::ExactlyOnce).packet_identifier(PacketIdentifier(5)).build().unwrap();
+        connection.send(Packet::Publish(publish.clone()));
+        assert_eq!(connection.receive(), Packet::Pubrec(AckData::new(PacketIdentifier(5))));
+        // as if the PUBREC got lost
+        connection.send(Packet::Publish(publish.redelivery()));
+        assert_eq!(connection.receive(), Packet::Pubrec(AckData::new(PacketIdentifier(5))));
+        connection.send(Packet::Pubrel(AckData::new(PacketIdentifier(5))));
+        assert_eq!(connection.receive(), Packet::Pubcomp(AckData::new(PacketIdentifier(5))));
+
+        let publish = PublishData::builder("a/b", "twice").qos(Qos::ExactlyOnce).packet_identifier(PacketIdentifier(5)).build().unwrap();
+        connection.send(Packet::Publish(publish));
+        assert_eq!(connection.receive(), Packet::Pubrec(AckData::new(PacketIdentifier(5))));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    assert_eq!(client.poll().unwrap().payload(), b"once");
+    assert_eq!(client.poll().unwrap().payload(), b"twice");
+    server.join().unwrap();
+}
+
+#[test]
+fn pinging_an_idle_server() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        assert_eq!(connection.accept_connect().keepalive(), 1);
+        assert_eq!(connection.receive(), Packet::Pingreq);
+        connection.send(Packet::Pingresp);
+        // the second PINGREQ goes unanswered
+        assert_eq!(connection.receive(), Packet::Pingreq);
+        assert_eq!(connection.try_receive(), None);
+    });
+
+    let connect = ConnectData::builder().client_id("client").keepalive(1).build();
+    let mut client = Client::connect(address, connect).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(100)));
+    match client.poll() {
+        Err(ClientError::IoError(ref err)) if err.kind() == io::ErrorKind::TimedOut => {},
+        other => panic!("unexpected {:?}", other),
+    }
+
+    client.set_read_timeout(None);
+    match client.poll() {
+        Err(ClientError::KeepAliveTimeout) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    drop(client);
+    server.join().unwrap();
+}
+
+#[test]
+fn reconnecting_and_resuming_the_session() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        match connection.receive() {
+            Packet::Subscribe(subscribe) => connection.send(Packet::Suback(SubackData::new(subscribe.packet_identifier(), vec![ReturnCode::Success(Qos::AtLeastOnce)]))),
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        }
+        let publish = match connection.receive() {
+            Packet::Publish(publish) => publish,
+            packet => panic!("expected PUBLISH, got {:?}", packet),
+        };
+        assert!(!publish.dup());
+        connection.close();
+
+        let mut connection = broker.accept();
+        assert!(!connection.handshake(true, ConnackReturnCode::Accepted).clean_session());
+        assert_eq!(connection.receive(), Packet::Publish(publish.clone().redelivery()));
+        connection.send(Packet::Puback(AckData::new(publish.packet_identifier().unwrap())));
+        connection.close();
+
+        // the session is gone this time
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        match connection.receive() {
+            Packet::Subscribe(subscribe) => {
+                assert_eq!(subscribe.topic_filters(), &[TopicFilter::new("a/+", Qos::AtLeastOnce)]);
+                connection.send(Packet::Suback(SubackData::new(subscribe.packet_identifier(), vec![ReturnCode::Success(Qos::AtLeastOnce)])));
+            },
+            packet => panic!("expected SUBSCRIBE, got {:?}", packet),
+        }
+        connection.send(Packet::Publish(PublishData::builder("a/b", "hello").build().unwrap()));
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    client.set_reconnect(Some(Backoff::new(Duration::from_millis(10), Duration::from_millis(100))));
+    client.subscribe(vec![TopicFilter::new("a/+", Qos::AtLeastOnce)]).unwrap();
+    client.publish("a/b", "survives", Qos::AtLeastOnce, false).unwrap();
+    assert!(client.session_present());
+
+    assert_eq!(client.poll().unwrap().payload(), b"hello");
+    assert!(!client.session_present());
+    client.disconnect().unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn giving_up_reconnecting() {
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        broker.accept().accept_connect();
+        // the broker goes away along with the connection
+    });
+
+    let mut client = Client::connect(address, connect_data(ProtocolVersion::V311)).unwrap();
+    client.set_reconnect(Some(Backoff::new(Duration::from_millis(1), Duration::from_millis(10)).with_max_attempts(3)));
+    server.join().unwrap();
+    match client.poll() {
+        Err(ClientError::IoError(ref err)) if err.kind() == io::ErrorKind::ConnectionRefused => {},
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn restoring_the_session_after_restarting() {
+    let path = env::temp_dir().join(format!("mqtt-client-session-{}", std::process::id()));
+    let broker = FakeBroker::start();
+    let address = broker.address();
+    let server = thread::spawn(move || {
+        let mut connection = broker.accept();
+        connection.accept_connect();
+        assert!(matches!(connection.receive(), Packet::Publish(_)));
+        let inbound = PublishData::builder("a/b", "inbound").qos(Qos::ExactlyOnce).packet_identifier(PacketIdentifier(7)).build().unwrap();
+        connection.send(Packet::Publish(inbound.clone()));
+        assert_eq!(connection.receive(), Packet::Pubrec(AckData::new(PacketIdentifier(7))));
+        connection.send(Packet::Pubrec(AckData::new(PacketIdentifier(1))));
+        assert_eq!(connection.receive(), Packet::Pubrel(AckData::new(PacketIdentifier(1))));
+        // the client goes away before the PUBCOMP
+        assert_eq!(connection.try_receive(), None);
+
+        let mut connection = broker.accept();
+        connection.handshake(true, ConnackReturnCode::Accepted);
+        assert_eq!(connection.receive(), Packet::Pubrel(AckData::new(PacketIdentifier(1))));
+        connection.send(Packet::Pubcomp(AckData::new(PacketIdentifier(1))));
+        connection.send(Packet::Publish(inbound.redelivery()));
+        assert_eq!(connection.receive(), Packet::Pubrec(AckData::new(PacketIdentifier(7))));
+        connection.send(Packet::Pubrel(AckData::new(PacketIdentifier(7))));
+        assert_eq!(connection.receive(), Packet::Pubcomp(AckData::new(PacketIdentifier(7))));
+        connection.send(Packet::Publish(PublishData::builder("a/b", "fresh").build().unwrap()));
+        assert_eq!(connection.receive(), Packet::Disconnect(DisconnectData::default()));
+    });
+
+    let connect = ConnectData::builder().client_id("client").clean_session(false).build();
+    let mut client = Client::connect_with_store(address, connect.clone(), FileSessionStore::open(&path).unwrap()).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(200)));
+    match client.publish("a/b", "outbound", Qos::ExactlyOnce, false) {
+        Err(ClientError::IoError(ref err)) if err.kind() == io::ErrorKind::TimedOut => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    drop(client);
+
+    let mut client = Client::connect_with_store(address, connect, FileSessionStore::open(&path).unwrap()).unwrap();
+    // the message delivered before the restart is not delivered again
+    assert_eq!(client.poll().unwrap().payload(), b"fresh");
+    client.disconnect().unwrap();
+    server.join().unwrap();
+
+    assert_eq!(FileSessionStore::open(&path).unwrap().load().unwrap(), (vec![], vec![]));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,81 @@
+//! An in-process stand-in for a broker, which tests script one packet at a time.
+
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use mqtt::codec::PacketDecoder;
+use mqtt::packet::*;
+
+pub struct FakeBroker {
+    listener: TcpListener,
+}
+
+impl FakeBroker {
+    pub fn start() -> FakeBroker {
+        FakeBroker { listener: TcpListener::bind("127.0.0.1:0").unwrap() }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    pub fn accept(&self) -> Connection {
+        let (stream, _) = self.listener.accept().unwrap();
+        Connection { stream, decoder: PacketDecoder::new() }
+    }
+}
+
+/// The broker's end of a client connection.
+pub struct Connection {
+    stream: TcpStream,
+    decoder: PacketDecoder,
+}
+
+impl Connection {
+    /// the next packet from the client, `None` once it has closed the connection
+    pub fn try_receive(&mut self) -> Option<Packet> {
+        let mut buffer = [0; 1024];
+        loop {
+            if let Some(packet) = self.decoder.next_packet().unwrap() {
+                return Some(packet);
+            }
+            match self.stream.read(&mut buffer) {
+                Ok(0) | Err(_) => return None,
+                Ok(read) => self.decoder.feed(&buffer[..read]),
+            }
+        }
+    }
+
+    pub fn receive(&mut self) -> Packet {
+        self.try_receive().expect("the client closed the connection")
+    }
+
+    pub fn send(&mut self, packet: Packet) {
+        let mut bytes = Vec::new();
+        packet.encode(&mut bytes).unwrap();
+        self.stream.write_all(&bytes).unwrap();
+    }
+
+    /// Expect a CONNECT and answer it with `return_code`.
+    pub fn handshake(&mut self, session_present: bool, return_code: ConnackReturnCode) -> ConnectData {
+        let connect = match self.receive() {
+            Packet::Connect(connect) => connect,
+            packet => panic!("expected CONNECT, got {:?}", packet),
+        };
+        let connack = ConnackData::new(session_present, return_code);
+        if self.decoder.context().protocol_version >= ProtocolVersion::V5 {
+            self.send(Packet::Connack(connack.with_properties(Properties::new())));
+        } else {
+            self.send(Packet::Connack(connack));
+        }
+        connect
+    }
+
+    pub fn accept_connect(&mut self) -> ConnectData {
+        self.handshake(false, ConnackReturnCode::Accepted)
+    }
+
+    pub fn close(self) {}
+}
@@ -0,0 +1,351 @@
+//! Byte-exact encoding of every packet type, checked against the normative
+//! statements of the MQTT 3.1.1 specification, and round trips through the decoder.
+
+extern crate mqtt;
+
+use std::io::Cursor;
+
+use mqtt::packet::*;
+
+fn encode(packet: &Packet) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    packet.encode(&mut bytes).unwrap();
+    assert_eq!(bytes.len() as u32, packet.encoded_length());
+    bytes
+}
+
+/// Encodes `packet`, compares the result with `expected`, then decodes and re-encodes it.
+fn assert_conforms(packet: Packet, expected: &[u8], protocol_version: ProtocolVersion) {
+    let bytes = encode(&packet);
+    assert_eq!(bytes, expected, "encoding {:?}", packet);
+
+    let mut state = DecodingContext::new(protocol_version);
+    let mut cursor = Cursor::new(&bytes);
+    let decoded = Packet::decode(&mut cursor, &mut state).unwrap();
+    assert_eq!(cursor.position() as usize, bytes.len(), "decoding {:?} left bytes behind", packet);
+    assert_eq!(encode(&decoded), bytes, "round trip of {:?}", packet);
+}
+
+#[test]
+fn connect_with_only_a_client_identifier() {
+    let packet = Packet::Connect(ConnectData::builder().client_id("a").build());
+    // [MQTT-3.1.2-1] protocol name, [MQTT-3.1.2-2] level 4, [MQTT-3.1.2-3] reserved flag zero
+    assert_conforms(packet, &[
+        0x10, 13,
+        0, 4, b'M', b'Q', b'T', b'T',
+        4,
+        0b0000_0010,
+        0, 60,
+        0, 1, b'a',
+    ], ProtocolVersion::V311);
+}
+
+#[test]
+fn connect_with_every_flag() {
+    let packet = Packet::Connect(ConnectData::builder()
+        .client_id("a")
+        .clean_session(false)
+        .keepalive(10)
+        .will("w", "m", Qos::AtLeastOnce, true)
+        .user_name("u")
+        .password("p")
+        .build());
+    // [MQTT-3.1.2-9] will flag, [MQTT-3.1.2-14] will QoS, [MQTT-3.1.2-17] will retain,
+    // [MQTT-3.1.2-19] user name flag, [MQTT-3.1.2-21] password flag
+    assert_conforms(packet, &[
+        0x10, 25,
+        0, 4, b'M', b'Q', b'T', b'T',
+        4,
+        0b1110_1100,
+        0, 10,
+        0, 1, b'a',
+        0, 1, b'w',
+        0, 1, b'm',
+        0, 1, b'u',
+        0, 1, b'p',
+    ], ProtocolVersion::V311);
+}
+
+#[test]
+fn connect_without_a_will_has_no_will_qos_or_retain() {
+    let mut bytes = encode(&Packet::Connect(ConnectData::builder().client_id("a").build()));
+    bytes[9] = 0b0010_1010;
+
+    // [MQTT-3.1.2-13] and [MQTT-3.1.2-15] a will QoS and retain without a will are malformed
+    let err = Packet::decode(&mut Cursor::new(&bytes), &mut DecodingContext::default()).unwrap_err();
+    assert!(matches!(err.root_cause(), DecodingError::Malformed(Field::ConnectFlags)));
+
+    // and ignored when lenient, so they are not encoded back
+    let mut state = DecodingContext { strictness: Strictness::Lenient, ..DecodingContext::default() };
+    let decoded = Packet::decode(&mut Cursor::new(&bytes), &mut state).unwrap();
+    assert_eq!(encode(&decoded)[9], 0b0000_0010);
+}
+
+#[test]
+fn connect_with_a_password_but_no_user_name() {
+    let packet = Packet::Connect(ConnectData::builder().client_id("a").build());
+    let mut bytes = encode(&packet);
+    bytes[9] |= 0b0100_0000;
+    bytes[1] += 3;
+    bytes.extend_from_slice(&[0, 1, b'p']);
+
+    // [MQTT-3.1.2-22] a password needs a user name
+    let err = Packet::decode(&mut Cursor::new(&bytes), &mut DecodingContext::default()).unwrap_err();
+    assert!(matches!(err.root_cause(), DecodingError::Malformed(Field::ConnectFlags)));
+}
+
+#[test]
+fn connect_for_mqtt_3_1() {
+    let packet = Packet::Connect(ConnectData::builder().protocol_version(ProtocolVersion::V31).client_id("a").build());
+    assert_conforms(packet, &[
+        0x10, 15,
+        0, 6, b'M', b'Q', b'I', b's', b'd', b'p',
+        3,
+        0b0000_0010,
+        0, 60,
+        0, 1, b'a',
+    ], ProtocolVersion::V31);
+}
+
+#[test]
+fn connect_for_an_unknown_protocol_level() {
+    let packet = Packet::Connect(ConnectData::builder().client_id("a").build());
+    for level in &[0, 2, 6, 255] {
+        let mut bytes = encode(&packet);
+        bytes[8] = *level;
+
+        // [MQTT-3.1.2-2] answered with an unacceptable protocol version return code
+        let err = Packet::decode(&mut Cursor::new(&bytes), &mut DecodingContext::default()).unwrap_err();
+        assert!(matches!(err.root_cause(), DecodingError::UnacceptableProtocolVersion(l) if l == level), "level {}", level);
+    }
+}
+
+#[test]
+fn connack() {
+    // [MQTT-3.2.2-1] reserved acknowledge flags are zero
+    assert_conforms(Packet::Connack(ConnackData::new(true, ConnackReturnCode::Accepted)), &[0x20, 2, 1, 0], ProtocolVersion::V311);
+    assert_conforms(Packet::Connack(ConnackData::new(false, ConnackReturnCode::NotAuthorized)), &[0x20, 2, 0, 5], ProtocolVersion::V311);
+}
+
+#[test]
+fn publish_at_most_once() {
+    let packet = Packet::Publish(PublishData::builder("a/b", "hi").build().unwrap());
+    // [MQTT-2.3.1-5] no packet identifier at QoS 0
+    assert_conforms(packet, &[0x30, 7, 0, 3, b'a', b'/', b'b', b'h', b'i'], ProtocolVersion::V311);
+}
+
+#[test]
+fn publish_at_least_once_retained_duplicate() {
+    let packet = Packet::Publish(PublishData::builder("a", vec![0xff])
+        .qos(Qos::AtLeastOnce)
+        .packet_identifier(PacketIdentifier(10))
+        .retain(true)
+        .dup(true)
+        .build().unwrap());
+    assert_conforms(packet, &[0x3b, 6, 0, 1, b'a', 0, 10, 0xff], ProtocolVersion::V311);
+}
+
+#[test]
+fn publish_exactly_once() {
+    let packet = Packet::Publish(PublishData::builder("a", vec![])
+        .qos(Qos::ExactlyOnce)
+        .packet_identifier(PacketIdentifier(0x0102))
+        .build().unwrap());
+    assert_conforms(packet, &[0x34, 5, 0, 1, b'a', 1, 2], ProtocolVersion::V311);
+}
+
+#[test]
+fn publish_at_most_once_is_never_a_duplicate() {
+    // [MQTT-3.3.1-2]
+    let err = PublishData::builder("a", vec![]).dup(true).build().unwrap_err();
+    assert_eq!(err, PublishError::DuplicateAtMostOnce);
+
+    let bytes = [0x38, 3, 0, 1, b'a'];
+    let err = Packet::decode(&mut Cursor::new(&bytes), &mut DecodingContext::default()).unwrap_err();
+    assert!(matches!(err.root_cause(), DecodingError::Malformed(Field::HeaderFlags)));
+
+    // and the flag is dropped when lenient, so it is not encoded back
+    let mut state = DecodingContext { strictness: Strictness::Lenient, ..DecodingContext::default() };
+    let decoded = Packet::decode(&mut Cursor::new(&bytes), &mut state).unwrap();
+    assert_eq!(encode(&decoded), [0x30, 3, 0, 1, b'a']);
+}
+
+#[test]
+fn publish_packet_identifiers_go_with_the_qos() {
+    // [MQTT-2.3.1-5] none at QoS 0
+    let err = PublishData::builder("a", vec![]).packet_identifier(PacketIdentifier(1)).build().unwrap_err();
+    assert_eq!(err, PublishError::UnexpectedPacketIdentifier);
+    let packet = Packet::Publish(PublishData::builder("a", vec![]).build().unwrap().with_packet_identifier(PacketIdentifier(1)));
+    assert_eq!(encode(&packet), [0x30, 3, 0, 1, b'a']);
+
+    // [MQTT-2.3.1-1] but one at QoS 1 and 2
+    for &qos in &[Qos::AtLeastOnce, Qos::ExactlyOnce] {
+        let err = PublishData::builder("a", vec![]).qos(qos).build().unwrap_err();
+        assert_eq!(err, PublishError::MissingPacketIdentifier);
+    }
+}
+
+#[test]
+fn publish_remaining_length_boundaries() {
+    // topic "a" takes 3 bytes of the remaining length, the payload the rest
+    for &(remaining_length, ref length_bytes) in &[
+        (127u32, vec![0x7f]),
+        (128, vec![0x80, 0x01]),
+        (16_383, vec![0xff, 0x7f]),
+        (16_384, vec![0x80, 0x80, 0x01]),
+    ] {
+        let payload = vec![0u8; remaining_length as usize - 3];
+        let mut expected = vec![0x30];
+        expected.extend_from_slice(length_bytes);
+        expected.extend_from_slice(&[0, 1, b'a']);
+        expected.extend_from_slice(&payload);
+        assert_conforms(Packet::Publish(PublishData::builder("a", payload).build().unwrap()), &expected, ProtocolVersion::V311);
+    }
+}
+
+#[test]
+fn publish_acknowledgements() {
+    let identifier = PacketIdentifier(7);
+    assert_conforms(Packet::Puback(AckData::new(identifier)), &[0x40, 2, 0, 7], ProtocolVersion::V311);
+    assert_conforms(Packet::Pubrec(AckData::new(identifier)), &[0x50, 2, 0, 7], ProtocolVersion::V311);
+    // [MQTT-3.6.1-1] PUBREL has the flags 0010
+    assert_conforms(Packet::Pubrel(AckData::new(identifier)), &[0x62, 2, 0, 7], ProtocolVersion::V311);
+    assert_conforms(Packet::Pubcomp(AckData::new(identifier)), &[0x70, 2, 0, 7], ProtocolVersion::V311);
+}
+
+#[test]
+fn subscribe() {
+    let packet = Packet::Subscribe(SubscribeData::new(PacketIdentifier(1), vec![
+        TopicFilter::new("a/+", Qos::AtMostOnce),
+        TopicFilter::new("#", Qos::ExactlyOnce),
+    ]));
+    // [MQTT-3.8.1-1] SUBSCRIBE has the flags 0010, [MQTT-3-8.3-4] reserved option bits are zero
+    assert_conforms(packet, &[
+        0x82, 12,
+        0, 1,
+        0, 3, b'a', b'/', b'+', 0,
+        0, 1, b'#', 2,
+    ], ProtocolVersion::V311);
+}
+
+#[test]
+fn subscribe_and_unsubscribe_need_topic_filters() {
+    // [MQTT-3.8.3-3] and [MQTT-3.10.3-2]
+    assert!(Packet::Subscribe(SubscribeData::new(PacketIdentifier(1), vec![])).encode(&mut Vec::new()).is_err());
+    assert!(Packet::Unsubscribe(UnsubscribeData::new(PacketIdentifier(1), vec![])).encode(&mut Vec::new()).is_err());
+
+    for bytes in &[[0x82, 2, 0, 1], [0xa2, 2, 0, 1]] {
+        let err = Packet::decode(&mut Cursor::new(bytes), &mut DecodingContext::default()).unwrap_err();
+        assert!(matches!(err.root_cause(), DecodingError::Malformed(Field::Payload)));
+    }
+}
+
+#[test]
+fn packet_identifiers_are_non_zero() {
+    // [MQTT-2.3.1-1]
+    let identifier = PacketIdentifier(0);
+    let publish = PublishData::builder("a", vec![]).qos(Qos::AtLeastOnce).packet_identifier(identifier).build().unwrap();
+    for packet in &[
+        Packet::Publish(publish),
+        Packet::Puback(AckData::new(identifier)),
+        Packet::Subscribe(SubscribeData::new(identifier, vec![TopicFilter::new("a", Qos::AtMostOnce)])),
+        Packet::Unsubscribe(UnsubscribeData::new(identifier, vec!["a".to_owned()])),
+    ] {
+        let mut bytes = Vec::new();
+        assert!(packet.encode(&mut bytes).is_err());
+        // nothing is written
+        assert!(bytes.is_empty());
+    }
+
+    for bytes in &[vec![0x32, 5, 0, 1, b'a', 0, 0], vec![0x40, 2, 0, 0], vec![0x82, 6, 0, 0, 0, 1, b'a', 0], vec![0xb0, 2, 0, 0]] {
+        let err = Packet::decode(&mut Cursor::new(bytes), &mut DecodingContext::default()).unwrap_err();
+        assert!(matches!(err.root_cause(), DecodingError::Malformed(Field::PacketIdentifier)));
+    }
+}
+
+#[test]
+fn suback() {
+    let packet = Packet::Suback(SubackData::new(PacketIdentifier(1), vec![
+        ReturnCode::Success(Qos::AtMostOnce),
+        ReturnCode::Success(Qos::AtLeastOnce),
+        ReturnCode::Success(Qos::ExactlyOnce),
+        ReturnCode::Failure,
+    ]));
+    assert_conforms(packet, &[0x90, 6, 0, 1, 0, 1, 2, 0x80], ProtocolVersion::V311);
+}
+
+#[test]
+fn unsubscribe() {
+    let packet = Packet::Unsubscribe(UnsubscribeData::new(PacketIdentifier(2), vec!["a/b".to_owned(), "c".to_owned()]));
+    // [MQTT-3.10.1-1] UNSUBSCRIBE has the flags 0010
+    assert_conforms(packet, &[0xa2, 10, 0, 2, 0, 3, b'a', b'/', b'b', 0, 1, b'c'], ProtocolVersion::V311);
+}
+
+#[test]
+fn unsuback() {
+    assert_conforms(Packet::Unsuback(UnsubackData::new(PacketIdentifier(2))), &[0xb0, 2, 0, 2], ProtocolVersion::V311);
+}
+
+#[test]
+fn packets_without_a_body() {
+    assert_conforms(Packet::Pingreq, &[0xc0, 0], ProtocolVersion::V311);
+    assert_conforms(Packet::Pingresp, &[0xd0, 0], ProtocolVersion::V311);
+    assert_conforms(Packet::Disconnect(DisconnectData::default()), &[0xe0, 0], ProtocolVersion::V311);
+}
+
+#[test]
+fn mqtt_5_packets() {
+    let properties = Properties(vec![Property::SessionExpiryInterval(10)]);
+
+    let packet = Packet::Connect(ConnectData::builder()
+        .protocol_version(ProtocolVersion::V5)
+        .client_id("a")
+        .properties(properties.clone())
+        .build());
+    assert_conforms(packet, &[
+        0x10, 19,
+        0, 4, b'M', b'Q', b'T', b'T',
+        5,
+        0b0000_0010,
+        0, 60,
+        5, 0x11, 0, 0, 0, 10,
+        0, 1, b'a',
+    ], ProtocolVersion::V5);
+
+    let packet = Packet::Connack(ConnackData::new(false, ConnackReturnCode::Accepted).with_properties(Properties::new()));
+    assert_conforms(packet, &[0x20, 3, 0, 0, 0], ProtocolVersion::V5);
+
+    let packet = Packet::Publish(PublishData::builder("a", "b").properties(Properties::new()).build().unwrap());
+    assert_conforms(packet, &[0x30, 5, 0, 1, b'a', 0, b'b'], ProtocolVersion::V5);
+
+    // the reason code and properties are left out when there is nothing to say
+    assert_conforms(Packet::Puback(AckData::new(PacketIdentifier(7))), &[0x40, 2, 0, 7], ProtocolVersion::V5);
+    let packet = Packet::Puback(AckData::with_reason(PacketIdentifier(7), ReasonCode::NO_MATCHING_SUBSCRIBERS, Properties::new()));
+    assert_conforms(packet, &[0x40, 3, 0, 7, 0x10], ProtocolVersion::V5);
+
+    let packet = Packet::Subscribe(SubscribeData::new(PacketIdentifier(1), vec![
+        TopicFilter::with_options("a", Qos::AtLeastOnce, true, true, RetainHandling::DoNotSend),
+    ]).with_properties(Properties::new()));
+    assert_conforms(packet, &[0x82, 7, 0, 1, 0, 0, 1, b'a', 0b0010_1101], ProtocolVersion::V5);
+
+    let packet = Packet::Suback(SubackData::new(PacketIdentifier(1), vec![ReturnCode::Success(Qos::AtLeastOnce)]).with_properties(Properties::new()));
+    assert_conforms(packet, &[0x90, 4, 0, 1, 0, 1], ProtocolVersion::V5);
+
+    let packet = Packet::Unsubscribe(UnsubscribeData::new(PacketIdentifier(1), vec!["a".to_owned()]).with_properties(Properties::new()));
+    assert_conforms(packet, &[0xa2, 6, 0, 1, 0, 0, 1, b'a'], ProtocolVersion::V5);
+
+    let packet = Packet::Unsuback(UnsubackData::with_reason_codes(PacketIdentifier(1), vec![ReasonCode::SUCCESS], Properties::new()));
+    assert_conforms(packet, &[0xb0, 4, 0, 1, 0, 0], ProtocolVersion::V5);
+
+    let packet = Packet::Disconnect(DisconnectData::new(ReasonCode::NORMAL_DISCONNECTION, properties));
+    assert_conforms(packet, &[0xe0, 7, 0, 5, 0x11, 0, 0, 0, 10], ProtocolVersion::V5);
+
+    assert_conforms(Packet::Auth(AuthData::new(ReasonCode::SUCCESS, Properties::new())), &[0xf0, 0], ProtocolVersion::V5);
+}
+
+#[test]
+fn strings_longer_than_the_length_prefix_allows() {
+    let topic_name = "a".repeat(65_536);
+    let packet = Packet::Publish(PublishData::builder(topic_name, vec![]).build().unwrap());
+    assert!(packet.encode(&mut Vec::new()).is_err());
+}
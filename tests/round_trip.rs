@@ -0,0 +1,329 @@
+//! Property tests: any packet that can be built decodes back to itself.
+
+extern crate mqtt;
+extern crate proptest;
+
+use std::io::Cursor;
+
+use mqtt::codec::PacketDecoder;
+use mqtt::packet::*;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+fn string() -> BoxedStrategy<String> {
+    "\\PC{0,12}".boxed()
+}
+
+fn topic_level() -> BoxedStrategy<String> {
+    "[^+#/\\x00]{0,4}".boxed()
+}
+
+fn topic_name() -> BoxedStrategy<String> {
+    vec(topic_level(), 1..4)
+        .prop_map(|levels| levels.join("/"))
+        .prop_filter("topic names are not empty", |name| !name.is_empty())
+        .boxed()
+}
+
+fn topic_filter_string() -> BoxedStrategy<String> {
+    let level = prop_oneof![3 => topic_level(), 1 => Just("+".to_owned())];
+    (vec(level, 1..4), any::<bool>())
+        .prop_map(|(mut levels, multi_level)| {
+            if multi_level {
+                levels.push("#".to_owned());
+            }
+            levels.join("/")
+        })
+        .prop_filter("topic filters are not empty", |filter| !filter.is_empty())
+        .boxed()
+}
+
+fn binary() -> BoxedStrategy<Vec<u8>> {
+    vec(any::<u8>(), 0..16).boxed()
+}
+
+fn protocol_version() -> BoxedStrategy<ProtocolVersion> {
+    prop_oneof![Just(ProtocolVersion::V31), Just(ProtocolVersion::V311), Just(ProtocolVersion::V5)].boxed()
+}
+
+fn qos() -> BoxedStrategy<Qos> {
+    prop_oneof![Just(Qos::AtMostOnce), Just(Qos::AtLeastOnce), Just(Qos::ExactlyOnce)].boxed()
+}
+
+fn packet_identifier() -> BoxedStrategy<PacketIdentifier> {
+    // zero is not a valid packet identifier
+    (1..=u16::MAX).prop_map(PacketIdentifier).boxed()
+}
+
+fn reason_code() -> BoxedStrategy<ReasonCode> {
+    any::<u8>().prop_map(ReasonCode).boxed()
+}
+
+fn property() -> BoxedStrategy<Property> {
+    prop_oneof![
+        any::<u8>().prop_map(Property::PayloadFormatIndicator),
+        any::<u32>().prop_map(Property::MessageExpiryInterval),
+        string().prop_map(Property::ContentType),
+        string().prop_map(Property::ResponseTopic),
+        binary().prop_map(Property::CorrelationData),
+        (0..=268_435_455u32).prop_map(Property::SubscriptionIdentifier),
+        any::<u32>().prop_map(Property::SessionExpiryInterval),
+        string().prop_map(Property::AssignedClientIdentifier),
+        any::<u16>().prop_map(Property::ServerKeepAlive),
+        string().prop_map(Property::AuthenticationMethod),
+        binary().prop_map(Property::AuthenticationData),
+        any::<u8>().prop_map(Property::RequestProblemInformation),
+        any::<u32>().prop_map(Property::WillDelayInterval),
+        any::<u8>().prop_map(Property::RequestResponseInformation),
+        string().prop_map(Property::ResponseInformation),
+        string().prop_map(Property::ServerReference),
+        string().prop_map(Property::ReasonString),
+        any::<u16>().prop_map(Property::ReceiveMaximum),
+        any::<u16>().prop_map(Property::TopicAliasMaximum),
+        (1..=u16::MAX).prop_map(Property::TopicAlias),
+        any::<u8>().prop_map(Property::MaximumQos),
+        any::<u8>().prop_map(Property::RetainAvailable),
+        (string(), string()).prop_map(|(key, value)| Property::UserProperty(key, value)),
+        any::<u32>().prop_map(Property::MaximumPacketSize),
+        any::<u8>().prop_map(Property::WildcardSubscriptionAvailable),
+        any::<u8>().prop_map(Property::SubscriptionIdentifierAvailable),
+        any::<u8>().prop_map(Property::SharedSubscriptionAvailable),
+    ].boxed()
+}
+
+fn properties() -> BoxedStrategy<Properties> {
+    vec(property(), 0..4).prop_map(Properties).boxed()
+}
+
+/// properties for MQTT 5, none otherwise
+fn properties_for(protocol_version: ProtocolVersion) -> BoxedStrategy<Option<Properties>> {
+    if protocol_version >= ProtocolVersion::V5 {
+        properties().prop_map(Some).boxed()
+    } else {
+        Just(None).boxed()
+    }
+}
+
+fn connect_data(protocol_version: ProtocolVersion) -> BoxedStrategy<ConnectData> {
+    let will = (topic_name(), string(), qos(), any::<bool>(), properties_for(protocol_version));
+    (string(), any::<bool>(), any::<u16>(), option::of(will), option::of(string()), option::of(string()), properties_for(protocol_version))
+        .prop_map(move |(client_identifier, clean_session, keepalive, will, user_name, password, properties)| {
+            let mut builder = ConnectData::builder()
+                .protocol_version(protocol_version)
+                .client_id(client_identifier)
+                .clean_session(clean_session)
+                .keepalive(keepalive)
+                .properties(properties.unwrap_or_default());
+            if let Some((topic, message, qos, retain, will_properties)) = will {
+                builder = builder.will(topic, message, qos, retain).will_properties(will_properties.unwrap_or_default());
+            }
+            // [MQTT-3.1.2-22] before MQTT 5 a password needs a user name
+            let password = password.filter(|_| user_name.is_some() || protocol_version >= ProtocolVersion::V5);
+            if let Some(user_name) = user_name {
+                builder = builder.user_name(user_name);
+            }
+            if let Some(password) = password {
+                builder = builder.password(password);
+            }
+            builder.build()
+        }).boxed()
+}
+
+fn connack_data(protocol_version: ProtocolVersion) -> BoxedStrategy<ConnackData> {
+    if protocol_version >= ProtocolVersion::V5 {
+        (any::<bool>(), reason_code(), properties())
+            .prop_map(|(session_present, reason_code, properties)| {
+                ConnackData::new(session_present, ConnackReturnCode::from_reason_code(reason_code)).with_properties(properties)
+            }).boxed()
+    } else {
+        let return_code = prop_oneof![
+            Just(ConnackReturnCode::Accepted),
+            Just(ConnackReturnCode::UnacceptableProtocolVersion),
+            Just(ConnackReturnCode::IdentifierRejected),
+            Just(ConnackReturnCode::ServerUnavailable),
+            Just(ConnackReturnCode::BadUsernameOrPassword),
+            Just(ConnackReturnCode::NotAuthorized),
+        ];
+        (any::<bool>(), return_code)
+            .prop_map(|(session_present, return_code)| ConnackData::new(session_present, return_code))
+            .boxed()
+    }
+}
+
+fn publish_data(protocol_version: ProtocolVersion) -> BoxedStrategy<PublishData> {
+    (topic_name(), vec(any::<u8>(), 0..64), qos(), any::<bool>(), any::<bool>(), packet_identifier(), properties_for(protocol_version))
+        .prop_map(|(topic_name, payload, qos, retain, dup, packet_identifier, properties)| {
+            let mut builder = PublishData::builder(topic_name, payload).qos(qos).retain(retain);
+            // only QoS 1 and 2 messages have an identifier and can be redelivered
+            if qos != Qos::AtMostOnce {
+                builder = builder.packet_identifier(packet_identifier).dup(dup);
+            }
+            if let Some(properties) = properties {
+                builder = builder.properties(properties);
+            }
+            builder.build().unwrap()
+        }).boxed()
+}
+
+fn ack_data(protocol_version: ProtocolVersion) -> BoxedStrategy<AckData> {
+    if protocol_version >= ProtocolVersion::V5 {
+        (packet_identifier(), reason_code(), properties())
+            .prop_map(|(packet_identifier, reason_code, properties)| AckData::with_reason(packet_identifier, reason_code, properties))
+            .boxed()
+    } else {
+        packet_identifier().prop_map(AckData::new).boxed()
+    }
+}
+
+fn topic_filter(protocol_version: ProtocolVersion) -> BoxedStrategy<TopicFilter> {
+    if protocol_version >= ProtocolVersion::V5 {
+        let retain_handling = prop_oneof![
+            Just(RetainHandling::SendAtSubscribe),
+            Just(RetainHandling::SendAtNewSubscribe),
+            Just(RetainHandling::DoNotSend),
+        ];
+        (topic_filter_string(), qos(), any::<bool>(), any::<bool>(), retain_handling)
+            .prop_map(|(filter, qos, no_local, retain_as_published, retain_handling)| {
+                TopicFilter::with_options(filter, qos, no_local, retain_as_published, retain_handling)
+            }).boxed()
+    } else {
+        (topic_filter_string(), qos()).prop_map(|(filter, qos)| TopicFilter::new(filter, qos)).boxed()
+    }
+}
+
+fn subscribe_data(protocol_version: ProtocolVersion) -> BoxedStrategy<SubscribeData> {
+    (packet_identifier(), vec(topic_filter(protocol_version), 1..4), properties_for(protocol_version))
+        .prop_map(|(packet_identifier, topic_filters, properties)| {
+            let subscribe_data = SubscribeData::new(packet_identifier, topic_filters);
+            match properties {
+                Some(properties) => subscribe_data.with_properties(properties),
+                None => subscribe_data,
+            }
+        }).boxed()
+}
+
+fn suback_data(protocol_version: ProtocolVersion) -> BoxedStrategy<SubackData> {
+    let return_code = if protocol_version >= ProtocolVersion::V5 {
+        prop_oneof![
+            qos().prop_map(ReturnCode::Success),
+            Just(ReturnCode::Failure),
+            (0x81..=0xffu8).prop_map(|code| ReturnCode::Refused(ReasonCode(code))),
+        ].boxed()
+    } else {
+        prop_oneof![qos().prop_map(ReturnCode::Success), Just(ReturnCode::Failure)].boxed()
+    };
+    (packet_identifier(), vec(return_code, 1..4), properties_for(protocol_version))
+        .prop_map(|(packet_identifier, return_codes, properties)| {
+            let suback_data = SubackData::new(packet_identifier, return_codes);
+            match properties {
+                Some(properties) => suback_data.with_properties(properties),
+                None => suback_data,
+            }
+        }).boxed()
+}
+
+fn unsubscribe_data(protocol_version: ProtocolVersion) -> BoxedStrategy<UnsubscribeData> {
+    (packet_identifier(), vec(topic_filter_string(), 1..4), properties_for(protocol_version))
+        .prop_map(|(packet_identifier, topic_filters, properties)| {
+            let unsubscribe_data = UnsubscribeData::new(packet_identifier, topic_filters);
+            match properties {
+                Some(properties) => unsubscribe_data.with_properties(properties),
+                None => unsubscribe_data,
+            }
+        }).boxed()
+}
+
+fn unsuback_data(protocol_version: ProtocolVersion) -> BoxedStrategy<UnsubackData> {
+    if protocol_version >= ProtocolVersion::V5 {
+        (packet_identifier(), vec(reason_code(), 0..4), properties())
+            .prop_map(|(packet_identifier, reason_codes, properties)| UnsubackData::with_reason_codes(packet_identifier, reason_codes, properties))
+            .boxed()
+    } else {
+        packet_identifier().prop_map(UnsubackData::new).boxed()
+    }
+}
+
+fn disconnect_data(protocol_version: ProtocolVersion) -> BoxedStrategy<DisconnectData> {
+    if protocol_version >= ProtocolVersion::V5 {
+        (reason_code(), properties())
+            .prop_map(|(reason_code, properties)| DisconnectData::new(reason_code, properties))
+            .boxed()
+    } else {
+        Just(DisconnectData::default()).boxed()
+    }
+}
+
+fn packet(protocol_version: ProtocolVersion) -> BoxedStrategy<Packet> {
+    let packet = prop_oneof![
+        connect_data(protocol_version).prop_map(Packet::Connect),
+        connack_data(protocol_version).prop_map(Packet::Connack),
+        publish_data(protocol_version).prop_map(Packet::Publish),
+        ack_data(protocol_version).prop_map(Packet::Puback),
+        ack_data(protocol_version).prop_map(Packet::Pubrec),
+        ack_data(protocol_version).prop_map(Packet::Pubrel),
+        ack_data(protocol_version).prop_map(Packet::Pubcomp),
+        subscribe_data(protocol_version).prop_map(Packet::Subscribe),
+        suback_data(protocol_version).prop_map(Packet::Suback),
+        unsubscribe_data(protocol_version).prop_map(Packet::Unsubscribe),
+        unsuback_data(protocol_version).prop_map(Packet::Unsuback),
+        Just(Packet::Pingreq),
+        Just(Packet::Pingresp),
+        disconnect_data(protocol_version).prop_map(Packet::Disconnect),
+    ];
+    if protocol_version >= ProtocolVersion::V5 {
+        let auth_data = (reason_code(), properties()).prop_map(|(reason_code, properties)| AuthData::new(reason_code, properties));
+        prop_oneof![14 => packet, 1 => auth_data.prop_map(Packet::Auth)].boxed()
+    } else {
+        packet.boxed()
+    }
+}
+
+fn versioned_packet() -> BoxedStrategy<(ProtocolVersion, Packet)> {
+    protocol_version().prop_flat_map(|protocol_version| (Just(protocol_version), packet(protocol_version))).boxed()
+}
+
+fn context(protocol_version: ProtocolVersion) -> DecodingContext {
+    DecodingContext { topic_alias_maximum: u16::MAX, ..DecodingContext::new(protocol_version) }
+}
+
+fn encode(packet: &Packet) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    packet.encode(&mut bytes).unwrap();
+    bytes
+}
+
+proptest! {
+    #[test]
+    fn decoding_reverses_encoding((protocol_version, packet) in versioned_packet()) {
+        let bytes = encode(&packet);
+        prop_assert_eq!(bytes.len() as u32, packet.encoded_length());
+
+        let mut cursor = Cursor::new(&bytes);
+        let decoded = Packet::decode(&mut cursor, &mut context(protocol_version)).unwrap();
+        prop_assert_eq!(cursor.position() as usize, bytes.len());
+        prop_assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn borrowed_decoding_reverses_encoding((protocol_version, packet) in versioned_packet()) {
+        let bytes = encode(&packet);
+        let decoded = PacketRef::decode(&bytes, &mut context(protocol_version)).unwrap();
+        prop_assert_eq!(decoded.into_owned(), packet);
+    }
+
+    #[test]
+    fn decoding_a_stream_in_chunks((protocol_version, packets) in protocol_version().prop_flat_map(|protocol_version| (Just(protocol_version), vec(packet(protocol_version), 1..8))), chunk_size in 1..32usize) {
+        let bytes: Vec<u8> = packets.iter().flat_map(encode).collect();
+        let mut decoder = PacketDecoder::with_context(context(protocol_version));
+        let mut decoded = Vec::new();
+        for chunk in bytes.chunks(chunk_size) {
+            decoder.feed(chunk);
+            while let Some(packet) = decoder.next_packet().unwrap() {
+                decoded.push(packet);
+            }
+        }
+        prop_assert_eq!(decoded, packets);
+        prop_assert_eq!(decoder.buffered(), 0);
+    }
+}
@@ -0,0 +1,90 @@
+//! Whatever a peer sends, decoding returns an error instead of panicking,
+//! looping forever or allocating according to lengths it was merely told.
+//! The fuzz targets in `fuzz/` explore the same ground more thoroughly.
+
+extern crate mqtt;
+extern crate proptest;
+
+use std::io::Cursor;
+
+use mqtt::codec::PacketDecoder;
+use mqtt::packet::*;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+fn context() -> BoxedStrategy<DecodingContext> {
+    let protocol_version = prop_oneof![Just(ProtocolVersion::V31), Just(ProtocolVersion::V311), Just(ProtocolVersion::V5)];
+    let strictness = prop_oneof![Just(Strictness::Strict), Just(Strictness::Lenient)];
+    (protocol_version, strictness, any::<u16>())
+        .prop_map(|(protocol_version, strictness, topic_alias_maximum)| {
+            DecodingContext { strictness, topic_alias_maximum, ..DecodingContext::new(protocol_version) }
+        }).boxed()
+}
+
+/// A fixed header with a correct remaining length followed by a random body,
+/// which gets past the header and into the body decoders.
+fn frame() -> BoxedStrategy<Vec<u8>> {
+    (any::<u8>(), vec(any::<u8>(), 0..200))
+        .prop_map(|(first_byte, body)| {
+            let mut frame = vec![first_byte];
+            let mut remaining_length = body.len();
+            loop {
+                let mut byte = (remaining_length % 128) as u8;
+                remaining_length /= 128;
+                if remaining_length > 0 { byte |= 128 }
+                frame.push(byte);
+                if remaining_length == 0 { break }
+            }
+            frame.extend_from_slice(&body);
+            frame
+        }).boxed()
+}
+
+fn input() -> BoxedStrategy<Vec<u8>> {
+    prop_oneof![vec(any::<u8>(), 0..200), frame()].boxed()
+}
+
+proptest! {
+    #[test]
+    fn decoding_never_panics(mut state in context(), data in input()) {
+        let _ = Packet::decode(&mut Cursor::new(&data), &mut state);
+    }
+
+    #[test]
+    fn borrowed_decoding_never_panics(mut state in context(), data in input()) {
+        let _ = PacketRef::decode(&data, &mut state);
+    }
+
+    #[test]
+    fn decoded_packets_encode_again(mut state in context(), data in frame()) {
+        if let Ok(packet) = Packet::decode(&mut Cursor::new(&data), &mut state) {
+            let mut bytes = Vec::new();
+            packet.encode(&mut bytes).unwrap();
+            prop_assert_eq!(bytes.len() as u32, packet.encoded_length());
+        }
+    }
+
+    #[test]
+    fn streaming_never_panics(state in context(), data in vec(input(), 1..4), chunk_size in 1..16usize) {
+        let mut decoder = PacketDecoder::with_context(state);
+        for chunk in data.concat().chunks(chunk_size) {
+            decoder.feed(chunk);
+            // errors discard what they fail on, so this runs out of data
+            while !matches!(decoder.next_packet(), Ok(None)) {}
+        }
+    }
+}
+
+/// Every packet type claiming the largest remaining length there is while only a few
+/// bytes follow, which must not be taken as a hint of how much to allocate.
+#[test]
+fn decoding_truncated_packets_with_the_largest_remaining_length() {
+    for first_byte in 0..=255u8 {
+        for &protocol_version in &[ProtocolVersion::V311, ProtocolVersion::V5] {
+            let data = vec![first_byte, 0xff, 0xff, 0xff, 0x7f, 0, 1, 0, 1, b'a', 0];
+            let mut state = DecodingContext::new(protocol_version);
+            let _ = Packet::decode(&mut Cursor::new(&data), &mut state);
+            assert!(PacketRef::decode(&data, &mut state).is_err(), "{:#04x} decoded", first_byte);
+        }
+    }
+}